@@ -0,0 +1,548 @@
+//! Capture and replay of HCI traffic in the btsnoop format.
+//!
+//! The btsnoop format (RFC 1761 with the HCI UART datalink) opens directly in Wireshark. This
+//! module provides:
+//!
+//! - [`Recorder`], which wraps any [`Controller`] and writes every command sent to it and every
+//!   packet read from it to a [`ByteSink`]. The sink is the only thing the recorder needs, so it
+//!   can run on the target and stream the capture over RTT, a UART or into a RAM buffer.
+//! - [`BtSnoopReader`], which iterates over the records of a capture.
+//! - [`Replay`], which plays a capture back as a [`Controller`] so that a field capture can
+//!   reproduce a bug in a unit test.
+//!
+//! Packets are recorded with their UART packet type byte (HCI UART datalink, type 1002).
+
+use crate::{Controller, Opcode};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use core::cell::{Cell, RefCell};
+
+/// Identification pattern at the start of every btsnoop file.
+pub const MAGIC: [u8; 8] = *b"btsnoop\0";
+
+/// Version of the btsnoop format written and read by this module.
+pub const VERSION: u32 = 1;
+
+/// Datalink type for HCI packets with the UART packet type byte.
+pub const DATALINK_HCI_UART: u32 = 1002;
+
+/// Length of the file header: the magic pattern, the version and the datalink type.
+pub const FILE_HEADER_LENGTH: usize = 16;
+
+/// Length of the header of each packet record.
+pub const RECORD_HEADER_LENGTH: usize = 24;
+
+/// Microseconds between midnight, January 1st, 0 AD (the btsnoop epoch) and the Unix epoch.
+pub const UNIX_EPOCH_OFFSET_MICROS: u64 = 0x00dc_ddb3_0f2f_8000;
+
+const PACKET_TYPE_HCI_COMMAND: u8 = 0x01;
+const PACKET_TYPE_ACL_DATA: u8 = 0x02;
+const PACKET_TYPE_HCI_EVENT: u8 = 0x04;
+
+const FLAG_RECEIVED: u32 = 0x01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0x02;
+
+const MAX_COMMAND_PACKET_LENGTH: usize = 4 + 255;
+
+/// Destination for the bytes of a capture.
+///
+/// Sinks are infallible from the point of view of the recorder; a sink that can run out of space
+/// or fail to transmit is expected to keep track of that itself (see [`BufferSink`]).
+pub trait ByteSink {
+    /// Appends the bytes to the capture.
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Only available with the `alloc` feature.
+#[cfg(feature = "alloc")]
+impl ByteSink for alloc::vec::Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// [`ByteSink`] that writes into a fixed buffer, for targets without an allocator.
+///
+/// Once the buffer is full, further writes are dropped and [`overflowed`](BufferSink::overflowed)
+/// returns true.
+pub struct BufferSink<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    overflowed: bool,
+}
+
+impl<'a> BufferSink<'a> {
+    /// Creates an empty sink over the given buffer.
+    pub fn new(buffer: &'a mut [u8]) -> BufferSink<'a> {
+        BufferSink {
+            buffer,
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Returns the bytes written so far.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// Returns true if a write did not fit in the buffer and was dropped.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
+
+impl<'a> ByteSink for BufferSink<'a> {
+    fn write(&mut self, bytes: &[u8]) {
+        let end = self.len + bytes.len();
+        if end > self.buffer.len() {
+            self.overflowed = true;
+            return;
+        }
+
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+}
+
+/// Source of the timestamps of recorded packets.
+pub trait Clock {
+    /// Returns the current time, in microseconds since the Unix epoch.
+    fn now_micros(&self) -> u64;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> u64,
+{
+    fn now_micros(&self) -> u64 {
+        self()
+    }
+}
+
+/// [`Clock`] that always returns the Unix epoch, for targets without a time source.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoClock;
+
+impl Clock for NoClock {
+    fn now_micros(&self) -> u64 {
+        0
+    }
+}
+
+/// Direction and kind of a recorded packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Host to controller.
+    Sent,
+    /// Controller to host.
+    Received,
+}
+
+/// Writes the btsnoop file header to the sink.
+pub fn write_file_header<S: ByteSink>(sink: &mut S) {
+    let mut header = [0; FILE_HEADER_LENGTH];
+    header[..8].copy_from_slice(&MAGIC);
+    BigEndian::write_u32(&mut header[8..12], VERSION);
+    BigEndian::write_u32(&mut header[12..16], DATALINK_HCI_UART);
+    sink.write(&header);
+}
+
+/// Writes one packet record to the sink.
+///
+/// `packet` must start with the UART packet type byte. `timestamp_micros` is relative to the Unix
+/// epoch.
+pub fn write_record<S: ByteSink>(
+    sink: &mut S,
+    direction: Direction,
+    timestamp_micros: u64,
+    packet: &[u8],
+) {
+    let mut flags = match direction {
+        Direction::Sent => 0,
+        Direction::Received => FLAG_RECEIVED,
+    };
+    if matches!(
+        packet.first(),
+        Some(&PACKET_TYPE_HCI_COMMAND) | Some(&PACKET_TYPE_HCI_EVENT)
+    ) {
+        flags |= FLAG_COMMAND_OR_EVENT;
+    }
+
+    let mut header = [0; RECORD_HEADER_LENGTH];
+    BigEndian::write_u32(&mut header[0..4], packet.len() as u32);
+    BigEndian::write_u32(&mut header[4..8], packet.len() as u32);
+    BigEndian::write_u32(&mut header[8..12], flags);
+    BigEndian::write_u32(&mut header[12..16], 0);
+    BigEndian::write_u64(
+        &mut header[16..24],
+        timestamp_micros.wrapping_add(UNIX_EPOCH_OFFSET_MICROS),
+    );
+    sink.write(&header);
+    sink.write(packet);
+}
+
+/// [`Controller`] that records all traffic to and from another controller in the btsnoop format.
+///
+/// Each call to [`controller_read_into`](Controller::controller_read_into) is assumed to return
+/// one whole packet, as [`UartHci::read`](crate::host::uart::UartHci::read) requests. The length
+/// of the recorded packet is taken from its header; a packet that does not fit in the read buffer
+/// is recorded as far as it was read.
+pub struct Recorder<C, S, K = NoClock> {
+    controller: C,
+    sink: RefCell<S>,
+    clock: K,
+}
+
+impl<C, S> Recorder<C, S, NoClock>
+where
+    C: Controller,
+    S: ByteSink,
+{
+    /// Wraps the controller and writes the file header to the sink. All records have a timestamp
+    /// of zero.
+    pub fn new(controller: C, sink: S) -> Recorder<C, S, NoClock> {
+        Recorder::with_clock(controller, sink, NoClock)
+    }
+}
+
+impl<C, S, K> Recorder<C, S, K>
+where
+    C: Controller,
+    S: ByteSink,
+    K: Clock,
+{
+    /// Wraps the controller and writes the file header to the sink. Records are timestamped with
+    /// the given clock.
+    pub fn with_clock(controller: C, mut sink: S, clock: K) -> Recorder<C, S, K> {
+        write_file_header(&mut sink);
+        Recorder {
+            controller,
+            sink: RefCell::new(sink),
+            clock,
+        }
+    }
+
+    /// Returns the wrapped controller.
+    pub fn controller(&self) -> &C {
+        &self.controller
+    }
+
+    /// Returns the wrapped controller.
+    pub fn controller_mut(&mut self) -> &mut C {
+        &mut self.controller
+    }
+
+    /// Returns the sink the capture is written to.
+    pub fn sink(&self) -> core::cell::Ref<'_, S> {
+        self.sink.borrow()
+    }
+
+    /// Consumes the recorder and returns the controller and the sink.
+    pub fn into_inner(self) -> (C, S) {
+        (self.controller, self.sink.into_inner())
+    }
+}
+
+impl<C, S, K> Controller for Recorder<C, S, K>
+where
+    C: Controller,
+    S: ByteSink,
+    K: Clock,
+{
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        let len = core::cmp::min(payload.len(), MAX_COMMAND_PACKET_LENGTH - 4);
+        let mut packet = [0; MAX_COMMAND_PACKET_LENGTH];
+        packet[0] = PACKET_TYPE_HCI_COMMAND;
+        LittleEndian::write_u16(&mut packet[1..3], opcode.0);
+        packet[3] = len as u8;
+        packet[4..4 + len].copy_from_slice(&payload[..len]);
+        write_record(
+            self.sink.get_mut(),
+            Direction::Sent,
+            self.clock.now_micros(),
+            &packet[..4 + len],
+        );
+
+        self.controller.controller_write(opcode, payload).await;
+    }
+
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        self.controller.controller_read_into(buf).await;
+
+        let len = core::cmp::min(packet_length(buf), buf.len());
+        write_record(
+            &mut *self.sink.borrow_mut(),
+            Direction::Received,
+            self.clock.now_micros(),
+            &buf[..len],
+        );
+    }
+}
+
+// Returns the length of the UART packet at the start of the buffer, including the packet type
+// byte, as given by its header. Unknown packet types span the whole buffer.
+fn packet_length(buf: &[u8]) -> usize {
+    match buf.first() {
+        Some(&PACKET_TYPE_HCI_EVENT) if buf.len() >= 3 => 3 + buf[2] as usize,
+        Some(&PACKET_TYPE_HCI_COMMAND) if buf.len() >= 4 => 4 + buf[3] as usize,
+        Some(&PACKET_TYPE_ACL_DATA) if buf.len() >= 5 => {
+            5 + LittleEndian::read_u16(&buf[3..5]) as usize
+        }
+        _ => buf.len(),
+    }
+}
+
+/// Potential errors from reading a btsnoop capture.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The file does not start with the btsnoop identification pattern.
+    BadMagic,
+    /// The file uses a version of the format other than [`VERSION`]. Contains the version.
+    UnsupportedVersion(u32),
+    /// The file uses a datalink other than [`DATALINK_HCI_UART`]. Contains the datalink type.
+    UnsupportedDatalink(u32),
+    /// The file ends in the middle of a header or packet. Contains the offset of the incomplete
+    /// record.
+    Truncated(usize),
+}
+
+/// One packet of a btsnoop capture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record<'a> {
+    /// Whether the packet was sent to or received from the controller.
+    pub direction: Direction,
+
+    /// Time the packet was captured, in microseconds since the Unix epoch.
+    pub timestamp_micros: u64,
+
+    /// The packet, starting with its UART packet type byte. If the capture truncated the packet,
+    /// this contains only the captured bytes.
+    pub packet: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Returns the opcode and parameters if the record is an HCI command.
+    pub fn command(&self) -> Option<(Opcode, &'a [u8])> {
+        if self.packet.len() < 4 || self.packet[0] != PACKET_TYPE_HCI_COMMAND {
+            return None;
+        }
+
+        Some((
+            Opcode(LittleEndian::read_u16(&self.packet[1..3])),
+            &self.packet[4..],
+        ))
+    }
+}
+
+/// Iterator over the records of a btsnoop capture held in memory.
+#[derive(Clone, Debug)]
+pub struct BtSnoopReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BtSnoopReader<'a> {
+    /// Checks the file header and returns a reader positioned at the first record.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Truncated`] if the data is shorter than the file header.
+    /// - [`Error::BadMagic`] if the data does not start with [`MAGIC`].
+    /// - [`Error::UnsupportedVersion`] or [`Error::UnsupportedDatalink`] if the header describes a
+    ///   capture this module cannot read.
+    pub fn new(data: &'a [u8]) -> Result<BtSnoopReader<'a>, Error> {
+        if data.len() < FILE_HEADER_LENGTH {
+            return Err(Error::Truncated(0));
+        }
+        if data[..8] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        let version = BigEndian::read_u32(&data[8..12]);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let datalink = BigEndian::read_u32(&data[12..16]);
+        if datalink != DATALINK_HCI_UART {
+            return Err(Error::UnsupportedDatalink(datalink));
+        }
+
+        Ok(BtSnoopReader {
+            data,
+            offset: FILE_HEADER_LENGTH,
+        })
+    }
+
+    /// Reads the record at `offset`, returning it and the offset of the next record.
+    fn record_at(&self, offset: usize) -> Option<Result<(Record<'a>, usize), Error>> {
+        if offset == self.data.len() {
+            return None;
+        }
+
+        let header_end = offset + RECORD_HEADER_LENGTH;
+        if header_end > self.data.len() {
+            return Some(Err(Error::Truncated(offset)));
+        }
+
+        let header = &self.data[offset..header_end];
+        let included_len = BigEndian::read_u32(&header[4..8]) as usize;
+        let flags = BigEndian::read_u32(&header[8..12]);
+        let timestamp = BigEndian::read_u64(&header[16..24]);
+
+        let end = header_end + included_len;
+        if end > self.data.len() {
+            return Some(Err(Error::Truncated(offset)));
+        }
+
+        let direction = if flags & FLAG_RECEIVED == 0 {
+            Direction::Sent
+        } else {
+            Direction::Received
+        };
+        Some(Ok((
+            Record {
+                direction,
+                timestamp_micros: timestamp.wrapping_sub(UNIX_EPOCH_OFFSET_MICROS),
+                packet: &self.data[header_end..end],
+            },
+            end,
+        )))
+    }
+}
+
+impl<'a> Iterator for BtSnoopReader<'a> {
+    type Item = Result<Record<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.record_at(self.offset)? {
+            Ok((record, next)) => {
+                self.offset = next;
+                Some(Ok(record))
+            }
+            Err(e) => {
+                self.offset = self.data.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The first command written to a [`Replay`] that differs from the capture.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Divergence {
+    /// Index of the command, counting from zero.
+    pub index: usize,
+
+    /// Opcode of the command in the capture, or `None` if the capture has no more commands.
+    pub expected: Option<Opcode>,
+
+    /// Opcode of the command that was written.
+    pub actual: Opcode,
+}
+
+/// [`Controller`] that plays back a btsnoop capture.
+///
+/// Reads return the received packets of the capture in order, regardless of the commands written
+/// in between. Once the capture is exhausted (or if a record cannot be read), reads fill the
+/// buffer with zeros, which [`UartHci::read`](crate::host::uart::UartHci::read) reports as a bad
+/// packet type.
+///
+/// Written commands are compared against the sent packets of the capture. The first command that
+/// differs is available from [`divergence`](Replay::divergence), which tells a test where the
+/// code under test stopped behaving as it did in the field.
+#[derive(Clone, Debug)]
+pub struct Replay<'a> {
+    reader: BtSnoopReader<'a>,
+    read_offset: Cell<usize>,
+    write_offset: usize,
+    written: usize,
+    divergence: Option<Divergence>,
+}
+
+impl<'a> Replay<'a> {
+    /// Creates a controller that plays back the capture.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`BtSnoopReader::new`].
+    pub fn new(data: &'a [u8]) -> Result<Replay<'a>, Error> {
+        Ok(Replay {
+            reader: BtSnoopReader::new(data)?,
+            read_offset: Cell::new(FILE_HEADER_LENGTH),
+            write_offset: FILE_HEADER_LENGTH,
+            written: 0,
+            divergence: None,
+        })
+    }
+
+    /// Returns the first written command that differs from the capture, if any.
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.divergence
+    }
+
+    /// Returns the number of commands written so far.
+    pub fn commands_written(&self) -> usize {
+        self.written
+    }
+
+    /// Returns true if all received packets of the capture have been read.
+    pub fn is_done(&self) -> bool {
+        self.next_record(self.read_offset.get(), Direction::Received)
+            .is_none()
+    }
+
+    // Returns the next record in the given direction at or after `offset`, with the offset of the
+    // record after it.
+    fn next_record(&self, mut offset: usize, direction: Direction) -> Option<(Record<'a>, usize)> {
+        loop {
+            let (record, next) = self.reader.record_at(offset)?.ok()?;
+            if record.direction == direction {
+                return Some((record, next));
+            }
+            offset = next;
+        }
+    }
+}
+
+impl<'a> Controller for Replay<'a> {
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        let index = self.written;
+        self.written += 1;
+
+        let expected = match self.next_record(self.write_offset, Direction::Sent) {
+            Some((record, next)) => {
+                self.write_offset = next;
+                record.command()
+            }
+            None => None,
+        };
+
+        let matches = expected.is_some_and(|(expected_opcode, expected_params)| {
+            expected_opcode == opcode && expected_params == payload
+        });
+        if !matches && self.divergence.is_none() {
+            self.divergence = Some(Divergence {
+                index,
+                expected: expected.map(|(opcode, _)| opcode),
+                actual: opcode,
+            });
+        }
+    }
+
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        match self.next_record(self.read_offset.get(), Direction::Received) {
+            Some((record, next)) => {
+                self.read_offset.set(next);
+                let len = core::cmp::min(record.packet.len(), buf.len());
+                buf[..len].copy_from_slice(&record.packet[..len]);
+                buf[len..].fill(0);
+            }
+            None => buf.fill(0),
+        }
+    }
+}
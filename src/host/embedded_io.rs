@@ -0,0 +1,233 @@
+//! [`Controller`] over any [`embedded_io_async`] byte stream.
+//!
+//! Only available with the `embedded-io-async` feature.
+//!
+//! [`IoController`] frames commands with the UART packet type byte and
+//! [`CommandHeader`](crate::host::uart::CommandHeader), and reassembles the event and ACL data
+//! packets coming back from the stream, however the transport splits them into reads. This lets a
+//! UART driver with DMA (such as the ones from embassy) be used directly with
+//! [`UartHci`](crate::host::uart::UartHci) and the vendor command traits.
+
+use crate::host::uart::CommandHeader;
+use crate::host::HciHeader;
+use crate::{Controller, Opcode};
+use byteorder::{ByteOrder, LittleEndian};
+use core::cell::RefCell;
+use embedded_io_async::{Read, ReadExactError, Write};
+
+const PACKET_TYPE_ACL_DATA: u8 = 0x02;
+const PACKET_TYPE_HCI_EVENT: u8 = 0x04;
+
+const EVENT_HEADER_LENGTH: usize = 2;
+const ACL_HEADER_LENGTH: usize = 4;
+
+/// Length of the longest event packet: the packet type byte, the event header and 255 bytes of
+/// parameters. This is the default size of the reassembly buffer of [`IoController`].
+pub const MAX_EVENT_PACKET_LENGTH: usize = 1 + EVENT_HEADER_LENGTH + 255;
+
+/// Potential errors from the byte stream of an [`IoController`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The underlying stream returned an error.
+    Io(E),
+    /// The stream ended in the middle of a packet.
+    UnexpectedEof,
+    /// A packet started with a byte that is not the type of an event or ACL data packet. The
+    /// stream is no longer aligned to packet boundaries. Contains the value of the byte.
+    UnknownPacketType(u8),
+    /// A packet did not fit in the reassembly buffer and was dropped. Contains the length of the
+    /// packet, including its packet type byte.
+    PacketTooLong(usize),
+}
+
+impl<E> From<ReadExactError<E>> for Error<E> {
+    fn from(e: ReadExactError<E>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => Error::UnexpectedEof,
+            ReadExactError::Other(e) => Error::Io(e),
+        }
+    }
+}
+
+struct Inner<T, E, const N: usize> {
+    io: T,
+    packet: [u8; N],
+    pos: usize,
+    len: usize,
+    error: Option<Error<E>>,
+}
+
+/// [`Controller`] that writes commands to and reads packets from a byte stream.
+///
+/// `N` is the size of the reassembly buffer, which must hold the longest packet the controller
+/// sends, including its packet type byte. Longer packets are dropped. The buffer must be longer
+/// than the packet type byte and the ACL data header, that is at least 6 bytes; smaller buffers
+/// fail to build.
+///
+/// The [`Controller`] trait cannot report errors, so errors from the stream are kept until
+/// [`take_error`](IoController::take_error) is called. After a read error, the rest of the read
+/// buffer is filled with zeros, which [`UartHci::read`](crate::host::uart::UartHci::read) reports
+/// as a bad packet type.
+pub struct IoController<T, const N: usize = MAX_EVENT_PACKET_LENGTH>
+where
+    T: Read + Write,
+{
+    inner: RefCell<Inner<T, T::Error, N>>,
+}
+
+impl<T, const N: usize> IoController<T, N>
+where
+    T: Read + Write,
+{
+    /// Creates a controller over the given stream.
+    pub fn new(io: T) -> IoController<T, N> {
+        const {
+            core::assert!(
+                N > 1 + ACL_HEADER_LENGTH,
+                "the reassembly buffer cannot hold the ACL data header"
+            )
+        };
+
+        IoController {
+            inner: RefCell::new(Inner {
+                io,
+                packet: [0; N],
+                pos: 0,
+                len: 0,
+                error: None,
+            }),
+        }
+    }
+
+    /// Returns and clears the first error since the last call.
+    pub fn take_error(&mut self) -> Option<Error<T::Error>> {
+        self.inner.get_mut().error.take()
+    }
+
+    /// Returns the number of bytes of the current packet that have not been read yet.
+    pub fn pending(&self) -> usize {
+        let inner = self.inner.borrow();
+        inner.len - inner.pos
+    }
+
+    /// Consumes the controller and returns the stream. Any partially read packet is lost.
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner().io
+    }
+}
+
+impl<T, E, const N: usize> Inner<T, E, N>
+where
+    T: Read<Error = E> + Write<Error = E>,
+{
+    fn record(&mut self, e: Error<E>) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+
+    // Reads the next packet into the reassembly buffer.
+    async fn fill(&mut self) -> Result<(), Error<E>> {
+        self.pos = 0;
+        self.len = 0;
+
+        self.io.read_exact(&mut self.packet[..1]).await?;
+        let header_len = match self.packet[0] {
+            PACKET_TYPE_HCI_EVENT => EVENT_HEADER_LENGTH,
+            PACKET_TYPE_ACL_DATA => ACL_HEADER_LENGTH,
+            x => {
+                // Hand the byte to the reader anyway, so that it sees the bad packet type.
+                self.len = 1;
+                return Err(Error::UnknownPacketType(x));
+            }
+        };
+
+        let header_end = 1 + header_len;
+        self.io.read_exact(&mut self.packet[1..header_end]).await?;
+        let payload_len = match self.packet[0] {
+            PACKET_TYPE_HCI_EVENT => self.packet[2] as usize,
+            _ => LittleEndian::read_u16(&self.packet[3..5]) as usize,
+        };
+
+        let packet_len = header_end + payload_len;
+        if packet_len > N {
+            let mut remaining = payload_len;
+            while remaining > 0 {
+                let chunk = core::cmp::min(remaining, N - header_end);
+                self.io
+                    .read_exact(&mut self.packet[header_end..header_end + chunk])
+                    .await?;
+                remaining -= chunk;
+            }
+            return Err(Error::PacketTooLong(packet_len));
+        }
+
+        self.io
+            .read_exact(&mut self.packet[header_end..packet_len])
+            .await?;
+        self.len = packet_len;
+        Ok(())
+    }
+}
+
+impl<T, const N: usize> Controller for IoController<T, N>
+where
+    T: Read + Write,
+{
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        const HEADER_LENGTH: usize = <CommandHeader as HciHeader>::HEADER_LENGTH;
+
+        let inner = self.inner.get_mut();
+        let mut header = [0; HEADER_LENGTH];
+        CommandHeader::new(opcode, payload.len()).copy_into_slice(&mut header);
+
+        let result = async {
+            inner.io.write_all(&header).await?;
+            inner.io.write_all(payload).await?;
+            inner.io.flush().await
+        }
+        .await;
+        if let Err(e) = result {
+            inner.record(Error::Io(e));
+        }
+    }
+
+    // Writes need `&mut self`, so only a second, concurrent read can contend for the borrow. Two
+    // readers would interleave the bytes of a packet anyway, so that panics instead.
+    #[allow(clippy::await_holding_refcell_ref)]
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        let mut inner = self.inner.borrow_mut();
+        let mut written = 0;
+        while written < buf.len() {
+            if inner.pos == inner.len {
+                match inner.fill().await {
+                    Ok(()) => (),
+                    Err(e @ Error::PacketTooLong(_)) => {
+                        inner.record(e);
+                        continue;
+                    }
+                    Err(e @ Error::UnknownPacketType(_)) => inner.record(e),
+                    Err(e) => {
+                        inner.record(e);
+                        buf[written..].fill(0);
+                        return;
+                    }
+                }
+            }
+
+            let count = core::cmp::min(buf.len() - written, inner.len - inner.pos);
+            let pos = inner.pos;
+            buf[written..written + count].copy_from_slice(&inner.packet[pos..pos + count]);
+            inner.pos += count;
+            written += count;
+
+            // Never read into the next packet: a caller that asks for more than the rest of the
+            // packet gets only the packet, followed by zeros.
+            if inner.pos == inner.len {
+                buf[written..].fill(0);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,616 @@
+//! Implementation of the HCI over the STM32WB transport layer (TL) and IPCC mailbox.
+//!
+//! On STM32WB parts, the host on the Cortex-M4 (CPU1) and the wireless stack on the Cortex-M0+
+//! (CPU2) exchange HCI packets through buffers in shared SRAM2. CPU1 signals that a command is
+//! ready, and CPU2 signals that events are queued, by setting flags on the IPCC channels.
+//!
+//! This module provides:
+//!
+//! - [`TlPacket`], which encodes and decodes the serial part of TL command, event and ACL data
+//!   packets. It is the same as a UART packet: the packet type byte followed by the HCI packet.
+//! - [`Queue`], the doubly-linked circular list that CPU2 uses to pass event buffers to CPU1, and
+//!   that CPU1 uses to give them back to the memory manager.
+//! - [`IpccController`], a [`Controller`] on top of the [`Mailbox`] and [`SharedMemory`] traits.
+//!   Both traits can be implemented in RAM, so the whole transport can be tested on the host.
+//!
+//! See AN5289, "Building wireless applications with STM32WB Series microcontrollers", section 4.
+
+use crate::{Controller, Opcode};
+use byteorder::{ByteOrder, LittleEndian};
+use core::cell::{Cell, RefCell};
+
+/// Length of the linked-list node (the `next` and `prev` pointers) that starts every TL packet in
+/// shared memory. The serial part of the packet follows it.
+pub const PACKET_HEADER_LENGTH: usize = 8;
+
+/// Length of the serial part of a command packet header: the packet type, the opcode and the
+/// parameter length.
+pub const COMMAND_HEADER_LENGTH: usize = 4;
+
+/// Length of the serial part of an event packet header: the packet type, the event code and the
+/// parameter length.
+pub const EVENT_HEADER_LENGTH: usize = 3;
+
+/// Length of the serial part of an ACL data packet header: the packet type, the connection handle
+/// with its flags and the data length.
+pub const ACL_DATA_HEADER_LENGTH: usize = 5;
+
+/// Length of the largest serial command or event packet, with 255 bytes of parameters.
+pub const MAX_SERIAL_PACKET_LENGTH: usize = COMMAND_HEADER_LENGTH + 255;
+
+/// Types of TL packets, given by the first byte of the serial part of the packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PacketType {
+    /// BLE HCI command.
+    BleCommand,
+    /// HCI ACL data.
+    AclData,
+    /// BLE HCI event.
+    BleEvent,
+    /// System (SHCI) command.
+    SystemCommand,
+    /// Response to a system command, written back into the system command buffer as a Command
+    /// Complete event.
+    SystemResponse,
+    /// Asynchronous system event.
+    SystemEvent,
+    /// Command handled locally by CPU2.
+    LocalCommand,
+    /// Response to a local command.
+    LocalResponse,
+}
+
+impl PacketType {
+    /// Returns the value of the packet type byte.
+    pub fn value(self) -> u8 {
+        match self {
+            PacketType::BleCommand => 0x01,
+            PacketType::AclData => 0x02,
+            PacketType::BleEvent => 0x04,
+            PacketType::SystemCommand => 0x10,
+            PacketType::SystemResponse => 0x11,
+            PacketType::SystemEvent => 0x12,
+            PacketType::LocalCommand => 0x20,
+            PacketType::LocalResponse => 0x21,
+        }
+    }
+}
+
+impl TryFrom<u8> for PacketType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(PacketType::BleCommand),
+            0x02 => Ok(PacketType::AclData),
+            0x04 => Ok(PacketType::BleEvent),
+            0x10 => Ok(PacketType::SystemCommand),
+            0x11 => Ok(PacketType::SystemResponse),
+            0x12 => Ok(PacketType::SystemEvent),
+            0x20 => Ok(PacketType::LocalCommand),
+            0x21 => Ok(PacketType::LocalResponse),
+            _ => Err(Error::BadPacketType(value)),
+        }
+    }
+}
+
+/// Potential errors from decoding TL packets.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The packet type byte is not a known TL packet type. Contains the value of the byte.
+    BadPacketType(u8),
+    /// The buffer is shorter than the packet header, or than the length given in the header.
+    /// Contains the length of the buffer.
+    BadLength(usize),
+}
+
+/// Serial part of a TL packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TlPacket<'a> {
+    /// A BLE, system or local command.
+    Command {
+        /// Type of the packet.
+        packet_type: PacketType,
+        /// Opcode of the command.
+        opcode: Opcode,
+        /// Parameters of the command.
+        params: &'a [u8],
+    },
+
+    /// A BLE or system event, or the response to a system or local command.
+    Event {
+        /// Type of the packet.
+        packet_type: PacketType,
+        /// Event code.
+        event_code: u8,
+        /// Parameters of the event.
+        params: &'a [u8],
+    },
+
+    /// HCI ACL data.
+    AclData {
+        /// Connection handle, with the packet boundary and broadcast flags in the top 4 bits.
+        handle: u16,
+        /// The data.
+        data: &'a [u8],
+    },
+}
+
+impl<'a> TlPacket<'a> {
+    /// Deserializes the packet at the start of the buffer. Bytes after the packet are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::BadPacketType`] if the first byte is not a known packet type.
+    /// - [`Error::BadLength`] if the buffer is too short for the packet.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<TlPacket<'a>, Error> {
+        let packet_type = PacketType::try_from(*bytes.first().ok_or(Error::BadLength(0))?)?;
+        let header_len = match packet_type {
+            PacketType::BleEvent
+            | PacketType::SystemEvent
+            | PacketType::SystemResponse
+            | PacketType::LocalResponse => EVENT_HEADER_LENGTH,
+            PacketType::AclData => ACL_DATA_HEADER_LENGTH,
+            _ => COMMAND_HEADER_LENGTH,
+        };
+        if bytes.len() < header_len {
+            return Err(Error::BadLength(bytes.len()));
+        }
+
+        let body_len = match packet_type {
+            PacketType::BleEvent
+            | PacketType::SystemEvent
+            | PacketType::SystemResponse
+            | PacketType::LocalResponse => bytes[2] as usize,
+            PacketType::AclData => LittleEndian::read_u16(&bytes[3..5]) as usize,
+            _ => bytes[3] as usize,
+        };
+        let body = bytes
+            .get(header_len..header_len + body_len)
+            .ok_or(Error::BadLength(bytes.len()))?;
+
+        Ok(match packet_type {
+            PacketType::BleEvent
+            | PacketType::SystemEvent
+            | PacketType::SystemResponse
+            | PacketType::LocalResponse => TlPacket::Event {
+                packet_type,
+                event_code: bytes[1],
+                params: body,
+            },
+            PacketType::AclData => TlPacket::AclData {
+                handle: LittleEndian::read_u16(&bytes[1..3]),
+                data: body,
+            },
+            _ => TlPacket::Command {
+                packet_type,
+                opcode: Opcode(LittleEndian::read_u16(&bytes[1..3])),
+                params: body,
+            },
+        })
+    }
+
+    /// Returns the length of the serialized packet.
+    pub fn serialized_length(&self) -> usize {
+        match self {
+            TlPacket::Command { params, .. } => COMMAND_HEADER_LENGTH + params.len(),
+            TlPacket::Event { params, .. } => EVENT_HEADER_LENGTH + params.len(),
+            TlPacket::AclData { data, .. } => ACL_DATA_HEADER_LENGTH + data.len(),
+        }
+    }
+
+    /// Serializes the packet into the buffer and returns the number of bytes written.
+    ///
+    /// # Panics
+    ///
+    /// - If the buffer is shorter than [`serialized_length`](TlPacket::serialized_length).
+    /// - If the parameters of a command or event are longer than 255 bytes.
+    pub fn copy_into_slice(&self, bytes: &mut [u8]) -> usize {
+        let len = self.serialized_length();
+        match *self {
+            TlPacket::Command {
+                packet_type,
+                opcode,
+                params,
+            } => {
+                bytes[0] = packet_type.value();
+                LittleEndian::write_u16(&mut bytes[1..3], opcode.0);
+                bytes[3] = u8::try_from(params.len()).unwrap();
+                bytes[COMMAND_HEADER_LENGTH..len].copy_from_slice(params);
+            }
+            TlPacket::Event {
+                packet_type,
+                event_code,
+                params,
+            } => {
+                bytes[0] = packet_type.value();
+                bytes[1] = event_code;
+                bytes[2] = u8::try_from(params.len()).unwrap();
+                bytes[EVENT_HEADER_LENGTH..len].copy_from_slice(params);
+            }
+            TlPacket::AclData { handle, data } => {
+                bytes[0] = PacketType::AclData.value();
+                LittleEndian::write_u16(&mut bytes[1..3], handle);
+                LittleEndian::write_u16(&mut bytes[3..5], data.len() as u16);
+                bytes[ACL_DATA_HEADER_LENGTH..len].copy_from_slice(data);
+            }
+        }
+
+        len
+    }
+}
+
+/// Memory shared between the two cores, addressed as CPU1 sees it.
+///
+/// Pointers stored in shared memory are 32-bit little-endian addresses.
+pub trait SharedMemory {
+    /// Reads `buf.len()` bytes starting at `address`.
+    fn read(&self, address: u32, buf: &mut [u8]);
+
+    /// Writes the bytes starting at `address`.
+    fn write(&mut self, address: u32, bytes: &[u8]);
+
+    /// Reads a 32-bit word (a pointer) at `address`.
+    fn read_u32(&self, address: u32) -> u32 {
+        let mut word = [0; 4];
+        self.read(address, &mut word);
+        LittleEndian::read_u32(&word)
+    }
+
+    /// Writes a 32-bit word (a pointer) at `address`.
+    fn write_u32(&mut self, address: u32, value: u32) {
+        let mut word = [0; 4];
+        LittleEndian::write_u32(&mut word, value);
+        self.write(address, &word);
+    }
+}
+
+/// [`SharedMemory`] backed by a slice that stands in for SRAM2 at a given base address.
+///
+/// Accesses outside of the slice panic.
+pub struct RamMemory<'a> {
+    base: u32,
+    bytes: &'a mut [u8],
+}
+
+impl<'a> RamMemory<'a> {
+    /// Maps the slice at the base address.
+    pub fn new(base: u32, bytes: &'a mut [u8]) -> RamMemory<'a> {
+        RamMemory { base, bytes }
+    }
+
+    fn range(&self, address: u32, len: usize) -> core::ops::Range<usize> {
+        let start = address
+            .checked_sub(self.base)
+            .expect("address below the mapped memory") as usize;
+        start..start + len
+    }
+}
+
+impl<'a> SharedMemory for RamMemory<'a> {
+    fn read(&self, address: u32, buf: &mut [u8]) {
+        let range = self.range(address, buf.len());
+        buf.copy_from_slice(&self.bytes[range]);
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) {
+        let range = self.range(address, bytes.len());
+        self.bytes[range].copy_from_slice(bytes);
+    }
+}
+
+/// [`SharedMemory`] that accesses the physical memory of the device with volatile reads and
+/// writes.
+pub struct PhysicalMemory {
+    _private: (),
+}
+
+impl PhysicalMemory {
+    /// Returns access to the physical memory.
+    ///
+    /// # Safety
+    ///
+    /// All addresses passed to the returned memory must be valid for reads and writes of the given
+    /// length, and must not be accessed by CPU1 through any other reference at the same time.
+    pub unsafe fn new() -> PhysicalMemory {
+        PhysicalMemory { _private: () }
+    }
+}
+
+impl SharedMemory for PhysicalMemory {
+    fn read(&self, address: u32, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            // SAFETY: guaranteed by the caller of `PhysicalMemory::new`.
+            *byte = unsafe { core::ptr::read_volatile((address as usize + i) as *const u8) };
+        }
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            // SAFETY: guaranteed by the caller of `PhysicalMemory::new`.
+            unsafe { core::ptr::write_volatile((address as usize + i) as *mut u8, *byte) };
+        }
+    }
+}
+
+/// Doubly-linked circular list in shared memory, identified by the address of its head node.
+///
+/// Each node is the 8-byte header of a packet: the address of the next node followed by the
+/// address of the previous node. The list is empty when the head points to itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Queue {
+    /// Address of the head node.
+    pub head: u32,
+}
+
+impl Queue {
+    /// Initializes the head node of an empty list.
+    pub fn init<S: SharedMemory>(&self, memory: &mut S) {
+        memory.write_u32(self.head, self.head);
+        memory.write_u32(self.head + 4, self.head);
+    }
+
+    /// Returns true if the list has no nodes.
+    pub fn is_empty<S: SharedMemory>(&self, memory: &S) -> bool {
+        memory.read_u32(self.head) == self.head
+    }
+
+    /// Appends the node at `node` to the end of the list.
+    pub fn push_back<S: SharedMemory>(&self, memory: &mut S, node: u32) {
+        let last = memory.read_u32(self.head + 4);
+        memory.write_u32(node, self.head);
+        memory.write_u32(node + 4, last);
+        memory.write_u32(self.head + 4, node);
+        memory.write_u32(last, node);
+    }
+
+    /// Removes the first node of the list and returns its address, or `None` if the list is
+    /// empty.
+    pub fn pop_front<S: SharedMemory>(&self, memory: &mut S) -> Option<u32> {
+        if self.is_empty(memory) {
+            return None;
+        }
+
+        let node = memory.read_u32(self.head);
+        let next = memory.read_u32(node);
+        let prev = memory.read_u32(node + 4);
+        memory.write_u32(prev, next);
+        memory.write_u32(next + 4, prev);
+        Some(node)
+    }
+}
+
+/// IPCC channel number. Each channel has one flag in each direction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Channel(pub u8);
+
+impl Channel {
+    /// CPU1 to CPU2: a BLE command is in the command buffer.
+    pub const BLE_COMMAND: Channel = Channel(1);
+    /// CPU2 to CPU1: BLE events are in the event queue.
+    pub const BLE_EVENT: Channel = Channel(1);
+    /// CPU1 to CPU2: a system command is in the system command buffer.
+    pub const SYSTEM_COMMAND: Channel = Channel(2);
+    /// CPU2 to CPU1: system events are in the system event queue.
+    pub const SYSTEM_EVENT: Channel = Channel(2);
+    /// CPU1 to CPU2: event buffers are in the free buffer queue.
+    pub const RELEASE_BUFFER: Channel = Channel(4);
+    /// CPU1 to CPU2: ACL data is in the ACL data buffer.
+    pub const ACL_DATA: Channel = Channel(6);
+}
+
+/// Abstraction of the IPCC peripheral, seen from CPU1.
+///
+/// Transmit flags are set by CPU1 and cleared by CPU2 once it has consumed the buffer; receive
+/// flags are set by CPU2 and cleared by CPU1 once it has consumed the buffer.
+pub trait Mailbox {
+    /// Waits until CPU2 has cleared the transmit flag of the channel, so that the buffer behind
+    /// the channel belongs to CPU1 again.
+    async fn wait_tx_free(&self, channel: Channel);
+
+    /// Sets the transmit flag of the channel, handing the buffer behind it to CPU2.
+    fn set_tx(&self, channel: Channel);
+
+    /// Waits until CPU2 sets the receive flag of the channel.
+    async fn wait_rx(&self, channel: Channel);
+
+    /// Clears the receive flag of the channel.
+    fn clear_rx(&self, channel: Channel);
+}
+
+/// Addresses of the BLE buffers in shared memory, from the BLE and memory manager tables of the
+/// reference table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Layout {
+    /// Address of the BLE command packet buffer.
+    pub command_buffer: u32,
+    /// Address of the HCI ACL data packet buffer.
+    pub acl_data_buffer: u32,
+    /// Head of the queue of BLE events from CPU2.
+    pub event_queue: Queue,
+    /// Head of the queue of event buffers handed back to the memory manager of CPU2.
+    pub free_buffer_queue: Queue,
+}
+
+/// [`Controller`] that exchanges BLE packets with CPU2 through the IPCC mailbox and shared memory.
+///
+/// Each call to [`controller_read_into`](Controller::controller_read_into) takes the next event
+/// (or ACL data packet) from the event queue, waiting on the mailbox if the queue is empty. The
+/// serial part of the packet is copied into the buffer, followed by zeros, and the event buffer is
+/// handed back to the memory manager of CPU2. A packet longer than the buffer is truncated.
+///
+/// System events belong to the system channel, which this controller does not serve. One found in
+/// the BLE event queue is dropped.
+pub struct IpccController<M, S> {
+    mailbox: M,
+    memory: RefCell<S>,
+    layout: Layout,
+    released: Cell<usize>,
+    // First and last event buffers waiting for the release channel, linked through their `next`
+    // pointers. CPU2 owns the free buffer queue until it clears the channel.
+    pending_release: Cell<Option<(u32, u32)>>,
+}
+
+impl<M, S> IpccController<M, S>
+where
+    M: Mailbox,
+    S: SharedMemory,
+{
+    /// Creates a controller and initializes the event and free buffer queues.
+    ///
+    /// This must be called before CPU2 is started, since CPU2 starts queuing events right away.
+    pub fn new(mailbox: M, mut memory: S, layout: Layout) -> IpccController<M, S> {
+        layout.event_queue.init(&mut memory);
+        layout.free_buffer_queue.init(&mut memory);
+        IpccController {
+            mailbox,
+            memory: RefCell::new(memory),
+            layout,
+            released: Cell::new(0),
+            pending_release: Cell::new(None),
+        }
+    }
+
+    /// Returns the mailbox.
+    pub fn mailbox(&self) -> &M {
+        &self.mailbox
+    }
+
+    /// Returns the shared memory.
+    pub fn memory_mut(&mut self) -> &mut S {
+        self.memory.get_mut()
+    }
+
+    /// Returns the layout of the buffers.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Returns the number of event buffers handed back to CPU2 so far.
+    pub fn buffers_released(&self) -> usize {
+        self.released.get()
+    }
+
+    /// Writes HCI ACL data to the ACL data buffer and hands it to CPU2.
+    ///
+    /// # Panics
+    ///
+    /// If the data is longer than 65535 bytes.
+    pub async fn write_acl_data(&mut self, handle: u16, data: &[u8]) {
+        self.mailbox.wait_tx_free(Channel::ACL_DATA).await;
+
+        let memory = self.memory.get_mut();
+        let address = self.layout.acl_data_buffer + PACKET_HEADER_LENGTH as u32;
+        let mut header = [0; ACL_DATA_HEADER_LENGTH];
+        TlPacket::AclData { handle, data: &[] }.copy_into_slice(&mut header);
+        LittleEndian::write_u16(&mut header[3..5], u16::try_from(data.len()).unwrap());
+        memory.write(address, &header);
+        memory.write(address + ACL_DATA_HEADER_LENGTH as u32, data);
+
+        self.mailbox.set_tx(Channel::ACL_DATA);
+    }
+
+    // Hands the event buffer back to the memory manager of CPU2. The buffer waits in the pending
+    // list until the release channel is free, and is then moved to the free buffer queue together
+    // with any buffer left pending by an earlier read.
+    async fn release(&self, node: u32) {
+        let pending = match self.pending_release.get() {
+            Some((first, last)) => {
+                self.memory.borrow_mut().write_u32(last, node);
+                (first, node)
+            }
+            None => (node, node),
+        };
+        self.pending_release.set(Some(pending));
+
+        self.mailbox.wait_tx_free(Channel::RELEASE_BUFFER).await;
+        let Some((first, last)) = self.pending_release.take() else {
+            return;
+        };
+        let mut node = first;
+        loop {
+            let mut memory = self.memory.borrow_mut();
+            // Pushing the node overwrites its `next` pointer.
+            let next = memory.read_u32(node);
+            self.layout.free_buffer_queue.push_back(&mut *memory, node);
+            self.released.set(self.released.get() + 1);
+            if node == last {
+                break;
+            }
+            node = next;
+        }
+        self.mailbox.set_tx(Channel::RELEASE_BUFFER);
+    }
+}
+
+impl<M, S> Controller for IpccController<M, S>
+where
+    M: Mailbox,
+    S: SharedMemory,
+{
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        self.mailbox.wait_tx_free(Channel::BLE_COMMAND).await;
+
+        let mut packet = [0; MAX_SERIAL_PACKET_LENGTH];
+        let len = TlPacket::Command {
+            packet_type: PacketType::BleCommand,
+            opcode,
+            params: payload,
+        }
+        .copy_into_slice(&mut packet);
+        self.memory.get_mut().write(
+            self.layout.command_buffer + PACKET_HEADER_LENGTH as u32,
+            &packet[..len],
+        );
+
+        self.mailbox.set_tx(Channel::BLE_COMMAND);
+    }
+
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        let event_queue = self.layout.event_queue;
+        loop {
+            // Clear the flag before looking at the queue: events queued after that set it again,
+            // so none can be missed.
+            loop {
+                self.mailbox.clear_rx(Channel::BLE_EVENT);
+                if !event_queue.is_empty(&*self.memory.borrow()) {
+                    break;
+                }
+                self.mailbox.wait_rx(Channel::BLE_EVENT).await;
+            }
+
+            let node = event_queue
+                .pop_front(&mut *self.memory.borrow_mut())
+                .unwrap();
+            let serial = node + PACKET_HEADER_LENGTH as u32;
+
+            let mut header = [0; ACL_DATA_HEADER_LENGTH];
+            self.memory.borrow().read(serial, &mut header);
+            let packet_len = match PacketType::try_from(header[0]) {
+                Ok(PacketType::BleEvent) => EVENT_HEADER_LENGTH + header[2] as usize,
+                Ok(PacketType::AclData) => {
+                    ACL_DATA_HEADER_LENGTH + LittleEndian::read_u16(&header[3..5]) as usize
+                }
+                Ok(PacketType::SystemEvent) => {
+                    self.release(node).await;
+                    continue;
+                }
+                // Hand over the packet type byte alone, so that the reader sees the bad packet
+                // type.
+                _ => 1,
+            };
+            let len = core::cmp::min(packet_len, buf.len());
+            self.memory.borrow().read(serial, &mut buf[..len]);
+            buf[len..].fill(0);
+
+            self.release(node).await;
+            return;
+        }
+    }
+}
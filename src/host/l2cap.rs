@@ -0,0 +1,1431 @@
+//! Host implementation of the LE L2CAP signaling channel.
+//!
+//! The controller-resident L2CAP commands ([`L2capCommands`](crate::vendor::stm32wb::command::l2cap::L2capCommands))
+//! cover connection parameter updates when the stack runs on the coprocessor. When the host
+//! receives ACL data itself, it must answer the LE signaling channel (CID 0x0005) on its own. This
+//! module provides:
+//!
+//! - [`SignalingCommand`], which serializes and deserializes the LE signaling commands: command
+//!   reject, connection parameter update request/response, LE credit-based connection
+//!   request/response, flow control credit, and disconnection request/response.
+//! - [`LeSignalingChannel`], which tracks outstanding requests and the open LE credit-based
+//!   channels of one connection, and produces the replies to requests from the peer.
+//! - [`CreditBasedChannel`] and [`Segmenter`], which segment outgoing SDUs into K-frames and
+//!   reassemble incoming K-frames into SDUs while respecting the MTU, MPS and credits of each side.
+//!
+//! See the Bluetooth Specification, v5.0, Vol 3, Part A.
+
+use crate::types::{ConnectionInterval, ConnectionIntervalError};
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Channel identifier of the attribute protocol.
+pub const ATT_CID: u16 = 0x0004;
+
+/// Channel identifier of the LE signaling channel.
+pub const LE_SIGNALING_CID: u16 = 0x0005;
+
+/// Channel identifier of the security manager protocol.
+pub const SMP_CID: u16 = 0x0006;
+
+/// Length of the basic L2CAP header: the 2-byte PDU length followed by the 2-byte channel ID.
+pub const BASIC_HEADER_LENGTH: usize = 4;
+
+/// Length of the signaling command header: code, identifier, and the 2-byte data length.
+pub const COMMAND_HEADER_LENGTH: usize = 4;
+
+/// Largest serialized signaling command, including its header.
+pub const MAX_COMMAND_LENGTH: usize = COMMAND_HEADER_LENGTH + 10;
+
+/// Minimum MTU and MPS for LE credit-based channels.
+pub const MIN_MTU: u16 = 23;
+
+/// Maximum MPS for LE credit-based channels.
+pub const MAX_MPS: u16 = 65533;
+
+const FIRST_DYNAMIC_CID: u16 = 0x0040;
+const LAST_DYNAMIC_CID: u16 = 0x007F;
+const SDU_LENGTH_FIELD: usize = 2;
+
+const COMMAND_REJECT: u8 = 0x01;
+const DISCONNECTION_REQUEST: u8 = 0x06;
+const DISCONNECTION_RESPONSE: u8 = 0x07;
+const CONNECTION_PARAMETER_UPDATE_REQUEST: u8 = 0x12;
+const CONNECTION_PARAMETER_UPDATE_RESPONSE: u8 = 0x13;
+const LE_CREDIT_BASED_CONNECTION_REQUEST: u8 = 0x14;
+const LE_CREDIT_BASED_CONNECTION_RESPONSE: u8 = 0x15;
+const FLOW_CONTROL_CREDIT: u8 = 0x16;
+
+/// Potential errors from handling L2CAP frames and signaling commands.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The frame or command is shorter than its header, or its length field does not match the
+    /// number of bytes provided. Includes the number of bytes provided.
+    BadLength(usize),
+
+    /// The signaling command code is not one of the LE signaling commands. Includes the code.
+    UnknownCommand(u8),
+
+    /// The command reject reason is not recognized. Includes the reason.
+    BadRejectReason(u16),
+
+    /// The connection parameter update response result is neither accepted nor rejected. Includes
+    /// the result.
+    BadConnectionParameterUpdateResult(u16),
+
+    /// The LE credit-based connection result is not recognized. Includes the result.
+    BadConnectionResult(u16),
+
+    /// The connection parameters are invalid. Includes the underlying error.
+    BadConnectionInterval(ConnectionIntervalError),
+
+    /// The MTU is smaller than [`MIN_MTU`]. Includes the MTU.
+    BadMtu(u16),
+
+    /// The MPS is outside of the range [`MIN_MTU`] to [`MAX_MPS`]. Includes the MPS.
+    BadMps(u16),
+
+    /// No credit-based channel with the local CID. Includes the CID.
+    UnknownChannel(u16),
+
+    /// All channel slots or dynamic CIDs are in use.
+    NoFreeChannel,
+
+    /// The channel is not open. Includes the local CID.
+    ChannelNotOpen(u16),
+
+    /// The SDU is longer than the MTU of the receiver. Includes the SDU length and the MTU.
+    MtuExceeded(usize, u16),
+
+    /// The K-frame payload is longer than the MPS of the receiver. Includes the payload length and
+    /// the MPS.
+    MpsExceeded(usize, u16),
+
+    /// The peer has not granted any credits to send the next K-frame.
+    NoCredits,
+
+    /// The credit count would exceed 65535. Includes the local CID.
+    CreditOverflow(u16),
+
+    /// The peer sent a K-frame although it had no credits left. Includes the local CID.
+    CreditUnderflow(u16),
+
+    /// The K-frames received so far do not add up to the announced SDU length. Includes the local
+    /// CID.
+    BadSduLength(u16),
+
+    /// The provided buffer cannot hold the frame or SDU. Includes the required length.
+    BufferTooSmall(usize),
+}
+
+impl From<ConnectionIntervalError> for Error {
+    fn from(e: ConnectionIntervalError) -> Self {
+        Error::BadConnectionInterval(e)
+    }
+}
+
+/// Reasons for a [command reject](SignalingCommand::CommandReject).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RejectReason {
+    /// The command code was not recognized, or the command was malformed.
+    CommandNotUnderstood,
+    /// The signaling MTU was exceeded. Includes the MTU of the sender of the reject.
+    SignalingMtuExceeded(u16),
+    /// The request referenced a channel that does not exist. Includes the local and remote CID, from
+    /// the point of view of the sender of the reject.
+    InvalidCid {
+        /// Local CID of the sender of the reject.
+        local: u16,
+        /// Remote CID of the sender of the reject.
+        remote: u16,
+    },
+}
+
+impl RejectReason {
+    fn copy_into_slice(&self, bytes: &mut [u8]) -> usize {
+        match *self {
+            RejectReason::CommandNotUnderstood => {
+                LittleEndian::write_u16(&mut bytes[0..2], 0x0000);
+                2
+            }
+            RejectReason::SignalingMtuExceeded(mtu) => {
+                LittleEndian::write_u16(&mut bytes[0..2], 0x0001);
+                LittleEndian::write_u16(&mut bytes[2..4], mtu);
+                4
+            }
+            RejectReason::InvalidCid { local, remote } => {
+                LittleEndian::write_u16(&mut bytes[0..2], 0x0002);
+                LittleEndian::write_u16(&mut bytes[2..4], local);
+                LittleEndian::write_u16(&mut bytes[4..6], remote);
+                6
+            }
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 2 {
+            return Err(Error::BadLength(bytes.len()));
+        }
+
+        match LittleEndian::read_u16(&bytes[0..2]) {
+            0x0000 => Ok(RejectReason::CommandNotUnderstood),
+            0x0001 => {
+                require_command_len(bytes, 4)?;
+                Ok(RejectReason::SignalingMtuExceeded(LittleEndian::read_u16(
+                    &bytes[2..4],
+                )))
+            }
+            0x0002 => {
+                require_command_len(bytes, 6)?;
+                Ok(RejectReason::InvalidCid {
+                    local: LittleEndian::read_u16(&bytes[2..4]),
+                    remote: LittleEndian::read_u16(&bytes[4..6]),
+                })
+            }
+            other => Err(Error::BadRejectReason(other)),
+        }
+    }
+}
+
+/// Results of an [LE credit-based connection
+/// request](SignalingCommand::LeCreditBasedConnectionRequest).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u16)]
+pub enum LeCreditBasedConnectionResult {
+    /// The connection was accepted.
+    Success = 0x0000,
+    /// No protocol or service is registered for the SPSM.
+    SpsmNotSupported = 0x0002,
+    /// No resources are available for the channel.
+    NoResources = 0x0004,
+    /// The link is not sufficiently authenticated.
+    InsufficientAuthentication = 0x0005,
+    /// The peer is not authorized to connect.
+    InsufficientAuthorization = 0x0006,
+    /// The encryption key is too short.
+    InsufficientEncryptionKeySize = 0x0007,
+    /// The link is not encrypted.
+    InsufficientEncryption = 0x0008,
+    /// The source CID is not in the dynamic range.
+    InvalidSourceCid = 0x0009,
+    /// The source CID is already in use on this connection.
+    SourceCidAlreadyAllocated = 0x000A,
+    /// The MTU, MPS or initial credits are not acceptable.
+    UnacceptableParameters = 0x000B,
+}
+
+impl TryFrom<u16> for LeCreditBasedConnectionResult {
+    type Error = Error;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0x0000 => Ok(LeCreditBasedConnectionResult::Success),
+            0x0002 => Ok(LeCreditBasedConnectionResult::SpsmNotSupported),
+            0x0004 => Ok(LeCreditBasedConnectionResult::NoResources),
+            0x0005 => Ok(LeCreditBasedConnectionResult::InsufficientAuthentication),
+            0x0006 => Ok(LeCreditBasedConnectionResult::InsufficientAuthorization),
+            0x0007 => Ok(LeCreditBasedConnectionResult::InsufficientEncryptionKeySize),
+            0x0008 => Ok(LeCreditBasedConnectionResult::InsufficientEncryption),
+            0x0009 => Ok(LeCreditBasedConnectionResult::InvalidSourceCid),
+            0x000A => Ok(LeCreditBasedConnectionResult::SourceCidAlreadyAllocated),
+            0x000B => Ok(LeCreditBasedConnectionResult::UnacceptableParameters),
+            _ => Err(Error::BadConnectionResult(value)),
+        }
+    }
+}
+
+/// Commands sent on the LE signaling channel.
+///
+/// Each command carries an identifier that matches responses to requests. See the Bluetooth
+/// Specification, v5.0, Vol 3, Part A, Section 4.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SignalingCommand {
+    /// Sent in response to a command that was not understood or referenced an invalid channel.
+    CommandReject {
+        /// Identifier of the rejected command.
+        identifier: u8,
+        /// Why the command was rejected.
+        reason: RejectReason,
+    },
+
+    /// Request to close an LE credit-based channel.
+    DisconnectionRequest {
+        /// Identifier of the request.
+        identifier: u8,
+        /// CID of the channel at the receiver of the request.
+        destination_cid: u16,
+        /// CID of the channel at the sender of the request.
+        source_cid: u16,
+    },
+
+    /// Confirms that an LE credit-based channel was closed.
+    DisconnectionResponse {
+        /// Identifier of the matching request.
+        identifier: u8,
+        /// CID of the channel at the sender of the response.
+        destination_cid: u16,
+        /// CID of the channel at the receiver of the response.
+        source_cid: u16,
+    },
+
+    /// Sent by the peripheral to request new connection parameters from the central.
+    ConnectionParameterUpdateRequest {
+        /// Identifier of the request.
+        identifier: u8,
+        /// Requested connection parameters.
+        conn_interval: ConnectionInterval,
+    },
+
+    /// Sent by the central to accept or reject requested connection parameters.
+    ConnectionParameterUpdateResponse {
+        /// Identifier of the matching request.
+        identifier: u8,
+        /// True if the central accepted the parameters.
+        accepted: bool,
+    },
+
+    /// Request to open an LE credit-based channel.
+    LeCreditBasedConnectionRequest {
+        /// Identifier of the request.
+        identifier: u8,
+        /// Simplified protocol/service multiplexer to connect to.
+        spsm: u16,
+        /// CID of the channel at the sender of the request.
+        source_cid: u16,
+        /// Largest SDU the sender of the request can receive.
+        mtu: u16,
+        /// Largest K-frame payload the sender of the request can receive.
+        mps: u16,
+        /// Number of K-frames the receiver of the request may send.
+        initial_credits: u16,
+    },
+
+    /// Accepts or refuses an LE credit-based channel.
+    LeCreditBasedConnectionResponse {
+        /// Identifier of the matching request.
+        identifier: u8,
+        /// CID of the channel at the sender of the response.
+        destination_cid: u16,
+        /// Largest SDU the sender of the response can receive.
+        mtu: u16,
+        /// Largest K-frame payload the sender of the response can receive.
+        mps: u16,
+        /// Number of K-frames the receiver of the response may send.
+        initial_credits: u16,
+        /// Whether the channel was opened, and if not, why.
+        result: LeCreditBasedConnectionResult,
+    },
+
+    /// Grants additional credits to the peer of an LE credit-based channel.
+    FlowControlCredit {
+        /// Identifier of the command.
+        identifier: u8,
+        /// CID of the channel at the sender of the command.
+        cid: u16,
+        /// Number of additional K-frames the receiver of the command may send.
+        credits: u16,
+    },
+}
+
+impl SignalingCommand {
+    /// Returns the identifier of the command.
+    pub fn identifier(&self) -> u8 {
+        match *self {
+            SignalingCommand::CommandReject { identifier, .. }
+            | SignalingCommand::DisconnectionRequest { identifier, .. }
+            | SignalingCommand::DisconnectionResponse { identifier, .. }
+            | SignalingCommand::ConnectionParameterUpdateRequest { identifier, .. }
+            | SignalingCommand::ConnectionParameterUpdateResponse { identifier, .. }
+            | SignalingCommand::LeCreditBasedConnectionRequest { identifier, .. }
+            | SignalingCommand::LeCreditBasedConnectionResponse { identifier, .. }
+            | SignalingCommand::FlowControlCredit { identifier, .. } => identifier,
+        }
+    }
+
+    /// Deserializes a signaling command, including its 4-byte command header.
+    ///
+    /// # Errors
+    ///
+    /// - [`BadLength`](Error::BadLength) if the buffer does not match the length in the command
+    ///   header or the length required by the command.
+    /// - [`UnknownCommand`](Error::UnknownCommand) if the code is not an LE signaling command.
+    /// - [`BadRejectReason`](Error::BadRejectReason),
+    ///   [`BadConnectionParameterUpdateResult`](Error::BadConnectionParameterUpdateResult) or
+    ///   [`BadConnectionResult`](Error::BadConnectionResult) if an enumerated field is invalid.
+    /// - [`BadConnectionInterval`](Error::BadConnectionInterval) if the requested connection
+    ///   parameters are invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < COMMAND_HEADER_LENGTH {
+            return Err(Error::BadLength(bytes.len()));
+        }
+
+        let code = bytes[0];
+        let identifier = bytes[1];
+        let data_len = LittleEndian::read_u16(&bytes[2..4]) as usize;
+        if bytes.len() != COMMAND_HEADER_LENGTH + data_len {
+            return Err(Error::BadLength(bytes.len()));
+        }
+
+        let data = &bytes[COMMAND_HEADER_LENGTH..];
+        match code {
+            COMMAND_REJECT => Ok(SignalingCommand::CommandReject {
+                identifier,
+                reason: RejectReason::from_bytes(data)?,
+            }),
+            DISCONNECTION_REQUEST => {
+                require_command_len(data, 4)?;
+                Ok(SignalingCommand::DisconnectionRequest {
+                    identifier,
+                    destination_cid: LittleEndian::read_u16(&data[0..2]),
+                    source_cid: LittleEndian::read_u16(&data[2..4]),
+                })
+            }
+            DISCONNECTION_RESPONSE => {
+                require_command_len(data, 4)?;
+                Ok(SignalingCommand::DisconnectionResponse {
+                    identifier,
+                    destination_cid: LittleEndian::read_u16(&data[0..2]),
+                    source_cid: LittleEndian::read_u16(&data[2..4]),
+                })
+            }
+            CONNECTION_PARAMETER_UPDATE_REQUEST => {
+                require_command_len(data, 8)?;
+                Ok(SignalingCommand::ConnectionParameterUpdateRequest {
+                    identifier,
+                    conn_interval: ConnectionInterval::from_bytes(data)?,
+                })
+            }
+            CONNECTION_PARAMETER_UPDATE_RESPONSE => {
+                require_command_len(data, 2)?;
+                let accepted = match LittleEndian::read_u16(&data[0..2]) {
+                    0x0000 => true,
+                    0x0001 => false,
+                    other => return Err(Error::BadConnectionParameterUpdateResult(other)),
+                };
+                Ok(SignalingCommand::ConnectionParameterUpdateResponse {
+                    identifier,
+                    accepted,
+                })
+            }
+            LE_CREDIT_BASED_CONNECTION_REQUEST => {
+                require_command_len(data, 10)?;
+                Ok(SignalingCommand::LeCreditBasedConnectionRequest {
+                    identifier,
+                    spsm: LittleEndian::read_u16(&data[0..2]),
+                    source_cid: LittleEndian::read_u16(&data[2..4]),
+                    mtu: LittleEndian::read_u16(&data[4..6]),
+                    mps: LittleEndian::read_u16(&data[6..8]),
+                    initial_credits: LittleEndian::read_u16(&data[8..10]),
+                })
+            }
+            LE_CREDIT_BASED_CONNECTION_RESPONSE => {
+                require_command_len(data, 10)?;
+                Ok(SignalingCommand::LeCreditBasedConnectionResponse {
+                    identifier,
+                    destination_cid: LittleEndian::read_u16(&data[0..2]),
+                    mtu: LittleEndian::read_u16(&data[2..4]),
+                    mps: LittleEndian::read_u16(&data[4..6]),
+                    initial_credits: LittleEndian::read_u16(&data[6..8]),
+                    result: LittleEndian::read_u16(&data[8..10]).try_into()?,
+                })
+            }
+            FLOW_CONTROL_CREDIT => {
+                require_command_len(data, 4)?;
+                Ok(SignalingCommand::FlowControlCredit {
+                    identifier,
+                    cid: LittleEndian::read_u16(&data[0..2]),
+                    credits: LittleEndian::read_u16(&data[2..4]),
+                })
+            }
+            other => Err(Error::UnknownCommand(other)),
+        }
+    }
+
+    /// Serializes the command, including its 4-byte command header, into the given buffer. Returns
+    /// the number of bytes written.
+    ///
+    /// # Panics
+    ///
+    /// The buffer must be at least [`MAX_COMMAND_LENGTH`] bytes long.
+    pub fn copy_into_slice(&self, bytes: &mut [u8]) -> usize {
+        assert!(bytes.len() >= MAX_COMMAND_LENGTH);
+
+        let data = &mut bytes[COMMAND_HEADER_LENGTH..];
+        let (code, data_len) = match *self {
+            SignalingCommand::CommandReject { reason, .. } => {
+                (COMMAND_REJECT, reason.copy_into_slice(data))
+            }
+            SignalingCommand::DisconnectionRequest {
+                destination_cid,
+                source_cid,
+                ..
+            } => {
+                LittleEndian::write_u16(&mut data[0..2], destination_cid);
+                LittleEndian::write_u16(&mut data[2..4], source_cid);
+                (DISCONNECTION_REQUEST, 4)
+            }
+            SignalingCommand::DisconnectionResponse {
+                destination_cid,
+                source_cid,
+                ..
+            } => {
+                LittleEndian::write_u16(&mut data[0..2], destination_cid);
+                LittleEndian::write_u16(&mut data[2..4], source_cid);
+                (DISCONNECTION_RESPONSE, 4)
+            }
+            SignalingCommand::ConnectionParameterUpdateRequest { conn_interval, .. } => {
+                conn_interval.copy_into_slice(&mut data[0..8]);
+                (CONNECTION_PARAMETER_UPDATE_REQUEST, 8)
+            }
+            SignalingCommand::ConnectionParameterUpdateResponse { accepted, .. } => {
+                LittleEndian::write_u16(&mut data[0..2], if accepted { 0x0000 } else { 0x0001 });
+                (CONNECTION_PARAMETER_UPDATE_RESPONSE, 2)
+            }
+            SignalingCommand::LeCreditBasedConnectionRequest {
+                spsm,
+                source_cid,
+                mtu,
+                mps,
+                initial_credits,
+                ..
+            } => {
+                LittleEndian::write_u16(&mut data[0..2], spsm);
+                LittleEndian::write_u16(&mut data[2..4], source_cid);
+                LittleEndian::write_u16(&mut data[4..6], mtu);
+                LittleEndian::write_u16(&mut data[6..8], mps);
+                LittleEndian::write_u16(&mut data[8..10], initial_credits);
+                (LE_CREDIT_BASED_CONNECTION_REQUEST, 10)
+            }
+            SignalingCommand::LeCreditBasedConnectionResponse {
+                destination_cid,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+                ..
+            } => {
+                LittleEndian::write_u16(&mut data[0..2], destination_cid);
+                LittleEndian::write_u16(&mut data[2..4], mtu);
+                LittleEndian::write_u16(&mut data[4..6], mps);
+                LittleEndian::write_u16(&mut data[6..8], initial_credits);
+                LittleEndian::write_u16(&mut data[8..10], result as u16);
+                (LE_CREDIT_BASED_CONNECTION_RESPONSE, 10)
+            }
+            SignalingCommand::FlowControlCredit { cid, credits, .. } => {
+                LittleEndian::write_u16(&mut data[0..2], cid);
+                LittleEndian::write_u16(&mut data[2..4], credits);
+                (FLOW_CONTROL_CREDIT, 4)
+            }
+        };
+
+        bytes[0] = code;
+        bytes[1] = self.identifier();
+        LittleEndian::write_u16(&mut bytes[2..4], data_len as u16);
+
+        COMMAND_HEADER_LENGTH + data_len
+    }
+
+    /// Serializes the command as a complete C-frame on the [LE signaling channel](LE_SIGNALING_CID),
+    /// including the basic L2CAP header. Returns the number of bytes written.
+    ///
+    /// # Panics
+    ///
+    /// The buffer must be at least [`BASIC_HEADER_LENGTH`] + [`MAX_COMMAND_LENGTH`] bytes long.
+    pub fn copy_into_frame(&self, bytes: &mut [u8]) -> usize {
+        let len = self.copy_into_slice(&mut bytes[BASIC_HEADER_LENGTH..]);
+        write_basic_header(bytes, LE_SIGNALING_CID, len);
+
+        BASIC_HEADER_LENGTH + len
+    }
+}
+
+fn require_command_len(data: &[u8], len: usize) -> Result<(), Error> {
+    if data.len() < len {
+        return Err(Error::BadLength(data.len()));
+    }
+
+    Ok(())
+}
+
+fn write_basic_header(bytes: &mut [u8], cid: u16, payload_len: usize) {
+    LittleEndian::write_u16(&mut bytes[0..2], payload_len as u16);
+    LittleEndian::write_u16(&mut bytes[2..4], cid);
+}
+
+/// Splits a basic L2CAP frame (the payload of a complete ACL data packet) into its channel ID and
+/// information payload.
+///
+/// # Errors
+///
+/// - [`BadLength`](Error::BadLength) if the frame is shorter than its header or its length field
+///   does not match the provided buffer.
+pub fn parse_basic_frame(bytes: &[u8]) -> Result<(u16, &[u8]), Error> {
+    if bytes.len() < BASIC_HEADER_LENGTH {
+        return Err(Error::BadLength(bytes.len()));
+    }
+
+    let len = LittleEndian::read_u16(&bytes[0..2]) as usize;
+    if bytes.len() != BASIC_HEADER_LENGTH + len {
+        return Err(Error::BadLength(bytes.len()));
+    }
+
+    Ok((
+        LittleEndian::read_u16(&bytes[2..4]),
+        &bytes[BASIC_HEADER_LENGTH..],
+    ))
+}
+
+/// Receive parameters of the local side of LE credit-based channels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelParameters {
+    /// Largest SDU the local side can receive.
+    pub mtu: u16,
+    /// Largest K-frame payload the local side can receive.
+    pub mps: u16,
+    /// Number of K-frames the peer may send before it needs more credits.
+    pub initial_credits: u16,
+}
+
+impl ChannelParameters {
+    fn validate(&self) -> Result<(), Error> {
+        validate_mtu_mps(self.mtu, self.mps)
+    }
+}
+
+fn validate_mtu_mps(mtu: u16, mps: u16) -> Result<(), Error> {
+    if mtu < MIN_MTU {
+        return Err(Error::BadMtu(mtu));
+    }
+
+    if !(MIN_MTU..=MAX_MPS).contains(&mps) {
+        return Err(Error::BadMps(mps));
+    }
+
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum ChannelState {
+    Connecting(u8),
+    Open,
+    Disconnecting(u8),
+}
+
+/// One LE credit-based channel.
+///
+/// Outgoing SDUs are split into K-frames with a [`Segmenter`]; incoming K-frames are reassembled
+/// with [`receive`](CreditBasedChannel::receive).
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CreditBasedChannel {
+    state: ChannelState,
+    spsm: u16,
+    local_cid: u16,
+    remote_cid: u16,
+    local: ChannelParameters,
+    remote_mtu: u16,
+    remote_mps: u16,
+    tx_credits: u16,
+    rx_credits: u16,
+    rx_sdu_len: Option<usize>,
+    rx_received: usize,
+}
+
+impl CreditBasedChannel {
+    /// Returns the SPSM the channel is connected to.
+    pub fn spsm(&self) -> u16 {
+        self.spsm
+    }
+
+    /// Returns the CID of the channel on the local side.
+    pub fn local_cid(&self) -> u16 {
+        self.local_cid
+    }
+
+    /// Returns the CID of the channel on the peer.
+    pub fn remote_cid(&self) -> u16 {
+        self.remote_cid
+    }
+
+    /// Returns true once both sides have agreed to open the channel.
+    pub fn is_open(&self) -> bool {
+        self.state == ChannelState::Open
+    }
+
+    /// Returns the largest SDU the peer can receive.
+    pub fn remote_mtu(&self) -> u16 {
+        self.remote_mtu
+    }
+
+    /// Returns the largest K-frame payload the peer can receive.
+    pub fn remote_mps(&self) -> u16 {
+        self.remote_mps
+    }
+
+    /// Returns the number of K-frames that may still be sent to the peer.
+    pub fn tx_credits(&self) -> u16 {
+        self.tx_credits
+    }
+
+    /// Returns the number of K-frames the peer may still send.
+    pub fn rx_credits(&self) -> u16 {
+        self.rx_credits
+    }
+
+    /// Starts segmenting an SDU for transmission on this channel.
+    ///
+    /// # Errors
+    ///
+    /// - [`ChannelNotOpen`](Error::ChannelNotOpen) if the channel is not open.
+    /// - [`MtuExceeded`](Error::MtuExceeded) if the SDU is longer than the [MTU of the
+    ///   peer](CreditBasedChannel::remote_mtu).
+    pub fn segment<'a>(&self, sdu: &'a [u8]) -> Result<Segmenter<'a>, Error> {
+        if !self.is_open() {
+            return Err(Error::ChannelNotOpen(self.local_cid));
+        }
+
+        if sdu.len() > self.remote_mtu as usize {
+            return Err(Error::MtuExceeded(sdu.len(), self.remote_mtu));
+        }
+
+        Ok(Segmenter {
+            local_cid: self.local_cid,
+            sdu,
+            offset: 0,
+            started: false,
+        })
+    }
+
+    /// Accepts the information payload of one K-frame received on this channel and copies it into
+    /// `sdu`. Returns the length of the SDU once its last K-frame has been received.
+    ///
+    /// Each K-frame consumes one of the credits granted to the peer. Use
+    /// [`LeSignalingChannel::grant_credits`] to replenish them.
+    ///
+    /// # Errors
+    ///
+    /// - [`ChannelNotOpen`](Error::ChannelNotOpen) if the channel is not open.
+    /// - [`CreditUnderflow`](Error::CreditUnderflow) if the peer had no credits left.
+    /// - [`MpsExceeded`](Error::MpsExceeded) if the payload is longer than the local MPS.
+    /// - [`MtuExceeded`](Error::MtuExceeded) if the announced SDU length is longer than the local
+    ///   MTU.
+    /// - [`BadSduLength`](Error::BadSduLength) if the K-frames are longer than the announced SDU
+    ///   length. The partial SDU is discarded.
+    /// - [`BufferTooSmall`](Error::BufferTooSmall) if `sdu` cannot hold the SDU.
+    pub fn receive(&mut self, payload: &[u8], sdu: &mut [u8]) -> Result<Option<usize>, Error> {
+        if !self.is_open() {
+            return Err(Error::ChannelNotOpen(self.local_cid));
+        }
+
+        if self.rx_credits == 0 {
+            return Err(Error::CreditUnderflow(self.local_cid));
+        }
+        self.rx_credits -= 1;
+
+        if payload.len() > self.local.mps as usize {
+            self.rx_sdu_len = None;
+            return Err(Error::MpsExceeded(payload.len(), self.local.mps));
+        }
+
+        let data = match self.rx_sdu_len {
+            Some(_) => payload,
+            None => {
+                if payload.len() < SDU_LENGTH_FIELD {
+                    return Err(Error::BadSduLength(self.local_cid));
+                }
+
+                let sdu_len = LittleEndian::read_u16(&payload[0..2]) as usize;
+                if sdu_len > self.local.mtu as usize {
+                    return Err(Error::MtuExceeded(sdu_len, self.local.mtu));
+                }
+                if sdu_len > sdu.len() {
+                    return Err(Error::BufferTooSmall(sdu_len));
+                }
+
+                self.rx_sdu_len = Some(sdu_len);
+                self.rx_received = 0;
+                &payload[SDU_LENGTH_FIELD..]
+            }
+        };
+
+        let sdu_len = self.rx_sdu_len.unwrap();
+        let end = self.rx_received + data.len();
+        if end > sdu_len {
+            self.rx_sdu_len = None;
+            return Err(Error::BadSduLength(self.local_cid));
+        }
+        if end > sdu.len() {
+            self.rx_sdu_len = None;
+            return Err(Error::BufferTooSmall(sdu_len));
+        }
+
+        sdu[self.rx_received..end].copy_from_slice(data);
+        self.rx_received = end;
+        if end == sdu_len {
+            self.rx_sdu_len = None;
+            return Ok(Some(sdu_len));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Splits an SDU into K-frames for one [`CreditBasedChannel`].
+///
+/// The first K-frame starts with the 2-byte SDU length. No K-frame payload is longer than the MPS of
+/// the peer, and each K-frame consumes one credit.
+pub struct Segmenter<'a> {
+    local_cid: u16,
+    sdu: &'a [u8],
+    offset: usize,
+    started: bool,
+}
+
+impl<'a> Segmenter<'a> {
+    /// Returns true if all K-frames of the SDU have been produced.
+    pub fn is_done(&self) -> bool {
+        self.started && self.offset == self.sdu.len()
+    }
+
+    /// Writes the next K-frame, including its basic L2CAP header, into `bytes`. Returns the length of
+    /// the frame, or `None` if the whole SDU has already been sent.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownChannel`](Error::UnknownChannel) if `channel` is not the channel the segmenter was
+    ///   created for.
+    /// - [`NoCredits`](Error::NoCredits) if the peer has not granted a credit for the next K-frame.
+    ///   Retry after the next [flow control credit](SignalingEvent::CreditsReceived).
+    /// - [`BufferTooSmall`](Error::BufferTooSmall) if `bytes` cannot hold the K-frame.
+    pub fn next_frame(
+        &mut self,
+        channel: &mut CreditBasedChannel,
+        bytes: &mut [u8],
+    ) -> Result<Option<usize>, Error> {
+        if channel.local_cid != self.local_cid {
+            return Err(Error::UnknownChannel(self.local_cid));
+        }
+
+        if self.is_done() {
+            return Ok(None);
+        }
+
+        if channel.tx_credits == 0 {
+            return Err(Error::NoCredits);
+        }
+
+        let mps = channel.remote_mps as usize;
+        let prefix = if self.started { 0 } else { SDU_LENGTH_FIELD };
+        let data_len = (self.sdu.len() - self.offset).min(mps - prefix);
+        let frame_len = BASIC_HEADER_LENGTH + prefix + data_len;
+        if bytes.len() < frame_len {
+            return Err(Error::BufferTooSmall(frame_len));
+        }
+
+        write_basic_header(bytes, channel.remote_cid, prefix + data_len);
+        if !self.started {
+            LittleEndian::write_u16(
+                &mut bytes[BASIC_HEADER_LENGTH..BASIC_HEADER_LENGTH + SDU_LENGTH_FIELD],
+                self.sdu.len() as u16,
+            );
+        }
+        bytes[BASIC_HEADER_LENGTH + prefix..frame_len]
+            .copy_from_slice(&self.sdu[self.offset..self.offset + data_len]);
+
+        channel.tx_credits -= 1;
+        self.offset += data_len;
+        self.started = true;
+
+        Ok(Some(frame_len))
+    }
+}
+
+/// Decisions the application makes about requests from the peer.
+///
+/// The default implementation accepts all valid connection parameters and refuses all LE
+/// credit-based connections.
+pub trait SignalingPolicy {
+    /// Returns true if the requested connection parameters are acceptable. Only called for
+    /// parameters that pass the checks of the
+    /// [`ConnectionIntervalBuilder`](crate::types::ConnectionIntervalBuilder).
+    ///
+    /// Accepting the request only answers the peer; the central must still apply the parameters
+    /// with the [LE Connection Update](crate::host::HostHci::le_connection_update) command.
+    fn accept_connection_parameters(&mut self, _conn_interval: &ConnectionInterval) -> bool {
+        true
+    }
+
+    /// Returns [`Success`](LeCreditBasedConnectionResult::Success) if the peer may open an LE
+    /// credit-based channel to the given SPSM, or the reason for refusing it.
+    fn accept_le_credit_based_connection(&mut self, _spsm: u16) -> LeCreditBasedConnectionResult {
+        LeCreditBasedConnectionResult::SpsmNotSupported
+    }
+}
+
+/// Events reported to the application after a signaling command was processed.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SignalingEvent {
+    /// The peer requested new connection parameters, and the policy accepted or rejected them.
+    ConnectionParameterUpdateRequest {
+        /// Requested connection parameters.
+        conn_interval: ConnectionInterval,
+        /// True if the request was accepted.
+        accepted: bool,
+    },
+
+    /// The peer answered a local [connection parameter update
+    /// request](LeSignalingChannel::connection_parameter_update_request).
+    ConnectionParameterUpdateResponse {
+        /// True if the peer accepted the parameters.
+        accepted: bool,
+    },
+
+    /// The peer rejected a local command.
+    CommandRejected {
+        /// Identifier of the rejected command.
+        identifier: u8,
+        /// Why the command was rejected.
+        reason: RejectReason,
+    },
+
+    /// An LE credit-based channel is open.
+    ChannelConnected {
+        /// Local CID of the channel.
+        local_cid: u16,
+    },
+
+    /// The peer refused a local [connection request](LeSignalingChannel::connect).
+    ChannelRefused {
+        /// Local CID that had been allocated for the channel.
+        local_cid: u16,
+        /// Why the channel was refused.
+        result: LeCreditBasedConnectionResult,
+    },
+
+    /// An LE credit-based channel was closed.
+    ChannelDisconnected {
+        /// Local CID of the channel.
+        local_cid: u16,
+    },
+
+    /// The peer granted additional credits.
+    CreditsReceived {
+        /// Local CID of the channel.
+        local_cid: u16,
+        /// Number of additional credits.
+        credits: u16,
+    },
+}
+
+/// Result of [processing](LeSignalingChannel::process) one signaling command.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response {
+    /// Command to send back to the peer, if any.
+    pub reply: Option<SignalingCommand>,
+    /// Event to report to the application, if any.
+    pub event: Option<SignalingEvent>,
+}
+
+impl Response {
+    fn none() -> Self {
+        Response {
+            reply: None,
+            event: None,
+        }
+    }
+
+    fn reply(reply: SignalingCommand) -> Self {
+        Response {
+            reply: Some(reply),
+            event: None,
+        }
+    }
+
+    fn event(event: SignalingEvent) -> Self {
+        Response {
+            reply: None,
+            event: Some(event),
+        }
+    }
+}
+
+/// State of the LE signaling channel of one connection.
+///
+/// Holds up to `N` LE credit-based channels.
+pub struct LeSignalingChannel<const N: usize> {
+    local: ChannelParameters,
+    next_identifier: u8,
+    pending_update: Option<u8>,
+    channels: [Option<CreditBasedChannel>; N],
+}
+
+impl<const N: usize> LeSignalingChannel<N> {
+    /// Creates the signaling state for a new connection. `local` defines how much data the local
+    /// side accepts on each LE credit-based channel.
+    ///
+    /// # Errors
+    ///
+    /// - [`BadMtu`](Error::BadMtu) or [`BadMps`](Error::BadMps) if the local parameters are out of
+    ///   range.
+    pub fn new(local: ChannelParameters) -> Result<Self, Error> {
+        local.validate()?;
+
+        Ok(LeSignalingChannel {
+            local,
+            next_identifier: 1,
+            pending_update: None,
+            channels: [None; N],
+        })
+    }
+
+    /// Returns the channel with the given local CID.
+    pub fn channel(&self, local_cid: u16) -> Option<&CreditBasedChannel> {
+        self.channels
+            .iter()
+            .flatten()
+            .find(|c| c.local_cid == local_cid)
+    }
+
+    /// Returns the channel with the given local CID.
+    pub fn channel_mut(&mut self, local_cid: u16) -> Option<&mut CreditBasedChannel> {
+        self.channels
+            .iter_mut()
+            .flatten()
+            .find(|c| c.local_cid == local_cid)
+    }
+
+    /// Builds a connection parameter update request to send to the central. The response is
+    /// reported as [`ConnectionParameterUpdateResponse`](SignalingEvent::ConnectionParameterUpdateResponse).
+    pub fn connection_parameter_update_request(
+        &mut self,
+        conn_interval: ConnectionInterval,
+    ) -> SignalingCommand {
+        let identifier = self.allocate_identifier();
+        self.pending_update = Some(identifier);
+
+        SignalingCommand::ConnectionParameterUpdateRequest {
+            identifier,
+            conn_interval,
+        }
+    }
+
+    /// Allocates a channel and builds the request to connect it to the SPSM on the peer. The
+    /// returned command contains the local CID as its `source_cid`. The outcome is reported as
+    /// [`ChannelConnected`](SignalingEvent::ChannelConnected) or
+    /// [`ChannelRefused`](SignalingEvent::ChannelRefused).
+    ///
+    /// # Errors
+    ///
+    /// - [`NoFreeChannel`](Error::NoFreeChannel) if all channel slots are in use.
+    pub fn connect(&mut self, spsm: u16) -> Result<SignalingCommand, Error> {
+        let identifier = self.allocate_identifier();
+        let local_cid = self.insert_channel(ChannelState::Connecting(identifier), spsm)?;
+
+        Ok(SignalingCommand::LeCreditBasedConnectionRequest {
+            identifier,
+            spsm,
+            source_cid: local_cid,
+            mtu: self.local.mtu,
+            mps: self.local.mps,
+            initial_credits: self.local.initial_credits,
+        })
+    }
+
+    /// Builds the request to close an open channel. The channel is released once the peer
+    /// responds.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownChannel`](Error::UnknownChannel) if there is no channel with the local CID.
+    pub fn disconnect(&mut self, local_cid: u16) -> Result<SignalingCommand, Error> {
+        let identifier = self.allocate_identifier();
+        let channel = self
+            .channel_mut(local_cid)
+            .ok_or(Error::UnknownChannel(local_cid))?;
+        channel.state = ChannelState::Disconnecting(identifier);
+
+        Ok(SignalingCommand::DisconnectionRequest {
+            identifier,
+            destination_cid: channel.remote_cid,
+            source_cid: local_cid,
+        })
+    }
+
+    /// Grants the peer additional credits to send K-frames, and builds the command that informs
+    /// it.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownChannel`](Error::UnknownChannel) if there is no channel with the local CID.
+    /// - [`CreditOverflow`](Error::CreditOverflow) if the peer would have more than 65535 credits.
+    pub fn grant_credits(
+        &mut self,
+        local_cid: u16,
+        credits: u16,
+    ) -> Result<SignalingCommand, Error> {
+        let identifier = self.allocate_identifier();
+        let channel = self
+            .channel_mut(local_cid)
+            .ok_or(Error::UnknownChannel(local_cid))?;
+        channel.rx_credits = channel
+            .rx_credits
+            .checked_add(credits)
+            .ok_or(Error::CreditOverflow(local_cid))?;
+
+        Ok(SignalingCommand::FlowControlCredit {
+            identifier,
+            cid: local_cid,
+            credits,
+        })
+    }
+
+    /// Processes one signaling command received from the peer. `command` is the information
+    /// payload of a C-frame on the [LE signaling channel](LE_SIGNALING_CID).
+    ///
+    /// Requests are answered according to `policy`. Commands that cannot be decoded are answered
+    /// with a [command reject](SignalingCommand::CommandReject).
+    ///
+    /// # Errors
+    ///
+    /// - [`BadLength`](Error::BadLength) if the command is too short to contain a command header.
+    /// - [`CreditOverflow`](Error::CreditOverflow) if the peer granted more than 65535 credits. The
+    ///   channel should be disconnected.
+    pub fn process<P: SignalingPolicy>(
+        &mut self,
+        command: &[u8],
+        policy: &mut P,
+    ) -> Result<Response, Error> {
+        if command.len() < COMMAND_HEADER_LENGTH {
+            return Err(Error::BadLength(command.len()));
+        }
+
+        let identifier = command[1];
+        let command = match SignalingCommand::from_bytes(command) {
+            Ok(command) => command,
+            Err(Error::BadConnectionInterval(_)) => {
+                return Ok(Response::reply(
+                    SignalingCommand::ConnectionParameterUpdateResponse {
+                        identifier,
+                        accepted: false,
+                    },
+                ));
+            }
+            Err(_) => {
+                return Ok(Response::reply(SignalingCommand::CommandReject {
+                    identifier,
+                    reason: RejectReason::CommandNotUnderstood,
+                }));
+            }
+        };
+
+        match command {
+            SignalingCommand::CommandReject { identifier, reason } => {
+                Ok(self.handle_command_reject(identifier, reason))
+            }
+            SignalingCommand::ConnectionParameterUpdateRequest {
+                identifier,
+                conn_interval,
+            } => {
+                let accepted = policy.accept_connection_parameters(&conn_interval);
+                Ok(Response {
+                    reply: Some(SignalingCommand::ConnectionParameterUpdateResponse {
+                        identifier,
+                        accepted,
+                    }),
+                    event: Some(SignalingEvent::ConnectionParameterUpdateRequest {
+                        conn_interval,
+                        accepted,
+                    }),
+                })
+            }
+            SignalingCommand::ConnectionParameterUpdateResponse {
+                identifier,
+                accepted,
+            } => {
+                if self.pending_update != Some(identifier) {
+                    return Ok(Response::none());
+                }
+
+                self.pending_update = None;
+                Ok(Response::event(
+                    SignalingEvent::ConnectionParameterUpdateResponse { accepted },
+                ))
+            }
+            SignalingCommand::LeCreditBasedConnectionRequest {
+                identifier,
+                spsm,
+                source_cid,
+                mtu,
+                mps,
+                initial_credits,
+            } => Ok(self.handle_connection_request(
+                policy,
+                identifier,
+                spsm,
+                source_cid,
+                mtu,
+                mps,
+                initial_credits,
+            )),
+            SignalingCommand::LeCreditBasedConnectionResponse {
+                identifier,
+                destination_cid,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+            } => Ok(self.handle_connection_response(
+                identifier,
+                destination_cid,
+                mtu,
+                mps,
+                initial_credits,
+                result,
+            )),
+            SignalingCommand::FlowControlCredit { cid, credits, .. } => {
+                let channel = match self
+                    .channels
+                    .iter_mut()
+                    .flatten()
+                    .find(|c| c.is_open() && c.remote_cid == cid)
+                {
+                    Some(channel) => channel,
+                    None => return Ok(Response::none()),
+                };
+                channel.tx_credits = channel
+                    .tx_credits
+                    .checked_add(credits)
+                    .ok_or(Error::CreditOverflow(channel.local_cid))?;
+
+                Ok(Response::event(SignalingEvent::CreditsReceived {
+                    local_cid: channel.local_cid,
+                    credits,
+                }))
+            }
+            SignalingCommand::DisconnectionRequest {
+                identifier,
+                destination_cid,
+                source_cid,
+            } => {
+                let slot = self.channels.iter_mut().find(|c| {
+                    c.is_some_and(|c| c.local_cid == destination_cid && c.remote_cid == source_cid)
+                });
+                match slot {
+                    Some(slot) => {
+                        *slot = None;
+                        Ok(Response {
+                            reply: Some(SignalingCommand::DisconnectionResponse {
+                                identifier,
+                                destination_cid,
+                                source_cid,
+                            }),
+                            event: Some(SignalingEvent::ChannelDisconnected {
+                                local_cid: destination_cid,
+                            }),
+                        })
+                    }
+                    None => Ok(Response::reply(SignalingCommand::CommandReject {
+                        identifier,
+                        reason: RejectReason::InvalidCid {
+                            local: destination_cid,
+                            remote: source_cid,
+                        },
+                    })),
+                }
+            }
+            SignalingCommand::DisconnectionResponse {
+                identifier,
+                source_cid,
+                ..
+            } => {
+                let slot = self.channels.iter_mut().find(|c| {
+                    c.is_some_and(|c| {
+                        c.local_cid == source_cid
+                            && c.state == ChannelState::Disconnecting(identifier)
+                    })
+                });
+                match slot {
+                    Some(slot) => {
+                        *slot = None;
+                        Ok(Response::event(SignalingEvent::ChannelDisconnected {
+                            local_cid: source_cid,
+                        }))
+                    }
+                    None => Ok(Response::none()),
+                }
+            }
+        }
+    }
+
+    fn handle_command_reject(&mut self, identifier: u8, reason: RejectReason) -> Response {
+        if self.pending_update == Some(identifier) {
+            self.pending_update = None;
+        }
+
+        for slot in self.channels.iter_mut() {
+            if let Some(channel) = slot {
+                match channel.state {
+                    ChannelState::Connecting(id) if id == identifier => *slot = None,
+                    ChannelState::Disconnecting(id) if id == identifier => *slot = None,
+                    _ => (),
+                }
+            }
+        }
+
+        Response::event(SignalingEvent::CommandRejected { identifier, reason })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_connection_request<P: SignalingPolicy>(
+        &mut self,
+        policy: &mut P,
+        identifier: u8,
+        spsm: u16,
+        source_cid: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+    ) -> Response {
+        let refuse = |result| {
+            Response::reply(SignalingCommand::LeCreditBasedConnectionResponse {
+                identifier,
+                destination_cid: 0,
+                mtu: 0,
+                mps: 0,
+                initial_credits: 0,
+                result,
+            })
+        };
+
+        if !(FIRST_DYNAMIC_CID..=LAST_DYNAMIC_CID).contains(&source_cid) {
+            return refuse(LeCreditBasedConnectionResult::InvalidSourceCid);
+        }
+        if self
+            .channels
+            .iter()
+            .flatten()
+            .any(|c| c.remote_cid == source_cid)
+        {
+            return refuse(LeCreditBasedConnectionResult::SourceCidAlreadyAllocated);
+        }
+        if validate_mtu_mps(mtu, mps).is_err() {
+            return refuse(LeCreditBasedConnectionResult::UnacceptableParameters);
+        }
+
+        let result = policy.accept_le_credit_based_connection(spsm);
+        if result != LeCreditBasedConnectionResult::Success {
+            return refuse(result);
+        }
+
+        let local_cid = match self.insert_channel(ChannelState::Open, spsm) {
+            Ok(local_cid) => local_cid,
+            Err(_) => return refuse(LeCreditBasedConnectionResult::NoResources),
+        };
+        let local = self.local;
+        let channel = self.channel_mut(local_cid).unwrap();
+        channel.remote_cid = source_cid;
+        channel.remote_mtu = mtu;
+        channel.remote_mps = mps;
+        channel.tx_credits = initial_credits;
+
+        Response {
+            reply: Some(SignalingCommand::LeCreditBasedConnectionResponse {
+                identifier,
+                destination_cid: local_cid,
+                mtu: local.mtu,
+                mps: local.mps,
+                initial_credits: local.initial_credits,
+                result: LeCreditBasedConnectionResult::Success,
+            }),
+            event: Some(SignalingEvent::ChannelConnected { local_cid }),
+        }
+    }
+
+    fn handle_connection_response(
+        &mut self,
+        identifier: u8,
+        destination_cid: u16,
+        mtu: u16,
+        mps: u16,
+        initial_credits: u16,
+        result: LeCreditBasedConnectionResult,
+    ) -> Response {
+        let slot = match self
+            .channels
+            .iter_mut()
+            .find(|c| c.is_some_and(|c| c.state == ChannelState::Connecting(identifier)))
+        {
+            Some(slot) => slot,
+            None => return Response::none(),
+        };
+        let local_cid = slot.unwrap().local_cid;
+
+        if result != LeCreditBasedConnectionResult::Success {
+            *slot = None;
+            return Response::event(SignalingEvent::ChannelRefused { local_cid, result });
+        }
+
+        if validate_mtu_mps(mtu, mps).is_err()
+            || !(FIRST_DYNAMIC_CID..=LAST_DYNAMIC_CID).contains(&destination_cid)
+        {
+            *slot = None;
+            return Response::event(SignalingEvent::ChannelRefused {
+                local_cid,
+                result: LeCreditBasedConnectionResult::UnacceptableParameters,
+            });
+        }
+
+        let channel = slot.as_mut().unwrap();
+        channel.state = ChannelState::Open;
+        channel.remote_cid = destination_cid;
+        channel.remote_mtu = mtu;
+        channel.remote_mps = mps;
+        channel.tx_credits = initial_credits;
+
+        Response::event(SignalingEvent::ChannelConnected { local_cid })
+    }
+
+    fn allocate_identifier(&mut self) -> u8 {
+        let identifier = self.next_identifier;
+        self.next_identifier = match self.next_identifier.wrapping_add(1) {
+            0 => 1,
+            next => next,
+        };
+
+        identifier
+    }
+
+    fn insert_channel(&mut self, state: ChannelState, spsm: u16) -> Result<u16, Error> {
+        let local_cid = (FIRST_DYNAMIC_CID..=LAST_DYNAMIC_CID)
+            .find(|&cid| self.channel(cid).is_none())
+            .ok_or(Error::NoFreeChannel)?;
+        let slot = self
+            .channels
+            .iter_mut()
+            .find(|c| c.is_none())
+            .ok_or(Error::NoFreeChannel)?;
+
+        *slot = Some(CreditBasedChannel {
+            state,
+            spsm,
+            local_cid,
+            remote_cid: 0,
+            local: self.local,
+            remote_mtu: 0,
+            remote_mps: 0,
+            tx_credits: 0,
+            rx_credits: self.local.initial_credits,
+            rx_sdu_len: None,
+            rx_received: 0,
+        });
+
+        Ok(local_cid)
+    }
+}
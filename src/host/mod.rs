@@ -14,6 +14,11 @@ use core::convert::Into;
 use core::fmt::{Debug, Formatter, Result as FmtResult};
 use core::time::Duration;
 
+pub mod btsnoop;
+#[cfg(feature = "embedded-io-async")]
+pub mod embedded_io;
+pub mod ipcc;
+pub mod l2cap;
 pub mod uart;
 
 pub use super::types::{
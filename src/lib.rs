@@ -77,7 +77,11 @@
 #![no_std]
 #![feature(async_fn_in_trait)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 extern crate byteorder;
+#[cfg(feature = "mock")]
+extern crate std;
 
 // This must go FIRST so that all the other modules see its macros.
 mod fmt;
@@ -87,6 +91,8 @@ pub mod bitflag_array;
 
 pub mod event;
 pub mod host;
+#[cfg(feature = "mock")]
+pub mod mock;
 mod opcode;
 pub mod types;
 pub mod vendor;
@@ -0,0 +1,392 @@
+//! Scriptable [`Controller`] for testing applications without a board.
+//!
+//! Only available with the `mock` feature, which links `std`.
+//!
+//! A [`MockController`] plays back a script: each step expects one command to be written, matched
+//! by opcode and (optionally) parameters, and then queues the events the controller would send in
+//! response. Events are returned from [`controller_read_into`](Controller::controller_read_into)
+//! in order, framed as UART packets so they can be read back with
+//! [`UartHci::read`](crate::host::uart::UartHci::read).
+//!
+//! ```
+//! # #![feature(async_fn_in_trait)]
+//! use stm32wb_hci::host::uart::UartHci;
+//! use stm32wb_hci::mock::{self, MockController};
+//! use stm32wb_hci::vendor::stm32wb::command::gap::{GapCommands, Role};
+//! use stm32wb_hci::vendor::stm32wb::opcode;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut controller = MockController::new();
+//! controller
+//!     .expect(opcode::GAP_INIT, &[0x01, 0x00, 0x07])
+//!     .respond(mock::gap_init_complete(0x0001, 0x0002, 0x0003));
+//!
+//! controller.init(Role::PERIPHERAL, false, 7).await;
+//! let packet = controller.read().await.unwrap();
+//! # }
+//! ```
+//!
+//! Any command that does not match the script panics with a description of the difference.
+//! Dropping the controller with unmet expectations also panics, unless the thread is already
+//! panicking.
+//!
+//! For tests that need a controller with state rather than a script, see
+//! [`simulator::Simulator`].
+
+use crate::event::command::ReturnParameters;
+use crate::event::{ConnectionRole, Event};
+use crate::host::uart::{Packet, UartHci};
+use crate::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use crate::vendor::stm32wb::event::{Status as VendorStatus, Stm32Wb5xEvent};
+use crate::{BdAddrType, ConnectionHandle, Controller, Opcode, Status};
+use byteorder::{ByteOrder, LittleEndian};
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::string::String;
+use std::vec::Vec;
+
+pub mod simulator;
+
+const PACKET_TYPE_HCI_EVENT: u8 = 0x04;
+
+struct Expectation {
+    opcode: Opcode,
+    params: Option<Vec<u8>>,
+    responses: Vec<Vec<u8>>,
+}
+
+/// Scripted [`Controller`].
+///
+/// Build the script with [`expect`](MockController::expect),
+/// [`expect_opcode`](MockController::expect_opcode) and
+/// [`respond`](MockController::respond), then pass the controller to the code under test.
+#[derive(Default)]
+pub struct MockController {
+    script: VecDeque<Expectation>,
+    events: RefCell<VecDeque<Vec<u8>>>,
+    written: usize,
+}
+
+impl MockController {
+    /// Creates a controller with an empty script.
+    pub fn new() -> MockController {
+        MockController::default()
+    }
+
+    /// Appends a step that expects a command with the given opcode and exactly the given
+    /// parameters.
+    pub fn expect(&mut self, opcode: Opcode, params: &[u8]) -> &mut MockController {
+        self.script.push_back(Expectation {
+            opcode,
+            params: Some(params.to_vec()),
+            responses: Vec::new(),
+        });
+        self
+    }
+
+    /// Appends a step that expects a command with the given opcode and any parameters.
+    pub fn expect_opcode(&mut self, opcode: Opcode) -> &mut MockController {
+        self.script.push_back(Expectation {
+            opcode,
+            params: None,
+            responses: Vec::new(),
+        });
+        self
+    }
+
+    /// Queues an event to be read after the command of the last step has been written. If there is
+    /// no step yet, the event can be read immediately.
+    ///
+    /// `event` is the event packet without the packet type byte: event code, parameter length and
+    /// parameters. The helper functions of this module build common events.
+    pub fn respond(&mut self, event: Vec<u8>) -> &mut MockController {
+        match self.script.back_mut() {
+            Some(expectation) => expectation.responses.push(event),
+            None => self.events.get_mut().push_back(event),
+        }
+        self
+    }
+
+    /// Queues an event that can be read immediately, regardless of the script. Use this for events
+    /// the controller generates on its own, such as a connection from a peer.
+    pub fn push_event(&mut self, event: Vec<u8>) -> &mut MockController {
+        self.events.get_mut().push_back(event);
+        self
+    }
+
+    /// Returns the number of commands written so far.
+    pub fn commands_written(&self) -> usize {
+        self.written
+    }
+
+    /// Returns the number of queued events that have not been read yet.
+    pub fn pending_events(&self) -> usize {
+        self.events.borrow().len()
+    }
+
+    /// Returns true if every expected command has been written.
+    pub fn is_done(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    /// Panics if any expected command has not been written, listing the missing commands.
+    pub fn verify(&self) {
+        if self.script.is_empty() {
+            return;
+        }
+
+        let mut message = String::new();
+        let _ = writeln!(
+            message,
+            "{} expected command(s) not written after {} command(s):",
+            self.script.len(),
+            self.written
+        );
+        for expectation in self.script.iter() {
+            let _ = writeln!(
+                message,
+                "  {}",
+                describe(expectation.opcode, &expectation.params)
+            );
+        }
+        core::panic!("{}", message);
+    }
+}
+
+impl Drop for MockController {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+impl Controller for MockController {
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        let index = self.written;
+        self.written += 1;
+
+        let expectation = match self.script.pop_front() {
+            Some(expectation) => expectation,
+            None => core::panic!(
+                "unexpected command #{}: {}\n  the script has no more expected commands",
+                index,
+                describe(opcode, &Some(payload.to_vec()))
+            ),
+        };
+
+        let opcode_matches = expectation.opcode == opcode;
+        let params_match = expectation
+            .params
+            .as_ref()
+            .is_none_or(|params| params[..] == payload[..]);
+        if !opcode_matches || !params_match {
+            let mut message = String::new();
+            let _ = writeln!(message, "command #{} does not match the script", index);
+            let _ = writeln!(
+                message,
+                "  expected: {}",
+                describe(expectation.opcode, &expectation.params)
+            );
+            let _ = writeln!(
+                message,
+                "  actual:   {}",
+                describe(opcode, &Some(payload.to_vec()))
+            );
+            if let (true, Some(params)) = (opcode_matches, expectation.params.as_ref()) {
+                match params.iter().zip(payload.iter()).position(|(e, a)| e != a) {
+                    Some(i) => {
+                        let _ = writeln!(
+                            message,
+                            "  first difference at byte {}: expected {:#04x}, got {:#04x}",
+                            i, params[i], payload[i]
+                        );
+                    }
+                    None => {
+                        let _ = writeln!(
+                            message,
+                            "  expected {} parameter byte(s), got {}",
+                            params.len(),
+                            payload.len()
+                        );
+                    }
+                }
+            }
+            core::panic!("{}", message);
+        }
+
+        self.events.get_mut().extend(expectation.responses);
+    }
+
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        let event = match self.events.borrow_mut().pop_front() {
+            Some(event) => event,
+            None => core::panic!(
+                "read from the controller after {} command(s), but no event is queued",
+                self.written
+            ),
+        };
+
+        core::assert!(
+            buf.len() > event.len(),
+            "read buffer of {} bytes is too small for an event of {} bytes",
+            buf.len(),
+            event.len() + 1
+        );
+        buf[0] = PACKET_TYPE_HCI_EVENT;
+        buf[1..=event.len()].copy_from_slice(&event);
+    }
+}
+
+fn describe(opcode: Opcode, params: &Option<Vec<u8>>) -> String {
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        "opcode {:#06x} (OGF {:#04x}, OCF {:#05x})",
+        opcode.0,
+        opcode.ogf(),
+        opcode.ocf()
+    );
+    match params {
+        Some(params) => {
+            let _ = write!(s, ", params [");
+            for (i, b) in params.iter().enumerate() {
+                if i > 0 {
+                    s.push(' ');
+                }
+                let _ = write!(s, "{:02x}", b);
+            }
+            s.push(']');
+        }
+        None => s.push_str(", any params"),
+    }
+
+    s
+}
+
+/// Builds an event packet from the event code and its parameters.
+pub fn event(code: u8, params: &[u8]) -> Vec<u8> {
+    core::assert!(params.len() <= 255);
+
+    let mut event = Vec::with_capacity(2 + params.len());
+    event.push(code);
+    event.push(params.len() as u8);
+    event.extend_from_slice(params);
+    event
+}
+
+/// Builds a vendor-specific event from the 2-byte vendor event code and its parameters.
+pub fn vendor_event(code: u16, params: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(2 + params.len());
+    bytes.extend_from_slice(&code.to_le_bytes());
+    bytes.extend_from_slice(params);
+    event(0xFF, &bytes)
+}
+
+/// Builds a Command Complete event for the opcode with the given return parameters. The return
+/// parameters normally start with the status byte.
+pub fn command_complete(opcode: Opcode, return_params: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(3 + return_params.len());
+    bytes.push(1);
+    bytes.extend_from_slice(&opcode.0.to_le_bytes());
+    bytes.extend_from_slice(return_params);
+    event(0x0E, &bytes)
+}
+
+/// Builds a Command Complete event for the opcode whose only return parameter is the status.
+pub fn command_complete_status(opcode: Opcode, status: Status<VendorStatus>) -> Vec<u8> {
+    command_complete(opcode, &[status.into()])
+}
+
+/// Builds a Command Status event for the opcode.
+pub fn command_status(opcode: Opcode, status: Status<VendorStatus>) -> Vec<u8> {
+    let mut bytes = [0; 4];
+    bytes[0] = status.into();
+    bytes[1] = 1;
+    LittleEndian::write_u16(&mut bytes[2..4], opcode.0);
+    event(0x0F, &bytes)
+}
+
+/// Builds a successful Command Complete event for the [GAP Init](crate::vendor::stm32wb::command::gap::GapCommands::init)
+/// command.
+pub fn gap_init_complete(
+    service_handle: u16,
+    dev_name_handle: u16,
+    appearance_handle: u16,
+) -> Vec<u8> {
+    let mut bytes = [0; 7];
+    LittleEndian::write_u16(&mut bytes[1..3], service_handle);
+    LittleEndian::write_u16(&mut bytes[3..5], dev_name_handle);
+    LittleEndian::write_u16(&mut bytes[5..7], appearance_handle);
+    command_complete(crate::vendor::stm32wb::opcode::GAP_INIT, &bytes)
+}
+
+/// Builds a successful LE Connection Complete event. The interval is rounded down to a multiple of
+/// 1.25 ms, and the supervision timeout to a multiple of 10 ms.
+pub fn le_connection_complete(
+    conn_handle: ConnectionHandle,
+    role: ConnectionRole,
+    peer_bd_addr: BdAddrType,
+    interval: Duration,
+    conn_latency: u16,
+    supervision_timeout: Duration,
+) -> Vec<u8> {
+    let mut bytes = [0; 19];
+    bytes[0] = 0x01;
+    bytes[1] = Status::<VendorStatus>::Success.into();
+    LittleEndian::write_u16(&mut bytes[2..4], conn_handle.0);
+    bytes[4] = match role {
+        ConnectionRole::Central => 0,
+        ConnectionRole::Peripheral => 1,
+    };
+    peer_bd_addr.copy_into_slice(&mut bytes[5..12]);
+    LittleEndian::write_u16(&mut bytes[12..14], (interval.as_micros() / 1_250) as u16);
+    LittleEndian::write_u16(&mut bytes[14..16], conn_latency);
+    LittleEndian::write_u16(
+        &mut bytes[16..18],
+        (supervision_timeout.as_millis() / 10) as u16,
+    );
+    // Central clock accuracy: 500 ppm
+    bytes[18] = 0;
+    event(0x3E, &bytes)
+}
+
+/// Builds a successful Disconnection Complete event.
+pub fn disconnection_complete(
+    conn_handle: ConnectionHandle,
+    reason: Status<VendorStatus>,
+) -> Vec<u8> {
+    let mut bytes = [0; 4];
+    LittleEndian::write_u16(&mut bytes[1..3], conn_handle.0);
+    bytes[3] = reason.into();
+    event(0x05, &bytes)
+}
+
+/// Reads the next packet from the controller, which must be a Command Complete event for a
+/// vendor-specific command, and returns its return parameters.
+///
+/// # Panics
+///
+/// Panics with a description of the packet if it is anything else.
+pub async fn read_vendor_return<C: Controller>(controller: &mut C) -> VendorReturnParameters {
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(cc)) => match cc.return_params {
+            ReturnParameters::Vendor(params) => params,
+            other => core::panic!("unexpected return parameters {:?}", other),
+        },
+        other => core::panic!("unexpected packet {:?}", other),
+    }
+}
+
+/// Parses an event built with the functions of this module, for code that takes events rather
+/// than a controller.
+///
+/// # Panics
+///
+/// Panics if the event cannot be parsed.
+pub fn parse_event(bytes: &[u8]) -> Event<Stm32Wb5xEvent> {
+    Event::new(crate::event::Packet(bytes)).unwrap()
+}
@@ -0,0 +1,907 @@
+//! Behavioral model of the STM32WB BLE controller.
+//!
+//! Where a [`MockController`](super::MockController) only plays back a script, the [`Simulator`]
+//! keeps the state the wireless coprocessor would keep: the GATT database built with
+//! [`add_service`](crate::vendor::stm32wb::command::gatt::GattCommands::add_service) and
+//! [`add_characteristic`](crate::vendor::stm32wb::command::gatt::GattCommands::add_characteristic),
+//! the advertising state, and the open connections. It answers each command with the events the
+//! real stack would emit.
+//!
+//! A virtual peer is driven through [`connect`](Simulator::connect),
+//! [`peer_write`](Simulator::peer_write), [`peer_subscribe`](Simulator::peer_subscribe) and
+//! [`peer_disconnect`](Simulator::peer_disconnect). Notifications and indications sent by the
+//! application are collected per connection and returned by
+//! [`take_peer_notifications`](Simulator::take_peer_notifications). The TX pool that holds them
+//! until they are sent can be limited with [`set_tx_pool_size`](Simulator::set_tx_pool_size).
+//!
+//! Commands the simulator does not model are answered with a [Command
+//! Status](crate::event::Event::CommandStatus) event carrying
+//! [`UnknownCommand`](crate::Status::UnknownCommand).
+
+use super::{
+    command_complete, command_complete_status, command_status, disconnection_complete,
+    le_connection_complete, vendor_event, PACKET_TYPE_HCI_EVENT,
+};
+use crate::event::ConnectionRole;
+use crate::vendor::stm32wb::command::gatt::{CharacteristicProperty, Uuid};
+use crate::vendor::stm32wb::event::{AttributeHandle, Status as VendorStatus};
+use crate::vendor::stm32wb::opcode;
+use crate::{BdAddrType, ConnectionHandle, Controller, Opcode, Status};
+use byteorder::{ByteOrder, LittleEndian};
+use core::cell::RefCell;
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+const CCCD_NOTIFY: u16 = 0x0001;
+const CCCD_INDICATE: u16 = 0x0002;
+const FIRST_CONNECTION_HANDLE: u16 = 0x0801;
+
+/// Kinds of attributes in the simulated GATT database.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeKind {
+    /// Primary or secondary service declaration.
+    Service(Uuid),
+    /// Characteristic declaration. The value attribute directly follows it.
+    CharacteristicDeclaration(CharacteristicProperty),
+    /// Characteristic value.
+    CharacteristicValue(Uuid, CharacteristicProperty),
+    /// Client characteristic configuration descriptor. The value is kept per connection.
+    ClientConfiguration,
+    /// Any other characteristic descriptor.
+    Descriptor(Uuid),
+}
+
+/// One attribute of the simulated GATT database.
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    /// Handle of the attribute.
+    pub handle: AttributeHandle,
+    /// What the attribute declares.
+    pub kind: AttributeKind,
+    /// Current value of the attribute.
+    pub value: Vec<u8>,
+    /// Maximum length of the value.
+    pub max_len: usize,
+    /// True if the length of the value can change.
+    pub is_variable: bool,
+}
+
+/// A notification or indication received by the virtual peer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerNotification {
+    /// Handle of the characteristic value.
+    pub handle: AttributeHandle,
+    /// Value sent to the peer.
+    pub value: Vec<u8>,
+    /// True for an indication, false for a notification.
+    pub indication: bool,
+}
+
+/// Advertising and connection state of the simulated controller.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    /// Neither advertising nor connected.
+    Idle,
+    /// Advertising, so a peer may connect.
+    Advertising,
+    /// At least one peer is connected.
+    Connected,
+}
+
+/// Errors from driving the virtual peer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SimulatorError {
+    /// The peer tried to connect, but the simulated controller is not advertising.
+    NotAdvertising,
+    /// There is no connection with the handle.
+    UnknownConnection(ConnectionHandle),
+    /// There is no attribute with the handle.
+    UnknownHandle(AttributeHandle),
+    /// The attribute cannot be written by the peer.
+    NotWritable(AttributeHandle),
+    /// The value is longer than the maximum length of the attribute.
+    ValueTooLong(AttributeHandle),
+    /// The characteristic has no client characteristic configuration descriptor.
+    NotSubscribable(AttributeHandle),
+}
+
+struct Service {
+    end: u16,
+    next: u16,
+}
+
+struct Update {
+    value_handle: u16,
+    target: Option<ConnectionHandle>,
+    notify: bool,
+    indicate: bool,
+    total_len: Option<usize>,
+}
+
+struct Connection {
+    conn_handle: ConnectionHandle,
+    cccds: Vec<(u16, u16)>,
+    received: Vec<PeerNotification>,
+}
+
+impl Connection {
+    fn cccd(&self, handle: u16) -> u16 {
+        self.cccds
+            .iter()
+            .find(|(h, _)| *h == handle)
+            .map_or(0, |(_, bits)| *bits)
+    }
+
+    fn set_cccd(&mut self, handle: u16, bits: u16) {
+        match self.cccds.iter_mut().find(|(h, _)| *h == handle) {
+            Some(entry) => entry.1 = bits,
+            None => self.cccds.push((handle, bits)),
+        }
+    }
+}
+
+/// Software model of the STM32WB BLE controller.
+pub struct Simulator {
+    attributes: Vec<Attribute>,
+    services: Vec<(u16, Service)>,
+    next_handle: u16,
+    advertising: bool,
+    connections: Vec<Connection>,
+    next_conn_handle: u16,
+    tx_pool_size: usize,
+    tx_pool_used: usize,
+    tx_pool_waiting: Option<ConnectionHandle>,
+    events: RefCell<VecDeque<Vec<u8>>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator::new()
+    }
+}
+
+impl Simulator {
+    /// Creates a simulator in the state after a reset: no GATT database, not advertising, and not
+    /// connected.
+    pub fn new() -> Simulator {
+        Simulator {
+            attributes: Vec::new(),
+            services: Vec::new(),
+            next_handle: 0x0001,
+            advertising: false,
+            connections: Vec::new(),
+            next_conn_handle: FIRST_CONNECTION_HANDLE,
+            tx_pool_size: usize::MAX,
+            tx_pool_used: 0,
+            tx_pool_waiting: None,
+            events: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Returns the advertising and connection state.
+    pub fn state(&self) -> State {
+        if !self.connections.is_empty() {
+            State::Connected
+        } else if self.advertising {
+            State::Advertising
+        } else {
+            State::Idle
+        }
+    }
+
+    /// Returns all attributes of the GATT database, in handle order.
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    /// Returns the attribute with the given handle.
+    pub fn attribute(&self, handle: AttributeHandle) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.handle == handle)
+    }
+
+    /// Returns the number of events that have not been read yet.
+    pub fn pending_events(&self) -> usize {
+        self.events.borrow().len()
+    }
+
+    /// Connects the virtual peer. The simulated controller stops advertising and emits an [LE
+    /// Connection Complete](crate::event::Event::LeConnectionComplete) event.
+    ///
+    /// # Errors
+    ///
+    /// - [`NotAdvertising`](SimulatorError::NotAdvertising) if the controller is not advertising.
+    pub fn connect(&mut self, peer: BdAddrType) -> Result<ConnectionHandle, SimulatorError> {
+        if !self.advertising {
+            return Err(SimulatorError::NotAdvertising);
+        }
+
+        self.advertising = false;
+        let conn_handle = ConnectionHandle(self.next_conn_handle);
+        self.next_conn_handle += 1;
+        self.connections.push(Connection {
+            conn_handle,
+            cccds: Vec::new(),
+            received: Vec::new(),
+        });
+        self.push_event(le_connection_complete(
+            conn_handle,
+            ConnectionRole::Peripheral,
+            peer,
+            Duration::from_millis(50),
+            0,
+            Duration::from_secs(5),
+        ));
+
+        Ok(conn_handle)
+    }
+
+    /// Writes a value from the virtual peer. The simulated controller emits a [GATT Attribute
+    /// Modified](crate::vendor::stm32wb::event::Stm32Wb5xEvent::GattAttributeModified) event.
+    ///
+    /// Writes to a client characteristic configuration descriptor change the subscription of the
+    /// connection.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownConnection`](SimulatorError::UnknownConnection) if the peer is not connected.
+    /// - [`UnknownHandle`](SimulatorError::UnknownHandle) if the attribute does not exist.
+    /// - [`NotWritable`](SimulatorError::NotWritable) if the characteristic does not allow writes.
+    /// - [`ValueTooLong`](SimulatorError::ValueTooLong) if the value does not fit the attribute.
+    pub fn peer_write(
+        &mut self,
+        conn_handle: ConnectionHandle,
+        handle: AttributeHandle,
+        value: &[u8],
+    ) -> Result<(), SimulatorError> {
+        let conn_index = self.connection_index(conn_handle)?;
+        let attribute = self
+            .attributes
+            .iter_mut()
+            .find(|a| a.handle == handle)
+            .ok_or(SimulatorError::UnknownHandle(handle))?;
+        if value.len() > attribute.max_len {
+            return Err(SimulatorError::ValueTooLong(handle));
+        }
+
+        match attribute.kind {
+            AttributeKind::CharacteristicValue(_, properties)
+                if properties.intersects(
+                    CharacteristicProperty::WRITE | CharacteristicProperty::WRITE_WITHOUT_RESPONSE,
+                ) =>
+            {
+                write_value(attribute, 0, value);
+            }
+            AttributeKind::ClientConfiguration if value.len() == 2 => {
+                self.connections[conn_index].set_cccd(handle.0, LittleEndian::read_u16(value));
+            }
+            _ => return Err(SimulatorError::NotWritable(handle)),
+        }
+
+        let mut params = Vec::with_capacity(8 + value.len());
+        params.extend_from_slice(&conn_handle.0.to_le_bytes());
+        params.extend_from_slice(&handle.0.to_le_bytes());
+        params.extend_from_slice(&0u16.to_le_bytes());
+        params.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        params.extend_from_slice(value);
+        self.push_event(vendor_event(0x0C01, &params));
+
+        Ok(())
+    }
+
+    /// Subscribes the virtual peer to notifications and/or indications of a characteristic, by
+    /// writing its client characteristic configuration descriptor. `value_handle` is the handle of
+    /// the characteristic value.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownConnection`](SimulatorError::UnknownConnection) if the peer is not connected.
+    /// - [`NotSubscribable`](SimulatorError::NotSubscribable) if the characteristic has no client
+    ///   characteristic configuration descriptor.
+    pub fn peer_subscribe(
+        &mut self,
+        conn_handle: ConnectionHandle,
+        value_handle: AttributeHandle,
+        notify: bool,
+        indicate: bool,
+    ) -> Result<(), SimulatorError> {
+        let cccd = self
+            .cccd_for(value_handle.0)
+            .ok_or(SimulatorError::NotSubscribable(value_handle))?;
+        let mut bits = 0;
+        if notify {
+            bits |= CCCD_NOTIFY;
+        }
+        if indicate {
+            bits |= CCCD_INDICATE;
+        }
+
+        self.peer_write(conn_handle, AttributeHandle(cccd), &bits.to_le_bytes())
+    }
+
+    /// Disconnects the virtual peer. The simulated controller emits a [Disconnection
+    /// Complete](crate::event::Event::DisconnectionComplete) event.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownConnection`](SimulatorError::UnknownConnection) if the peer is not connected.
+    pub fn peer_disconnect(&mut self, conn_handle: ConnectionHandle) -> Result<(), SimulatorError> {
+        let index = self.connection_index(conn_handle)?;
+        self.connections.remove(index);
+        self.push_event(disconnection_complete(
+            conn_handle,
+            Status::RemoteTerminationByUser,
+        ));
+
+        Ok(())
+    }
+
+    /// Returns and clears the notifications and indications the virtual peer received on the
+    /// connection.
+    ///
+    /// # Errors
+    ///
+    /// - [`UnknownConnection`](SimulatorError::UnknownConnection) if the peer is not connected.
+    pub fn take_peer_notifications(
+        &mut self,
+        conn_handle: ConnectionHandle,
+    ) -> Result<Vec<PeerNotification>, SimulatorError> {
+        let index = self.connection_index(conn_handle)?;
+        Ok(core::mem::take(&mut self.connections[index].received))
+    }
+
+    /// Limits the number of notifications and indications the simulated controller holds before
+    /// they are sent. Once the limit is reached, updates fail with
+    /// [`InsufficientResources`](VendorStatus::InsufficientResources) until
+    /// [`transmit`](Simulator::transmit) is called. Without a limit, the pool never fills up.
+    pub fn set_tx_pool_size(&mut self, size: usize) {
+        self.tx_pool_size = size;
+    }
+
+    /// Sends all notifications and indications held in the TX pool. If an update failed because
+    /// the pool was full, the simulated controller emits a [GATT TX Pool
+    /// Available](crate::vendor::stm32wb::event::Stm32Wb5xEvent::GattTxPoolAvailable) event.
+    pub fn transmit(&mut self) {
+        let available = self.tx_pool_size.min(u16::MAX as usize) as u16;
+        self.tx_pool_used = 0;
+        if let Some(conn_handle) = self.tx_pool_waiting.take() {
+            let mut params = [0; 4];
+            LittleEndian::write_u16(&mut params[0..2], conn_handle.0);
+            LittleEndian::write_u16(&mut params[2..4], available);
+            self.push_event(vendor_event(0x0C16, &params));
+        }
+    }
+
+    fn connection_index(&self, conn_handle: ConnectionHandle) -> Result<usize, SimulatorError> {
+        self.connections
+            .iter()
+            .position(|c| c.conn_handle == conn_handle)
+            .ok_or(SimulatorError::UnknownConnection(conn_handle))
+    }
+
+    fn cccd_for(&self, value_handle: u16) -> Option<u16> {
+        let service_end = self
+            .services
+            .iter()
+            .find(|(start, s)| *start < value_handle && value_handle <= s.end)
+            .map(|(_, s)| s.end)?;
+        self.attributes
+            .iter()
+            .skip_while(|a| a.handle.0 <= value_handle)
+            .take_while(|a| {
+                a.handle.0 <= service_end
+                    && !matches!(a.kind, AttributeKind::CharacteristicDeclaration(_))
+            })
+            .find(|a| a.kind == AttributeKind::ClientConfiguration)
+            .map(|a| a.handle.0)
+    }
+
+    fn push_event(&self, event: Vec<u8>) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    fn add_attribute(&mut self, handle: u16, kind: AttributeKind, max_len: usize, variable: bool) {
+        let value = if variable {
+            Vec::new()
+        } else {
+            std::vec![0; max_len]
+        };
+        self.attributes.push(Attribute {
+            handle: AttributeHandle(handle),
+            kind,
+            value,
+            max_len,
+            is_variable: variable,
+        });
+        self.attributes.sort_by_key(|a| a.handle.0);
+    }
+
+    fn allocate_service(&mut self, uuid: Uuid, records: u16) -> Option<u16> {
+        if records == 0 || u32::from(self.next_handle) + u32::from(records) > 0x1_0000 {
+            return None;
+        }
+
+        let start = self.next_handle;
+        self.next_handle += records;
+        self.services.push((
+            start,
+            Service {
+                end: start + records - 1,
+                next: start + 1,
+            },
+        ));
+        self.add_attribute(start, AttributeKind::Service(uuid), 16, false);
+
+        Some(start)
+    }
+
+    fn allocate_in_service(&mut self, service_handle: u16, count: u16) -> Option<u16> {
+        let service = self
+            .services
+            .iter_mut()
+            .find(|(start, _)| *start == service_handle)
+            .map(|(_, s)| s)?;
+        if u32::from(service.next) + u32::from(count) > u32::from(service.end) + 1 {
+            return None;
+        }
+
+        let first = service.next;
+        service.next += count;
+        Some(first)
+    }
+
+    fn add_characteristic(
+        &mut self,
+        service_handle: u16,
+        uuid: Uuid,
+        max_len: usize,
+        properties: CharacteristicProperty,
+        variable: bool,
+    ) -> Option<u16> {
+        let subscribable = properties
+            .intersects(CharacteristicProperty::NOTIFY | CharacteristicProperty::INDICATE);
+        let count = if subscribable { 3 } else { 2 };
+        let handle = self.allocate_in_service(service_handle, count)?;
+
+        self.add_attribute(
+            handle,
+            AttributeKind::CharacteristicDeclaration(properties),
+            19,
+            false,
+        );
+        self.add_attribute(
+            handle + 1,
+            AttributeKind::CharacteristicValue(uuid, properties),
+            max_len,
+            variable,
+        );
+        if subscribable {
+            self.add_attribute(handle + 2, AttributeKind::ClientConfiguration, 2, false);
+        }
+
+        Some(handle)
+    }
+
+    fn reset(&mut self) {
+        let events = core::mem::take(&mut self.events);
+        *self = Simulator::new();
+        self.events = events;
+    }
+
+    fn gatt_init(&mut self) {
+        let service = self
+            .allocate_service(Uuid::Uuid16(0x1801), 4)
+            .expect("empty database");
+        self.add_characteristic(
+            service,
+            Uuid::Uuid16(0x2A05),
+            4,
+            CharacteristicProperty::INDICATE,
+            false,
+        );
+    }
+
+    fn gap_init(&mut self, dev_name_len: usize) -> Option<(u16, u16, u16)> {
+        let service = self.allocate_service(Uuid::Uuid16(0x1800), 5)?;
+        let dev_name = self.add_characteristic(
+            service,
+            Uuid::Uuid16(0x2A00),
+            dev_name_len,
+            CharacteristicProperty::READ,
+            true,
+        )?;
+        let appearance = self.add_characteristic(
+            service,
+            Uuid::Uuid16(0x2A01),
+            2,
+            CharacteristicProperty::READ,
+            false,
+        )?;
+
+        Some((service, dev_name, appearance))
+    }
+
+    fn update_characteristic_value(&mut self, params: &[u8]) -> Status<VendorStatus> {
+        if params.len() < 6 || params.len() != 6 + params[5] as usize {
+            return Status::Vendor(VendorStatus::InvalidParameters);
+        }
+
+        let char_handle = LittleEndian::read_u16(&params[2..4]);
+        let offset = params[4] as usize;
+        let update = Update {
+            value_handle: char_handle + 1,
+            target: None,
+            notify: true,
+            indicate: true,
+            total_len: None,
+        };
+        match self.update_value(&update, offset, &params[6..]) {
+            Ok(()) => Status::Success,
+            Err(status) => Status::Vendor(status),
+        }
+    }
+
+    fn update_characteristic_value_ext(&mut self, params: &[u8]) -> Status<VendorStatus> {
+        if params.len() < 12 || params.len() != 12 + params[11] as usize {
+            return Status::Vendor(VendorStatus::InvalidParameters);
+        }
+
+        let target = match LittleEndian::read_u16(&params[0..2]) {
+            0x0000 => None,
+            conn_handle => Some(ConnectionHandle(conn_handle)),
+        };
+        let char_handle = LittleEndian::read_u16(&params[4..6]);
+        let update = Update {
+            value_handle: char_handle + 1,
+            target,
+            notify: params[6] & 0x01 != 0,
+            indicate: params[6] & 0x02 != 0,
+            total_len: Some(LittleEndian::read_u16(&params[7..9]) as usize),
+        };
+        let offset = LittleEndian::read_u16(&params[9..11]) as usize;
+        match self.update_value(&update, offset, &params[12..]) {
+            Ok(()) => Status::Success,
+            Err(status) => Status::Vendor(status),
+        }
+    }
+
+    fn update_value(
+        &mut self,
+        update: &Update,
+        offset: usize,
+        value: &[u8],
+    ) -> Result<(), VendorStatus> {
+        let attribute = self
+            .attributes
+            .iter()
+            .find(|a| a.handle.0 == update.value_handle)
+            .ok_or(VendorStatus::InvalidHandle)?;
+        let properties = match attribute.kind {
+            AttributeKind::CharacteristicValue(_, properties) => properties,
+            _ => return Err(VendorStatus::InvalidHandle),
+        };
+        if offset + value.len() > attribute.max_len {
+            return Err(VendorStatus::InvalidParameter);
+        }
+
+        // Each notification or indication takes a buffer of the TX pool until it is sent.
+        let recipients = self.recipients(update, properties);
+        if !recipients.is_empty() && self.tx_pool_used + recipients.len() > self.tx_pool_size {
+            self.tx_pool_waiting = Some(recipients[0].0);
+            return Err(VendorStatus::InsufficientResources);
+        }
+
+        let attribute = self
+            .attributes
+            .iter_mut()
+            .find(|a| a.handle.0 == update.value_handle)
+            .unwrap();
+        write_value(attribute, offset, value);
+        if let Some(total_len) = update.total_len {
+            if attribute.is_variable && total_len <= attribute.max_len {
+                attribute.value.resize(total_len, 0);
+            }
+        }
+        let full_value = attribute.value.clone();
+
+        self.tx_pool_used += recipients.len();
+        for &(conn_handle, indication) in recipients.iter() {
+            let connection = self
+                .connections
+                .iter_mut()
+                .find(|c| c.conn_handle == conn_handle)
+                .unwrap();
+            connection.received.push(PeerNotification {
+                handle: AttributeHandle(update.value_handle),
+                value: full_value.clone(),
+                indication,
+            });
+        }
+        for &(conn_handle, indication) in recipients.iter() {
+            if indication {
+                self.push_event(vendor_event(0x0C17, &conn_handle.0.to_le_bytes()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the connections the update is sent to, and whether it is sent as an indication.
+    fn recipients(
+        &self,
+        update: &Update,
+        properties: CharacteristicProperty,
+    ) -> Vec<(ConnectionHandle, bool)> {
+        let Some(cccd) = self.cccd_for(update.value_handle) else {
+            return Vec::new();
+        };
+
+        self.connections
+            .iter()
+            .filter(|c| update.target.is_none_or(|target| c.conn_handle == target))
+            .filter_map(|connection| {
+                let bits = connection.cccd(cccd);
+                let indication = if update.indicate
+                    && bits & CCCD_INDICATE != 0
+                    && properties.contains(CharacteristicProperty::INDICATE)
+                {
+                    true
+                } else if update.notify
+                    && bits & CCCD_NOTIFY != 0
+                    && properties.contains(CharacteristicProperty::NOTIFY)
+                {
+                    false
+                } else {
+                    return None;
+                };
+
+                Some((connection.conn_handle, indication))
+            })
+            .collect()
+    }
+
+    fn read_handle_value(&self, params: &[u8]) -> Vec<u8> {
+        let mut bytes = [0; 5];
+        let value = match self.read_handle_value_range(params) {
+            Ok((attribute_len, value)) => {
+                LittleEndian::write_u16(&mut bytes[1..3], attribute_len as u16);
+                LittleEndian::write_u16(&mut bytes[3..5], value.len() as u16);
+                value
+            }
+            Err(status) => {
+                bytes[0] = Status::Vendor(status).into();
+                &[]
+            }
+        };
+
+        let mut bytes = bytes.to_vec();
+        bytes.extend_from_slice(value);
+        command_complete(opcode::GATT_READ_HANDLE_VALUE, &bytes)
+    }
+
+    fn read_handle_value_range(&self, params: &[u8]) -> Result<(usize, &[u8]), VendorStatus> {
+        if params.len() != 6 {
+            return Err(VendorStatus::InvalidParameters);
+        }
+
+        let handle = LittleEndian::read_u16(&params[0..2]);
+        let offset = LittleEndian::read_u16(&params[2..4]) as usize;
+        let max_len = LittleEndian::read_u16(&params[4..6]) as usize;
+        let attribute = self
+            .attributes
+            .iter()
+            .find(|a| a.handle.0 == handle)
+            .ok_or(VendorStatus::InvalidHandle)?;
+        if offset > attribute.value.len() {
+            return Err(VendorStatus::InvalidParameters);
+        }
+
+        let value = &attribute.value[offset..];
+        Ok((attribute.value.len(), &value[..value.len().min(max_len)]))
+    }
+
+    fn handle_command(&mut self, opcode: Opcode, params: &[u8]) {
+        let invalid = Status::Vendor(VendorStatus::InvalidParameters);
+        match opcode {
+            crate::opcode::RESET => {
+                self.reset();
+                self.push_event(command_complete_status(opcode, Status::Success));
+            }
+            opcode::GATT_INIT => {
+                let status = if self.attributes.is_empty() {
+                    self.gatt_init();
+                    Status::Success
+                } else {
+                    Status::Vendor(VendorStatus::NotAllowed)
+                };
+                self.push_event(command_complete_status(opcode, status));
+            }
+            opcode::GAP_INIT => {
+                let handles = if params.len() == 3 {
+                    self.gap_init(params[2] as usize)
+                } else {
+                    None
+                };
+                let mut bytes = [0; 7];
+                match handles {
+                    Some((service, dev_name, appearance)) => {
+                        LittleEndian::write_u16(&mut bytes[1..3], service);
+                        LittleEndian::write_u16(&mut bytes[3..5], dev_name);
+                        LittleEndian::write_u16(&mut bytes[5..7], appearance);
+                    }
+                    None => bytes[0] = invalid.into(),
+                }
+                self.push_event(command_complete(opcode, &bytes));
+            }
+            opcode::GATT_ADD_SERVICE => {
+                let handle = parse_uuid(params).and_then(|(uuid, next)| {
+                    if params.len() < next + 2 {
+                        return None;
+                    }
+                    self.allocate_service(uuid, u16::from(params[next + 1]))
+                });
+                self.push_event(handle_complete(opcode, handle, VendorStatus::OutOfHandle));
+            }
+            opcode::GATT_ADD_CHARACTERISTIC => {
+                let handle = if params.len() > 2 {
+                    let service_handle = LittleEndian::read_u16(&params[0..2]);
+                    parse_uuid(&params[2..]).and_then(|(uuid, next)| {
+                        let rest = &params[2 + next..];
+                        // Length, properties, permissions, event mask, key size and variable
+                        // length flag.
+                        if rest.len() < 7 {
+                            return None;
+                        }
+                        let max_len = LittleEndian::read_u16(&rest[0..2]) as usize;
+                        let properties = CharacteristicProperty::from_bits_truncate(rest[2]);
+                        let variable = rest[6] != 0;
+                        self.add_characteristic(service_handle, uuid, max_len, properties, variable)
+                    })
+                } else {
+                    None
+                };
+                self.push_event(handle_complete(
+                    opcode,
+                    handle,
+                    VendorStatus::InsufficientResources,
+                ));
+            }
+            opcode::GATT_ADD_CHARACTERISTIC_DESCRIPTOR => {
+                let handle = if params.len() > 4 {
+                    let service_handle = LittleEndian::read_u16(&params[0..2]);
+                    parse_uuid(&params[4..]).and_then(|(uuid, next)| {
+                        let rest = &params[4 + next..];
+                        if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+                            return None;
+                        }
+                        let max_len = rest[0] as usize;
+                        let value = &rest[2..2 + rest[1] as usize];
+                        let handle = self.allocate_in_service(service_handle, 1)?;
+                        let kind = if uuid == Uuid::Uuid16(0x2902) {
+                            AttributeKind::ClientConfiguration
+                        } else {
+                            AttributeKind::Descriptor(uuid)
+                        };
+                        self.add_attribute(handle, kind, max_len, true);
+                        let attribute = self
+                            .attributes
+                            .iter_mut()
+                            .find(|a| a.handle.0 == handle)
+                            .unwrap();
+                        write_value(attribute, 0, value);
+                        Some(handle)
+                    })
+                } else {
+                    None
+                };
+                self.push_event(handle_complete(
+                    opcode,
+                    handle,
+                    VendorStatus::InsufficientResources,
+                ));
+            }
+            opcode::GATT_UPDATE_CHARACTERISTIC_VALUE => {
+                let status = self.update_characteristic_value(params);
+                self.push_event(command_complete_status(opcode, status));
+            }
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE => {
+                let status = self.update_characteristic_value_ext(params);
+                self.push_event(command_complete_status(opcode, status));
+            }
+            opcode::GATT_READ_HANDLE_VALUE => {
+                let event = self.read_handle_value(params);
+                self.push_event(event);
+            }
+            opcode::GAP_SET_DISCOVERABLE | opcode::GAP_SET_UNDIRECTED_CONNECTABLE => {
+                let status = if self.connections.is_empty() {
+                    self.advertising = true;
+                    Status::Success
+                } else {
+                    Status::CommandDisallowed
+                };
+                self.push_event(command_complete_status(opcode, status));
+            }
+            opcode::GAP_SET_NONDISCOVERABLE => {
+                self.advertising = false;
+                self.push_event(command_complete_status(opcode, Status::Success));
+            }
+            opcode::GAP_TERMINATE => {
+                if params.len() != 3 {
+                    self.push_event(command_status(opcode, invalid));
+                    return;
+                }
+
+                let conn_handle = ConnectionHandle(LittleEndian::read_u16(&params[0..2]));
+                match self.connection_index(conn_handle) {
+                    Ok(index) => {
+                        self.connections.remove(index);
+                        self.push_event(command_status(opcode, Status::Success));
+                        self.push_event(disconnection_complete(
+                            conn_handle,
+                            Status::ConnectionTerminatedByHost,
+                        ));
+                    }
+                    Err(_) => {
+                        self.push_event(command_status(opcode, Status::UnknownConnectionId));
+                    }
+                }
+            }
+            _ => self.push_event(command_status(opcode, Status::UnknownCommand)),
+        }
+    }
+}
+
+fn handle_complete(opcode: Opcode, handle: Option<u16>, failure: VendorStatus) -> Vec<u8> {
+    let mut bytes = [0; 3];
+    match handle {
+        Some(handle) => LittleEndian::write_u16(&mut bytes[1..3], handle),
+        None => bytes[0] = Status::Vendor(failure).into(),
+    }
+    command_complete(opcode, &bytes)
+}
+
+fn parse_uuid(bytes: &[u8]) -> Option<(Uuid, usize)> {
+    match bytes.first()? {
+        0x01 if bytes.len() >= 3 => Some((Uuid::Uuid16(LittleEndian::read_u16(&bytes[1..3])), 3)),
+        0x02 if bytes.len() >= 17 => {
+            let mut uuid = [0; 16];
+            uuid.copy_from_slice(&bytes[1..17]);
+            Some((Uuid::Uuid128(uuid), 17))
+        }
+        _ => None,
+    }
+}
+
+fn write_value(attribute: &mut Attribute, offset: usize, value: &[u8]) {
+    if attribute.is_variable && offset == 0 {
+        attribute.value.clear();
+    }
+    let end = offset + value.len();
+    if attribute.value.len() < end {
+        attribute.value.resize(end, 0);
+    }
+    attribute.value[offset..end].copy_from_slice(value);
+}
+
+impl Controller for Simulator {
+    async fn controller_write(&mut self, opcode: Opcode, payload: &[u8]) {
+        self.handle_command(opcode, payload);
+    }
+
+    async fn controller_read_into(&self, buf: &mut [u8]) {
+        let event = self
+            .events
+            .borrow_mut()
+            .pop_front()
+            .expect("read from the simulator, but no event is pending");
+
+        core::assert!(
+            buf.len() > event.len(),
+            "read buffer of {} bytes is too small for an event of {} bytes",
+            buf.len(),
+            event.len() + 1
+        );
+        buf[0] = PACKET_TYPE_HCI_EVENT;
+        buf[1..=event.len()].copy_from_slice(&event);
+    }
+}
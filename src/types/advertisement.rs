@@ -2,7 +2,7 @@
 
 use byteorder::{ByteOrder, LittleEndian};
 
-use super::CommonDataType;
+use super::{CommonDataType, Uuid};
 
 /// LE Advertisement Type
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -36,6 +36,20 @@ pub enum Advertisement<'a> {
     ManufacturerSpecificData(u16, &'a [u8]),
 }
 
+impl<'a> Advertisement<'a> {
+    /// Returns the service data advertisement for the UUID, in the shortest of the
+    /// [ServiceData16BitUuid](Advertisement::ServiceData16BitUuid),
+    /// [ServiceData32BitUuid](Advertisement::ServiceData32BitUuid) and
+    /// [ServiceData128BitUuid](Advertisement::ServiceData128BitUuid) forms.
+    pub const fn service_data(uuid: Uuid, data: &'a [u8]) -> Advertisement<'a> {
+        match uuid.shortened() {
+            Uuid::Uuid16(uuid) => Advertisement::ServiceData16BitUuid(uuid, data),
+            Uuid::Uuid32(uuid) => Advertisement::ServiceData32BitUuid(uuid, data),
+            Uuid::Uuid128(_) => Advertisement::ServiceData128BitUuid(uuid.to_u128(), data),
+        }
+    }
+}
+
 impl Advertisement<'_> {
     /// Gets the length of the advertisement payload, in bytes.
     ///
@@ -95,3 +109,51 @@ impl Advertisement<'_> {
         len
     }
 }
+
+/// One AD structure of advertising or scan response data, as defined in Vol 3, Part C, Section
+/// 11 of the spec.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdStructure<'a> {
+    /// Type of the data. Usually one of the [CommonDataType] values.
+    pub ad_type: u8,
+    /// The data, without the length and type.
+    pub data: &'a [u8],
+}
+
+/// Iterator over the AD structures of advertising or scan response data.
+///
+/// The iteration ends at the first structure of length 0, which marks the end of the significant
+/// part of the data, or at the first structure that does not fit in the data.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdStructures<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AdStructures<'a> {
+    /// Returns an iterator over the AD structures of the data.
+    pub fn new(data: &'a [u8]) -> AdStructures<'a> {
+        AdStructures { data }
+    }
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = AdStructure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The length counts the type and the data.
+        let len = *self.data.first()? as usize;
+        if len == 0 || len >= self.data.len() {
+            self.data = &[];
+            return None;
+        }
+
+        let structure = AdStructure {
+            ad_type: self.data[1],
+            data: &self.data[2..1 + len],
+        };
+        self.data = &self.data[1 + len..];
+        Some(structure)
+    }
+}
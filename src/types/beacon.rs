@@ -0,0 +1,396 @@
+//! Types for beacon formats carried in advertising data.
+//!
+//! Each beacon serializes into complete advertising data, ready to be passed to
+//! [`set_broadcast_mode`](crate::vendor::stm32wb::command::gap::GapCommands::set_broadcast_mode)
+//! or
+//! [`additonal_beacon_set_data`](crate::vendor::stm32wb::command::gap::GapCommands::additonal_beacon_set_data).
+//! [`Beacon::from_advertising_data`] recognizes the beacons in the advertising data of scan
+//! results.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use core::fmt;
+use core::time::Duration;
+
+use super::{AdStructures, Advertisement, CommonDataType};
+
+/// Flags placed before every beacon: LE General Discoverable Mode, BR/EDR Not Supported.
+pub const BEACON_FLAGS: u8 = 0x06;
+
+/// Company identifier of Apple, used by iBeacon.
+pub const APPLE_COMPANY_ID: u16 = 0x004C;
+
+/// 16-bit service UUID of Eddystone.
+pub const EDDYSTONE_UUID: u16 = 0xFEAA;
+
+const IBEACON_PREFIX: [u8; 2] = [0x02, 0x15];
+const ALTBEACON_CODE: [u8; 2] = [0xBE, 0xAC];
+
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+
+/// Longest encoded URL of an Eddystone-URL frame, without the scheme prefix.
+pub const MAX_EDDYSTONE_URL_LEN: usize = 17;
+
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// Beacon formats.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Beacon {
+    /// Apple iBeacon.
+    IBeacon(IBeacon),
+    /// Eddystone-UID frame.
+    EddystoneUid(EddystoneUid),
+    /// Eddystone-URL frame.
+    EddystoneUrl(EddystoneUrl),
+    /// Unencrypted Eddystone-TLM frame.
+    EddystoneTlm(EddystoneTlm),
+    /// AltBeacon.
+    AltBeacon(AltBeacon),
+}
+
+impl Beacon {
+    /// Gets the length of the advertising data of the beacon, in bytes.
+    ///
+    /// This includes the flags and, for Eddystone, the service UUID list.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let mut payload = [0; 24];
+        self.advertisement(&mut payload).len()
+            + match self {
+                Beacon::IBeacon(_) | Beacon::AltBeacon(_) => 3,
+                _ => 7,
+            }
+    }
+
+    /// Serializes the advertising data of the beacon into the given buffer, and returns the
+    /// number of bytes written.
+    ///
+    /// The advertising data starts with the [flags](BEACON_FLAGS). Eddystone frames then list the
+    /// Eddystone service UUID. All beacons fit in the 31 bytes of legacy advertising.
+    ///
+    /// `bytes` must be at least [Self::len()] bytes.
+    pub fn copy_into_slice(&self, bytes: &mut [u8]) -> usize {
+        bytes[..3].copy_from_slice(&[2, CommonDataType::Flags as u8, BEACON_FLAGS]);
+        let mut len = 3;
+        if !matches!(self, Beacon::IBeacon(_) | Beacon::AltBeacon(_)) {
+            bytes[3] = 3;
+            bytes[4] = CommonDataType::CompleteListOf16BitServiceClassUuids as u8;
+            LittleEndian::write_u16(&mut bytes[5..], EDDYSTONE_UUID);
+            len = 7;
+        }
+
+        let mut payload = [0; 24];
+        len + self
+            .advertisement(&mut payload)
+            .copy_into_slice(&mut bytes[len..])
+    }
+
+    /// Returns the first beacon found in advertising or scan response data, if any.
+    pub fn from_advertising_data(data: &[u8]) -> Option<Beacon> {
+        AdStructures::new(data).find_map(|structure| {
+            if structure.data.len() < 2 {
+                return None;
+            }
+            let (id, payload) = structure.data.split_at(2);
+            let id = LittleEndian::read_u16(id);
+            match structure.ad_type {
+                t if t == CommonDataType::ManufacturerSpecificData as u8 => {
+                    Self::from_manufacturer_data(id, payload)
+                }
+                t if t == CommonDataType::ServiceData16BitUuid as u8 && id == EDDYSTONE_UUID => {
+                    Self::from_eddystone_frame(payload)
+                }
+                _ => None,
+            }
+        })
+    }
+
+    fn from_manufacturer_data(company_id: u16, payload: &[u8]) -> Option<Beacon> {
+        if company_id == APPLE_COMPANY_ID && payload.len() == 23 && payload[..2] == IBEACON_PREFIX {
+            let mut uuid = [0; 16];
+            uuid.copy_from_slice(&payload[2..18]);
+            return Some(Beacon::IBeacon(IBeacon {
+                uuid,
+                major: BigEndian::read_u16(&payload[18..]),
+                minor: BigEndian::read_u16(&payload[20..]),
+                measured_power: payload[22] as i8,
+            }));
+        }
+
+        if payload.len() == 24 && payload[..2] == ALTBEACON_CODE {
+            let mut id = [0; 20];
+            id.copy_from_slice(&payload[2..22]);
+            return Some(Beacon::AltBeacon(AltBeacon {
+                manufacturer_id: company_id,
+                id,
+                reference_rssi: payload[22] as i8,
+                manufacturer_reserved: payload[23],
+            }));
+        }
+
+        None
+    }
+
+    fn from_eddystone_frame(frame: &[u8]) -> Option<Beacon> {
+        match *frame.first()? {
+            EDDYSTONE_UID if frame.len() == 18 || frame.len() == 20 => {
+                let mut namespace = [0; 10];
+                namespace.copy_from_slice(&frame[2..12]);
+                let mut instance = [0; 6];
+                instance.copy_from_slice(&frame[12..18]);
+                Some(Beacon::EddystoneUid(EddystoneUid {
+                    tx_power: frame[1] as i8,
+                    namespace,
+                    instance,
+                }))
+            }
+            EDDYSTONE_URL if frame.len() >= 3 && frame.len() <= 3 + MAX_EDDYSTONE_URL_LEN => {
+                let scheme = frame[2];
+                let url = &frame[3..];
+                if usize::from(scheme) >= URL_SCHEMES.len()
+                    || url
+                        .iter()
+                        .any(|&b| Self::expansion(b).is_none() && !is_url_char(b))
+                {
+                    return None;
+                }
+
+                let mut encoded = [0; MAX_EDDYSTONE_URL_LEN];
+                encoded[..url.len()].copy_from_slice(url);
+                Some(Beacon::EddystoneUrl(EddystoneUrl {
+                    tx_power: frame[1] as i8,
+                    scheme,
+                    encoded,
+                    len: url.len(),
+                }))
+            }
+            // Only version 0, the unencrypted TLM frame, is supported.
+            EDDYSTONE_TLM if frame.len() == 14 && frame[1] == 0 => {
+                let temperature = BigEndian::read_i16(&frame[4..]);
+                Some(Beacon::EddystoneTlm(EddystoneTlm {
+                    battery_voltage: BigEndian::read_u16(&frame[2..]),
+                    temperature: if temperature == i16::MIN {
+                        None
+                    } else {
+                        Some(temperature)
+                    },
+                    advertising_count: BigEndian::read_u32(&frame[6..]),
+                    uptime: Duration::from_millis(
+                        100 * u64::from(BigEndian::read_u32(&frame[10..])),
+                    ),
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn expansion(byte: u8) -> Option<&'static str> {
+        URL_EXPANSIONS.get(usize::from(byte)).copied()
+    }
+
+    // Writes the beacon payload into the buffer and returns the AD structure that carries it.
+    fn advertisement<'a>(&self, payload: &'a mut [u8; 24]) -> Advertisement<'a> {
+        match self {
+            Beacon::IBeacon(beacon) => {
+                payload[..2].copy_from_slice(&IBEACON_PREFIX);
+                payload[2..18].copy_from_slice(&beacon.uuid);
+                BigEndian::write_u16(&mut payload[18..], beacon.major);
+                BigEndian::write_u16(&mut payload[20..], beacon.minor);
+                payload[22] = beacon.measured_power as u8;
+                Advertisement::ManufacturerSpecificData(APPLE_COMPANY_ID, &payload[..23])
+            }
+            Beacon::AltBeacon(beacon) => {
+                payload[..2].copy_from_slice(&ALTBEACON_CODE);
+                payload[2..22].copy_from_slice(&beacon.id);
+                payload[22] = beacon.reference_rssi as u8;
+                payload[23] = beacon.manufacturer_reserved;
+                Advertisement::ManufacturerSpecificData(beacon.manufacturer_id, &payload[..24])
+            }
+            Beacon::EddystoneUid(beacon) => {
+                payload[0] = EDDYSTONE_UID;
+                payload[1] = beacon.tx_power as u8;
+                payload[2..12].copy_from_slice(&beacon.namespace);
+                payload[12..18].copy_from_slice(&beacon.instance);
+                // Reserved for future use.
+                payload[18..20].fill(0);
+                Advertisement::ServiceData16BitUuid(EDDYSTONE_UUID, &payload[..20])
+            }
+            Beacon::EddystoneUrl(beacon) => {
+                payload[0] = EDDYSTONE_URL;
+                payload[1] = beacon.tx_power as u8;
+                payload[2] = beacon.scheme;
+                payload[3..3 + beacon.len].copy_from_slice(beacon.encoded());
+                Advertisement::ServiceData16BitUuid(EDDYSTONE_UUID, &payload[..3 + beacon.len])
+            }
+            Beacon::EddystoneTlm(beacon) => {
+                payload[0] = EDDYSTONE_TLM;
+                payload[1] = 0;
+                BigEndian::write_u16(&mut payload[2..], beacon.battery_voltage);
+                BigEndian::write_i16(&mut payload[4..], beacon.temperature.unwrap_or(i16::MIN));
+                BigEndian::write_u32(&mut payload[6..], beacon.advertising_count);
+                BigEndian::write_u32(&mut payload[10..], (beacon.uptime.as_millis() / 100) as u32);
+                Advertisement::ServiceData16BitUuid(EDDYSTONE_UUID, &payload[..14])
+            }
+        }
+    }
+}
+
+/// Apple iBeacon.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IBeacon {
+    /// Proximity UUID, in the order it is usually written.
+    pub uuid: [u8; 16],
+    /// Major number.
+    pub major: u16,
+    /// Minor number.
+    pub minor: u16,
+    /// RSSI measured at 1 m from the beacon, in dBm.
+    pub measured_power: i8,
+}
+
+/// AltBeacon.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AltBeacon {
+    /// Company identifier of the manufacturer of the beacon.
+    pub manufacturer_id: u16,
+    /// Beacon identifier. The first 16 bytes usually hold an organizational unit.
+    pub id: [u8; 20],
+    /// RSSI measured at 1 m from the beacon, in dBm.
+    pub reference_rssi: i8,
+    /// Reserved for use by the manufacturer.
+    pub manufacturer_reserved: u8,
+}
+
+/// Eddystone-UID frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneUid {
+    /// Transmit power measured at 0 m, in dBm.
+    pub tx_power: i8,
+    /// Namespace of the beacon.
+    pub namespace: [u8; 10],
+    /// Instance of the beacon within the namespace.
+    pub instance: [u8; 6],
+}
+
+/// Eddystone-URL frame.
+///
+/// The URL is compressed when the frame is created. Use [Display](fmt::Display) to expand it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneUrl {
+    /// Transmit power measured at 0 m, in dBm.
+    pub tx_power: i8,
+    scheme: u8,
+    encoded: [u8; MAX_EDDYSTONE_URL_LEN],
+    len: usize,
+}
+
+impl EddystoneUrl {
+    /// Creates an Eddystone-URL frame for the URL.
+    ///
+    /// # Errors
+    ///
+    /// - [BadUrlScheme](EddystoneUrlError::BadUrlScheme) if the URL does not start with `http://`
+    ///   or `https://`.
+    /// - [BadUrlCharacter](EddystoneUrlError::BadUrlCharacter) if the URL contains a character
+    ///   that is not printable ASCII.
+    /// - [UrlTooLong](EddystoneUrlError::UrlTooLong) if the compressed URL is longer than
+    ///   [`MAX_EDDYSTONE_URL_LEN`] bytes.
+    pub fn new(tx_power: i8, url: &str) -> Result<EddystoneUrl, EddystoneUrlError> {
+        let (scheme, prefix) = URL_SCHEMES
+            .iter()
+            .enumerate()
+            .find(|(_, prefix)| url.starts_with(*prefix))
+            .ok_or(EddystoneUrlError::BadUrlScheme)?;
+
+        let mut encoded = [0; MAX_EDDYSTONE_URL_LEN];
+        let mut len = 0;
+        let mut rest = &url[prefix.len()..];
+        while let Some(&first) = rest.as_bytes().first() {
+            if len == MAX_EDDYSTONE_URL_LEN {
+                return Err(EddystoneUrlError::UrlTooLong);
+            }
+
+            // The expansions ending with a slash come first, so they are preferred.
+            if let Some((code, expansion)) = URL_EXPANSIONS
+                .iter()
+                .enumerate()
+                .find(|(_, expansion)| rest.starts_with(*expansion))
+            {
+                encoded[len] = code as u8;
+                rest = &rest[expansion.len()..];
+            } else if is_url_char(first) {
+                encoded[len] = first;
+                rest = &rest[1..];
+            } else {
+                return Err(EddystoneUrlError::BadUrlCharacter);
+            }
+            len += 1;
+        }
+
+        Ok(EddystoneUrl {
+            tx_power,
+            scheme: scheme as u8,
+            encoded,
+            len,
+        })
+    }
+
+    /// Returns the compressed URL, without the scheme prefix.
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded[..self.len]
+    }
+}
+
+impl fmt::Display for EddystoneUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(URL_SCHEMES[usize::from(self.scheme)])?;
+        for &byte in self.encoded() {
+            match Beacon::expansion(byte) {
+                Some(expansion) => f.write_str(expansion)?,
+                None => fmt::Write::write_char(f, char::from(byte))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Types of errors that can occur when creating an [`EddystoneUrl`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EddystoneUrlError {
+    /// The URL does not start with `http://` or `https://`.
+    BadUrlScheme,
+    /// The URL contains a character that is not printable ASCII.
+    BadUrlCharacter,
+    /// The compressed URL does not fit in the frame.
+    UrlTooLong,
+}
+
+/// Unencrypted Eddystone-TLM frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EddystoneTlm {
+    /// Battery voltage, in mV. 0 if the beacon is not battery powered.
+    pub battery_voltage: u16,
+    /// Temperature, in 1/256 °C, if the beacon has a sensor.
+    pub temperature: Option<i16>,
+    /// Number of advertising frames sent since the beacon was powered on.
+    pub advertising_count: u32,
+    /// Time since the beacon was powered on, with a resolution of 0.1 s.
+    pub uptime: Duration,
+}
+
+// Characters that are sent as themselves in an Eddystone URL.
+fn is_url_char(byte: u8) -> bool {
+    (0x21..0x7F).contains(&byte)
+}
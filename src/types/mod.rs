@@ -2,14 +2,18 @@
 
 mod advertisement;
 mod advertising_interval;
+mod beacon;
 mod common;
 mod connection_interval;
 mod expected_connection_length;
 mod scan_window;
+mod uuid;
 
 pub use self::advertisement::*;
 pub use self::advertising_interval::*;
+pub use self::beacon::*;
 pub use self::common::*;
 pub use self::connection_interval::*;
 pub use self::expected_connection_length::*;
 pub use self::scan_window::*;
+pub use self::uuid::*;
@@ -0,0 +1,244 @@
+//! Types for Bluetooth UUIDs.
+
+use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
+use core::str::FromStr;
+
+/// The Bluetooth Base UUID, 00000000-0000-1000-8000-00805F9B34FB, from which the 16-bit and
+/// 32-bit UUIDs are shortened.
+pub const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5F9B_34FB;
+
+/// A Bluetooth UUID, of any of the sizes defined in Vol 3, Part B, Section 2.5.1 of the spec.
+///
+/// 16-bit and 32-bit UUIDs are shortened forms of 128-bit UUIDs built on the [Base
+/// UUID](BASE_UUID). Two UUIDs are equal if they have the same 128-bit form, whatever their sizes.
+///
+/// The bytes of [`Uuid128`](Uuid::Uuid128) are in little-endian order, as sent over the air. Use
+/// [`from_u128`](Uuid::from_u128), [`parse`](Uuid::parse) or [`new`](Uuid::new) to build one from
+/// the usual written form.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Uuid {
+    /// 16-bit UUID
+    Uuid16(u16),
+
+    /// 32-bit UUID
+    Uuid32(u32),
+
+    /// 128-bit UUID, in little-endian order.
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// Parses the canonical form of a UUID, `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, and returns
+    /// it in its shortest form. Both lowercase and uppercase hexadecimal digits are accepted.
+    ///
+    /// # Panics
+    ///
+    /// The string must be a valid UUID. In a `const` item, an invalid string fails the build.
+    pub const fn new(s: &str) -> Uuid {
+        match Uuid::parse(s) {
+            Ok(uuid) => uuid,
+            Err(_) => core::panic!("invalid UUID"),
+        }
+    }
+
+    /// Parses the canonical form of a UUID, `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, and returns
+    /// it in its shortest form. Both lowercase and uppercase hexadecimal digits are accepted.
+    ///
+    /// # Errors
+    ///
+    /// - [BadStringLength](UuidError::BadStringLength) if the string is not 36 bytes long.
+    /// - [BadCharacter](UuidError::BadCharacter) if a character is not a hexadecimal digit, or a
+    ///   hyphen is missing.
+    pub const fn parse(s: &str) -> Result<Uuid, UuidError> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 36 {
+            return Err(UuidError::BadStringLength(bytes.len()));
+        }
+
+        let mut value: u128 = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            let c = bytes[i];
+            if i == 8 || i == 13 || i == 18 || i == 23 {
+                if c != b'-' {
+                    return Err(UuidError::BadCharacter(i));
+                }
+            } else {
+                let digit = match c {
+                    b'0'..=b'9' => c - b'0',
+                    b'a'..=b'f' => c - b'a' + 10,
+                    b'A'..=b'F' => c - b'A' + 10,
+                    _ => return Err(UuidError::BadCharacter(i)),
+                };
+                value = (value << 4) | digit as u128;
+            }
+            i += 1;
+        }
+
+        Ok(Uuid::from_u128(value))
+    }
+
+    /// Returns the UUID with the given 128-bit value, in its shortest form.
+    pub const fn from_u128(value: u128) -> Uuid {
+        const MASK: u128 = (1 << 96) - 1;
+        if value & MASK == BASE_UUID {
+            let short = (value >> 96) as u32;
+            if short <= u16::MAX as u32 {
+                Uuid::Uuid16(short as u16)
+            } else {
+                Uuid::Uuid32(short)
+            }
+        } else {
+            Uuid::Uuid128(value.to_le_bytes())
+        }
+    }
+
+    /// Returns the 128-bit value of the UUID, as it is usually written.
+    pub const fn to_u128(&self) -> u128 {
+        match *self {
+            Uuid::Uuid16(uuid) => BASE_UUID | (uuid as u128) << 96,
+            Uuid::Uuid32(uuid) => BASE_UUID | (uuid as u128) << 96,
+            Uuid::Uuid128(bytes) => u128::from_le_bytes(bytes),
+        }
+    }
+
+    /// Returns the 16-bit form of the UUID, if it has one.
+    pub const fn as_u16(&self) -> Option<u16> {
+        match self.shortened() {
+            Uuid::Uuid16(uuid) => Some(uuid),
+            _ => None,
+        }
+    }
+
+    /// Returns the 32-bit form of the UUID, if it has one. 16-bit UUIDs also have a 32-bit form.
+    pub const fn as_u32(&self) -> Option<u32> {
+        match self.shortened() {
+            Uuid::Uuid16(uuid) => Some(uuid as u32),
+            Uuid::Uuid32(uuid) => Some(uuid),
+            Uuid::Uuid128(_) => None,
+        }
+    }
+
+    /// Returns the UUID in its shortest form.
+    pub const fn shortened(&self) -> Uuid {
+        Uuid::from_u128(self.to_u128())
+    }
+
+    /// Returns the UUID in its 128-bit form.
+    pub const fn to_uuid128(&self) -> Uuid {
+        Uuid::Uuid128(self.to_u128().to_le_bytes())
+    }
+
+    /// Gets the length of the UUID as sent over the air: 2, 4 or 16 bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid32(_) => 4,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Serializes the UUID into the given buffer, in little-endian order, and returns the number
+    /// of bytes written.
+    ///
+    /// # Panics
+    ///
+    /// `bytes` must be at least [Self::len()] bytes.
+    pub fn copy_into_slice(&self, bytes: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => LittleEndian::write_u16(bytes, *uuid),
+            Uuid::Uuid32(uuid) => LittleEndian::write_u32(bytes, *uuid),
+            Uuid::Uuid128(uuid) => bytes[..16].copy_from_slice(uuid),
+        }
+        self.len()
+    }
+
+    /// Deserializes a UUID sent over the air, in little-endian order. The size of the UUID is the
+    /// length of `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// - [BadLength](UuidError::BadLength) if `bytes` is not 2, 4 or 16 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Uuid, UuidError> {
+        match bytes.len() {
+            2 => Ok(Uuid::Uuid16(LittleEndian::read_u16(bytes))),
+            4 => Ok(Uuid::Uuid32(LittleEndian::read_u32(bytes))),
+            16 => {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(bytes);
+                Ok(Uuid::Uuid128(uuid))
+            }
+            len => Err(UuidError::BadLength(len)),
+        }
+    }
+}
+
+impl PartialEq for Uuid {
+    fn eq(&self, other: &Uuid) -> bool {
+        self.to_u128() == other.to_u128()
+    }
+}
+
+impl Eq for Uuid {}
+
+impl From<u16> for Uuid {
+    fn from(uuid: u16) -> Self {
+        Uuid::Uuid16(uuid)
+    }
+}
+
+impl From<u32> for Uuid {
+    fn from(uuid: u32) -> Self {
+        Uuid::Uuid32(uuid)
+    }
+}
+
+impl From<u128> for Uuid {
+    fn from(value: u128) -> Self {
+        Uuid::from_u128(value)
+    }
+}
+
+impl From<Uuid> for u128 {
+    fn from(uuid: Uuid) -> Self {
+        uuid.to_u128()
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = UuidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Uuid::parse(s)
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.to_u128();
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            value >> 96,
+            (value >> 80) & 0xFFFF,
+            (value >> 64) & 0xFFFF,
+            (value >> 48) & 0xFFFF,
+            value & 0xFFFF_FFFF_FFFF
+        )
+    }
+}
+
+/// Types of errors that can occur when reading a [`Uuid`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UuidError {
+    /// The bytes are not 2, 4 or 16 bytes long. Includes the length.
+    BadLength(usize),
+    /// The string is not 36 bytes long. Includes the length.
+    BadStringLength(usize),
+    /// The string holds an unexpected character. Includes its index.
+    BadCharacter(usize),
+}
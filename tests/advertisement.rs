@@ -1,6 +1,6 @@
 #![feature(async_fn_in_trait)]
 
-use hci::types::Advertisement;
+use hci::types::{AdStructure, AdStructures, Advertisement};
 
 extern crate stm32wb_hci as hci;
 
@@ -50,3 +50,38 @@ fn eddystone() {
     assert_eq!(expected.len(), l);
     assert_eq!(expected, o[..l]);
 }
+
+#[test]
+fn ad_structures() {
+    let data = [
+        0x02, 0x01, 0x06, 0x03, 0x03, 0x0F, 0x18, 0x01, 0xFF, 0x00, 0x05, 0x09,
+    ];
+    let mut structures = AdStructures::new(&data);
+    assert_eq!(
+        structures.next(),
+        Some(AdStructure {
+            ad_type: 0x01,
+            data: &[0x06]
+        })
+    );
+    assert_eq!(
+        structures.next(),
+        Some(AdStructure {
+            ad_type: 0x03,
+            data: &[0x0F, 0x18]
+        })
+    );
+    assert_eq!(
+        structures.next(),
+        Some(AdStructure {
+            ad_type: 0xFF,
+            data: &[]
+        })
+    );
+    // Length 0 ends the significant part.
+    assert_eq!(structures.next(), None);
+
+    // The last structure does not fit.
+    let truncated = [0x02, 0x01, 0x06, 0x05, 0x09, b'a'];
+    assert_eq!(AdStructures::new(&truncated).count(), 1);
+}
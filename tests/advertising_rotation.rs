@@ -0,0 +1,254 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::advertising_rotation::{AdvertisingRotation, Error, Payload, Timer};
+use hci::vendor::stm32wb::opcode;
+use hci::Opcode;
+use std::time::Duration;
+
+const LE_SET_SCAN_RESPONSE_DATA: Opcode = Opcode::new(0x08, 0x0009);
+
+// Flags, then manufacturer specific data.
+const MANUFACTURER: [u8; 9] = [0x02, 0x01, 0x06, 0x05, 0xFF, 0x59, 0x00, 0x01, 0x02];
+
+// Flags, then Eddystone service data.
+const EDDYSTONE: [u8; 8] = [0x02, 0x01, 0x06, 0x04, 0x16, 0xAA, 0xFE, 0x20];
+
+// Complete local name.
+const NAME: [u8; 4] = [0x03, 0x09, b'h', b'i'];
+
+struct MockTimer {
+    now: Duration,
+}
+
+impl Timer for MockTimer {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    async fn wait_until(&mut self, deadline: Duration) {
+        self.now = self.now.max(deadline);
+    }
+}
+
+fn update(data: &[u8]) -> Vec<u8> {
+    let mut params = vec![data.len() as u8];
+    params.extend_from_slice(data);
+    params
+}
+
+fn scan_response(data: &[u8]) -> Vec<u8> {
+    let mut params = vec![0; 32];
+    params[0] = data.len() as u8;
+    params[1..=data.len()].copy_from_slice(data);
+    params
+}
+
+fn ok(opcode: Opcode) -> Vec<u8> {
+    mock::command_complete_status(opcode, hci::Status::Success)
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[tokio::test]
+async fn rotates_payloads() {
+    let mut controller = MockController::new();
+    let mut timer = MockTimer { now: ms(0) };
+    let mut rotation: AdvertisingRotation<4> = AdvertisingRotation::new();
+    assert_eq!(
+        rotation.add(Payload {
+            advertising_data: &MANUFACTURER,
+            scan_response_data: Some(&NAME),
+            dwell: ms(100),
+        }),
+        Ok(0)
+    );
+    assert_eq!(
+        rotation.add(Payload {
+            advertising_data: &EDDYSTONE,
+            scan_response_data: None,
+            dwell: ms(300),
+        }),
+        Ok(1)
+    );
+
+    controller
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&MANUFACTURER))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA))
+        .expect(LE_SET_SCAN_RESPONSE_DATA, &scan_response(&NAME))
+        .respond(ok(LE_SET_SCAN_RESPONSE_DATA))
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0xFF])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&EDDYSTONE))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA))
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0x16])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&MANUFACTURER))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA));
+
+    assert_eq!(rotation.poll(&mut controller, &timer).await, Ok(Some(0)));
+    assert_eq!(rotation.deadline(), Some(ms(100)));
+    assert_eq!(controller.commands_written(), 2);
+
+    timer.now = ms(50);
+    assert_eq!(rotation.poll(&mut controller, &timer).await, Ok(None));
+    assert_eq!(controller.commands_written(), 2);
+
+    assert_eq!(
+        rotation.next(&mut controller, &mut timer).await,
+        Ok(Some(1))
+    );
+    assert_eq!(timer.now, ms(100));
+    assert_eq!(rotation.deadline(), Some(ms(400)));
+
+    // The scan response data has not changed.
+    assert_eq!(
+        rotation.next(&mut controller, &mut timer).await,
+        Ok(Some(0))
+    );
+    assert_eq!(rotation.current(), Some(0));
+    assert_eq!(rotation.deadline(), Some(ms(500)));
+    controller.verify();
+}
+
+#[tokio::test]
+async fn removes_and_stops() {
+    let mut controller = MockController::new();
+    let mut timer = MockTimer { now: ms(0) };
+    let mut rotation: AdvertisingRotation<2> = AdvertisingRotation::new();
+    let manufacturer = Payload {
+        advertising_data: &MANUFACTURER,
+        scan_response_data: None,
+        dwell: ms(100),
+    };
+    rotation.add(manufacturer).unwrap();
+    rotation
+        .add(Payload {
+            advertising_data: &EDDYSTONE,
+            ..manufacturer
+        })
+        .unwrap();
+    assert_eq!(rotation.add(manufacturer), Err(Error::Full));
+    assert_eq!(rotation.remove(1).unwrap().advertising_data, EDDYSTONE);
+    assert_eq!(rotation.len(), 1);
+
+    // A single payload is sent once.
+    controller
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&MANUFACTURER))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA))
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0x01])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0xFF])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE));
+    assert_eq!(
+        rotation.next(&mut controller, &mut timer).await,
+        Ok(Some(0))
+    );
+    assert_eq!(
+        rotation.next(&mut controller, &mut timer).await,
+        Ok(Some(0))
+    );
+    assert_eq!(timer.now, ms(100));
+    assert_eq!(controller.commands_written(), 1);
+
+    rotation.stop(&mut controller).await.unwrap();
+    assert_eq!(rotation.current(), None);
+    assert_eq!(rotation.deadline(), None);
+    controller.verify();
+
+    rotation.remove(0);
+    assert!(rotation.is_empty());
+    assert_eq!(rotation.poll(&mut controller, &timer).await, Ok(None));
+}
+
+#[tokio::test]
+async fn failed_switch_is_retried() {
+    let mut controller = MockController::new();
+    let mut timer = MockTimer { now: ms(0) };
+    let mut rotation: AdvertisingRotation<2> = AdvertisingRotation::new();
+    let manufacturer = Payload {
+        advertising_data: &MANUFACTURER,
+        scan_response_data: None,
+        dwell: ms(100),
+    };
+    rotation.add(manufacturer).unwrap();
+    rotation
+        .add(Payload {
+            advertising_data: &EDDYSTONE,
+            ..manufacturer
+        })
+        .unwrap();
+
+    controller
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&MANUFACTURER))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA))
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0xFF])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&EDDYSTONE))
+        .respond(mock::command_complete_status(
+            opcode::GAP_UPDATE_ADVERTISING_DATA,
+            hci::Status::InvalidParameters,
+        ))
+        // The manufacturer data is still on air.
+        .expect(opcode::GAP_DELETE_AD_TYPE, &[0xFF])
+        .respond(ok(opcode::GAP_DELETE_AD_TYPE))
+        .expect(opcode::GAP_UPDATE_ADVERTISING_DATA, &update(&EDDYSTONE))
+        .respond(ok(opcode::GAP_UPDATE_ADVERTISING_DATA));
+
+    assert_eq!(rotation.poll(&mut controller, &timer).await, Ok(Some(0)));
+    timer.now = ms(100);
+    assert_eq!(
+        rotation.poll(&mut controller, &timer).await,
+        Err(Error::CommandFailed(hci::Status::InvalidParameters))
+    );
+    assert_eq!(rotation.current(), Some(0));
+    assert_eq!(rotation.deadline(), Some(ms(100)));
+    assert_eq!(rotation.poll(&mut controller, &timer).await, Ok(Some(1)));
+    controller.verify();
+}
+
+#[test]
+fn rejects_bad_payloads() {
+    let mut rotation: AdvertisingRotation<2> = AdvertisingRotation::new();
+    let payload = Payload {
+        advertising_data: &MANUFACTURER,
+        scan_response_data: None,
+        dwell: ms(100),
+    };
+    assert_eq!(
+        rotation.add(Payload {
+            advertising_data: &[0; 32],
+            ..payload
+        }),
+        Err(Error::AdvertisingDataTooLong(32))
+    );
+    assert_eq!(
+        rotation.add(Payload {
+            scan_response_data: Some(&[0; 32]),
+            ..payload
+        }),
+        Err(Error::ScanResponseDataTooLong(32))
+    );
+    // 128-bit service data.
+    assert_eq!(
+        rotation.add(Payload {
+            advertising_data: &[0x02, 0x21, 0x00],
+            ..payload
+        }),
+        Err(Error::UnsupportedAdType(0x21))
+    );
+    assert_eq!(
+        rotation.add(Payload {
+            dwell: Duration::ZERO,
+            ..payload
+        }),
+        Err(Error::ZeroDwell)
+    );
+    assert!(rotation.is_empty());
+}
@@ -0,0 +1,136 @@
+#![feature(async_fn_in_trait)]
+
+extern crate stm32wb_hci as hci;
+
+use hci::types::{
+    AltBeacon, Beacon, EddystoneTlm, EddystoneUid, EddystoneUrl, EddystoneUrlError, IBeacon,
+};
+use std::time::Duration;
+
+fn encode(beacon: &Beacon) -> Vec<u8> {
+    let mut bytes = [0; 31];
+    let len = beacon.copy_into_slice(&mut bytes);
+    assert_eq!(len, beacon.len());
+    bytes[..len].to_vec()
+}
+
+#[test]
+fn ibeacon() {
+    let beacon = Beacon::IBeacon(IBeacon {
+        uuid: [
+            0xfb, 0x0b, 0x57, 0xa2, 0x82, 0x28, 0x44, 0xcd, 0x91, 0x3a, 0x94, 0xa1, 0x22, 0xba,
+            0x12, 0x06,
+        ],
+        major: 1,
+        minor: 2,
+        measured_power: -47,
+    });
+    let expected = [
+        0x02, 0x01, 0x06, 0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15, 0xfb, 0x0b, 0x57, 0xa2, 0x82, 0x28,
+        0x44, 0xcd, 0x91, 0x3a, 0x94, 0xa1, 0x22, 0xba, 0x12, 0x06, 0x00, 0x01, 0x00, 0x02, 0xd1,
+    ];
+    assert_eq!(encode(&beacon), expected);
+    assert_eq!(Beacon::from_advertising_data(&expected), Some(beacon));
+}
+
+#[test]
+fn altbeacon() {
+    let beacon = Beacon::AltBeacon(AltBeacon {
+        manufacturer_id: 0x0118,
+        id: [0x11; 20],
+        reference_rssi: -59,
+        manufacturer_reserved: 0x42,
+    });
+    let data = encode(&beacon);
+    assert_eq!(data.len(), 31);
+    assert_eq!(data[3..9], [0x1b, 0xff, 0x18, 0x01, 0xbe, 0xac]);
+    assert_eq!(Beacon::from_advertising_data(&data), Some(beacon));
+}
+
+#[test]
+fn eddystone_uid() {
+    let beacon = Beacon::EddystoneUid(EddystoneUid {
+        tx_power: -20,
+        namespace: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+        instance: [0xa, 0xb, 0xc, 0xd, 0xe, 0xf],
+    });
+    let expected = [
+        0x02, 0x01, 0x06, 0x03, 0x03, 0xaa, 0xfe, 0x17, 0x16, 0xaa, 0xfe, 0x00, 0xec, 0, 1, 2, 3,
+        4, 5, 6, 7, 8, 9, 0xa, 0xb, 0xc, 0xd, 0xe, 0xf, 0x00, 0x00,
+    ];
+    assert_eq!(encode(&beacon), expected);
+    assert_eq!(Beacon::from_advertising_data(&expected), Some(beacon));
+}
+
+#[test]
+fn eddystone_url() {
+    let url = EddystoneUrl::new(0, "https://www.rust-lang.org/").unwrap();
+    assert_eq!(url.encoded(), b"rust-lang\x01");
+    assert_eq!(url.to_string(), "https://www.rust-lang.org/");
+
+    let beacon = Beacon::EddystoneUrl(url);
+    let data = encode(&beacon);
+    assert_eq!(
+        data[7..],
+        [
+            0x10, 0x16, 0xaa, 0xfe, 0x10, 0x00, 0x01, 0x72, 0x75, 0x73, 0x74, 0x2d, 0x6c, 0x61,
+            0x6e, 0x67, 0x01,
+        ]
+    );
+    assert_eq!(Beacon::from_advertising_data(&data), Some(beacon));
+
+    let url = EddystoneUrl::new(-4, "http://example.com/a.info").unwrap();
+    assert_eq!(url.encoded(), b"example\x00a\x0b");
+    assert_eq!(url.to_string(), "http://example.com/a.info");
+
+    assert_eq!(
+        EddystoneUrl::new(0, "ftp://example.com"),
+        Err(EddystoneUrlError::BadUrlScheme)
+    );
+    assert_eq!(
+        EddystoneUrl::new(0, "https://a b.com"),
+        Err(EddystoneUrlError::BadUrlCharacter)
+    );
+    assert_eq!(
+        EddystoneUrl::new(0, "https://www.eighteen-characters.com"),
+        Err(EddystoneUrlError::UrlTooLong)
+    );
+}
+
+#[test]
+fn eddystone_tlm() {
+    let beacon = Beacon::EddystoneTlm(EddystoneTlm {
+        battery_voltage: 3_000,
+        temperature: Some(0x1780),
+        advertising_count: 1_000,
+        uptime: Duration::from_secs(60),
+    });
+    let data = encode(&beacon);
+    assert_eq!(
+        data[7..],
+        [
+            0x11, 0x16, 0xaa, 0xfe, 0x20, 0x00, 0x0b, 0xb8, 0x17, 0x80, 0x00, 0x00, 0x03, 0xe8,
+            0x00, 0x00, 0x02, 0x58,
+        ]
+    );
+    assert_eq!(Beacon::from_advertising_data(&data), Some(beacon));
+
+    // Without a temperature sensor.
+    let mut data = data;
+    data[15..17].copy_from_slice(&[0x80, 0x00]);
+    match Beacon::from_advertising_data(&data) {
+        Some(Beacon::EddystoneTlm(tlm)) => assert_eq!(tlm.temperature, None),
+        other => panic!("Did not get TLM frame: {:?}", other),
+    }
+}
+
+#[test]
+fn not_a_beacon() {
+    // Flags, then manufacturer specific data of Apple that is not an iBeacon.
+    let data = [0x02, 0x01, 0x06, 0x05, 0xff, 0x4c, 0x00, 0x10, 0x05];
+    assert_eq!(Beacon::from_advertising_data(&data), None);
+
+    // Encrypted TLM frame.
+    let data = [0x06, 0x16, 0xaa, 0xfe, 0x20, 0x01, 0x00];
+    assert_eq!(Beacon::from_advertising_data(&data), None);
+}
@@ -0,0 +1,221 @@
+#![feature(async_fn_in_trait)]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::command::ReturnParameters;
+use hci::event::Event;
+use hci::host::btsnoop::*;
+use hci::host::uart::{Error as UartError, Packet, UartHci};
+use hci::vendor::stm32wb::command::gap::{GapCommands, Role};
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::opcode;
+
+const GAP_INIT_COMMAND: [u8; 7] = [0x01, 0x8A, 0xFC, 0x03, 0x01, 0x00, 0x07];
+const GAP_INIT_COMPLETE: [u8; 13] = [
+    0x04, 0x0E, 0x0A, 0x01, 0x8A, 0xFC, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00,
+];
+
+fn field_capture(buffer: &mut [u8]) -> &[u8] {
+    let mut sink = BufferSink::new(buffer);
+    write_file_header(&mut sink);
+    write_record(&mut sink, Direction::Sent, 1_000, &GAP_INIT_COMMAND);
+    write_record(&mut sink, Direction::Received, 2_000, &GAP_INIT_COMPLETE);
+    assert!(!sink.overflowed());
+
+    let len = sink.as_slice().len();
+    &buffer[..len]
+}
+
+#[test]
+fn file_header() {
+    let mut buffer = [0; 16];
+    let mut sink = BufferSink::new(&mut buffer);
+    write_file_header(&mut sink);
+    assert_eq!(
+        sink.as_slice(),
+        [
+            b'b', b't', b's', b'n', b'o', b'o', b'p', 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x03, 0xEA
+        ]
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn vec_sink() {
+    let mut sink = Vec::new();
+    write_file_header(&mut sink);
+    write_record(&mut sink, Direction::Sent, 1_000, &GAP_INIT_COMMAND);
+    write_record(&mut sink, Direction::Received, 2_000, &GAP_INIT_COMPLETE);
+
+    let mut buffer = [0; 128];
+    assert_eq!(sink, field_capture(&mut buffer));
+}
+
+#[test]
+fn record_layout() {
+    let mut buffer = [0; 64];
+    let mut sink = BufferSink::new(&mut buffer);
+    write_record(&mut sink, Direction::Received, 0, &[0x04, 0x0F, 0x00]);
+    assert_eq!(
+        sink.as_slice(),
+        [
+            0x00, 0x00, 0x00, 0x03, // original length
+            0x00, 0x00, 0x00, 0x03, // included length
+            0x00, 0x00, 0x00, 0x03, // received event
+            0x00, 0x00, 0x00, 0x00, // drops
+            0x00, 0xDC, 0xDD, 0xB3, 0x0F, 0x2F, 0x80, 0x00, // Unix epoch
+            0x04, 0x0F, 0x00,
+        ]
+    );
+}
+
+#[test]
+fn buffer_sink_overflow() {
+    let mut buffer = [0; 20];
+    let mut sink = BufferSink::new(&mut buffer);
+    write_file_header(&mut sink);
+    write_record(&mut sink, Direction::Sent, 0, &GAP_INIT_COMMAND);
+    assert!(sink.overflowed());
+    assert_eq!(sink.as_slice().len(), FILE_HEADER_LENGTH);
+}
+
+#[test]
+fn reader_iterates_records() {
+    let mut buffer = [0; 128];
+    let capture = field_capture(&mut buffer);
+
+    let records: Vec<Record> = BtSnoopReader::new(capture)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        records,
+        [
+            Record {
+                direction: Direction::Sent,
+                timestamp_micros: 1_000,
+                packet: &GAP_INIT_COMMAND,
+            },
+            Record {
+                direction: Direction::Received,
+                timestamp_micros: 2_000,
+                packet: &GAP_INIT_COMPLETE,
+            },
+        ]
+    );
+    assert_eq!(
+        records[0].command(),
+        Some((opcode::GAP_INIT, &[0x01, 0x00, 0x07][..]))
+    );
+    assert_eq!(records[1].command(), None);
+}
+
+#[test]
+fn reader_errors() {
+    assert_eq!(
+        BtSnoopReader::new(b"btsnoop").err(),
+        Some(Error::Truncated(0))
+    );
+    assert_eq!(
+        BtSnoopReader::new(b"btsnoo\0\0\0\0\0\x01\0\0\x03\xEA").err(),
+        Some(Error::BadMagic)
+    );
+    assert_eq!(
+        BtSnoopReader::new(b"btsnoop\0\0\0\0\x02\0\0\x03\xEA").err(),
+        Some(Error::UnsupportedVersion(2))
+    );
+    assert_eq!(
+        BtSnoopReader::new(b"btsnoop\0\0\0\0\x01\0\0\x03\xE9").err(),
+        Some(Error::UnsupportedDatalink(1001))
+    );
+
+    let mut buffer = [0; 128];
+    let capture = field_capture(&mut buffer);
+    let truncated = &capture[..capture.len() - 1];
+    let mut reader = BtSnoopReader::new(truncated).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(
+        reader.next().unwrap(),
+        Err(Error::Truncated(
+            FILE_HEADER_LENGTH + 24 + GAP_INIT_COMMAND.len()
+        ))
+    );
+    assert_eq!(reader.next(), None);
+}
+
+#[tokio::test]
+async fn replay_reproduces_capture() {
+    let mut buffer = [0; 128];
+    let capture = field_capture(&mut buffer);
+    let mut replay = Replay::new(capture).unwrap();
+
+    replay.init(Role::PERIPHERAL, false, 7).await;
+    match replay.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(cc)) => match cc.return_params {
+            ReturnParameters::Vendor(VendorReturnParameters::GapInit(params)) => {
+                assert_eq!(params.service_handle.0, 0x0001);
+                assert_eq!(params.dev_name_handle.0, 0x0002);
+                assert_eq!(params.appearance_handle.0, 0x0003);
+            }
+            other => panic!("unexpected return parameters {:?}", other),
+        },
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert!(replay.is_done());
+    assert_eq!(replay.divergence(), None);
+
+    match replay.read().await {
+        Err(UartError::BadPacketType(0)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn replay_reports_divergence() {
+    let mut buffer = [0; 128];
+    let capture = field_capture(&mut buffer);
+    let mut replay = Replay::new(capture).unwrap();
+
+    replay.init(Role::PERIPHERAL, false, 8).await;
+    replay.init(Role::PERIPHERAL, false, 7).await;
+    assert_eq!(replay.commands_written(), 2);
+    assert_eq!(
+        replay.divergence(),
+        Some(Divergence {
+            index: 0,
+            expected: Some(opcode::GAP_INIT),
+            actual: opcode::GAP_INIT,
+        })
+    );
+}
+
+#[tokio::test]
+async fn recorder_captures_traffic() {
+    let mut field = [0; 128];
+    let capture = field_capture(&mut field);
+
+    let mut buffer = [0; 128];
+    let mut recorder = Recorder::with_clock(
+        Replay::new(capture).unwrap(),
+        BufferSink::new(&mut buffer),
+        || 1_000,
+    );
+    recorder.init(Role::PERIPHERAL, false, 7).await;
+    recorder.read().await.unwrap();
+
+    let (replay, sink) = recorder.into_inner();
+    assert_eq!(replay.divergence(), None);
+    assert!(!sink.overflowed());
+
+    let records: Vec<Record> = BtSnoopReader::new(sink.as_slice())
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].direction, Direction::Sent);
+    assert_eq!(records[0].packet, GAP_INIT_COMMAND);
+    assert_eq!(records[1].direction, Direction::Received);
+    assert_eq!(records[1].packet, GAP_INIT_COMPLETE);
+    assert_eq!(records[1].timestamp_micros, 1_000);
+}
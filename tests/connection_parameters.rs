@@ -0,0 +1,373 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::{ConnectionRole, Event};
+use hci::mock::{self, MockController};
+use hci::types::{ConnectionIntervalBuilder, ExpectedConnectionLength};
+use hci::vendor::stm32wb::connection_parameters::{
+    Acceptable, Backoff, ConnectionParameters, Error, Outcome, Policy, Profile,
+};
+use hci::vendor::stm32wb::event::Stm32Wb5xEvent;
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Status};
+use std::time::Duration;
+
+const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+
+fn policy() -> Policy {
+    Policy {
+        fast: ConnectionIntervalBuilder::new()
+            .with_range(Duration::from_millis(10), Duration::from_millis(15))
+            .with_latency(0)
+            .with_supervision_timeout(Duration::from_secs(2))
+            .build()
+            .unwrap(),
+        idle: ConnectionIntervalBuilder::new()
+            .with_range(Duration::from_millis(100), Duration::from_millis(200))
+            .with_latency(4)
+            .with_supervision_timeout(Duration::from_secs(6))
+            .build()
+            .unwrap(),
+        expected_connection_length: ExpectedConnectionLength::new(
+            Duration::from_micros(625),
+            Duration::from_micros(1_250),
+        )
+        .unwrap(),
+        acceptable: Acceptable {
+            interval: (Duration::from_micros(7_500), Duration::from_millis(500)),
+            max_latency: 10,
+            supervision_timeout: (Duration::from_secs(1), Duration::from_secs(10)),
+        },
+        backoff: Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(4),
+            max_attempts: 3,
+        },
+    }
+}
+
+// Fast profile: 8 and 12 units of 1.25 ms, no latency, 200 units of 10 ms, then the expected
+// connection length of 1 and 2 units of 0.625 ms.
+const FAST: [u8; 12] = [
+    0x08, 0x00, 0x0C, 0x00, 0x00, 0x00, 0xC8, 0x00, 0x01, 0x00, 0x02, 0x00,
+];
+
+fn connected(conn_handle: ConnectionHandle, role: ConnectionRole) -> Event<Stm32Wb5xEvent> {
+    mock::parse_event(&mock::le_connection_complete(
+        conn_handle,
+        role,
+        BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])),
+        Duration::from_millis(50),
+        0,
+        Duration::from_secs(5),
+    ))
+}
+
+fn update_complete(status: u8, interval: u16) -> Event<Stm32Wb5xEvent> {
+    let mut params = vec![0x03, status];
+    params.extend_from_slice(&CONN.0.to_le_bytes());
+    params.extend_from_slice(&interval.to_le_bytes());
+    params.extend_from_slice(&[0x00, 0x00, 0x58, 0x02]);
+    mock::parse_event(&mock::event(0x3E, &params))
+}
+
+fn peer_request(identifier: u8, interval: [u8; 8]) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&[11, identifier, 8, 0]);
+    params.extend_from_slice(&interval);
+    mock::parse_event(&mock::vendor_event(0x0802, &params))
+}
+
+fn peer_response(result: u8) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&[6, 0x13, 1, 2, 0, result, 0]);
+    mock::parse_event(&mock::vendor_event(0x0800, &params))
+}
+
+fn status(
+    opcode: hci::Opcode,
+    status: Status<hci::vendor::stm32wb::event::Status>,
+) -> Event<Stm32Wb5xEvent> {
+    mock::parse_event(&mock::command_status(opcode, status))
+}
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+#[tokio::test]
+async fn central_answers_peer_requests() {
+    let mut controller = MockController::new();
+    let mut parameters: ConnectionParameters<2> = ConnectionParameters::new(policy());
+    let connected = connected(CONN, ConnectionRole::Central);
+    parameters
+        .process(&mut controller, &connected, secs(0))
+        .await
+        .unwrap();
+
+    // 30 to 50 ms, latency 2, 4 s.
+    let acceptable = [0x18, 0x00, 0x28, 0x00, 0x02, 0x00, 0x90, 0x01];
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&acceptable);
+    params.extend_from_slice(&[0x01, 0x00, 0x02, 0x00, 7, 1]);
+    controller.expect(opcode::L2CAP_CONN_PARAM_UPDATE_RESP, &params);
+    let outcome = parameters
+        .process(&mut controller, &peer_request(7, acceptable), secs(0))
+        .await;
+    assert!(matches!(
+        outcome,
+        Ok(Some(Outcome::Answered {
+            conn_handle: CONN,
+            accepted: true,
+            ..
+        }))
+    ));
+
+    // Latency 20 is too large.
+    let too_slow = [0x18, 0x00, 0x28, 0x00, 0x14, 0x00, 0x90, 0x01];
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&too_slow);
+    params.extend_from_slice(&[0x01, 0x00, 0x02, 0x00, 8, 0]);
+    controller.expect(opcode::L2CAP_CONN_PARAM_UPDATE_RESP, &params);
+    let outcome = parameters
+        .process(&mut controller, &peer_request(8, too_slow), secs(0))
+        .await;
+    assert!(matches!(
+        outcome,
+        Ok(Some(Outcome::Answered {
+            accepted: false,
+            ..
+        }))
+    ));
+    controller.verify();
+
+    // The central updates the connection after accepting.
+    let outcome = parameters
+        .process(&mut controller, &update_complete(0x00, 0x0020), secs(1))
+        .await;
+    assert!(matches!(
+        outcome,
+        Ok(Some(Outcome::Updated {
+            conn_handle: CONN,
+            profile: None,
+            ..
+        }))
+    ));
+    assert_eq!(
+        parameters.conn_interval(CONN).unwrap().interval(),
+        Duration::from_millis(40)
+    );
+}
+
+#[tokio::test]
+async fn central_moves_between_profiles() {
+    let mut controller = MockController::new();
+    let mut parameters: ConnectionParameters<2> = ConnectionParameters::new(policy());
+    let connected = connected(CONN, ConnectionRole::Central);
+    parameters
+        .process(&mut controller, &connected, secs(0))
+        .await
+        .unwrap();
+    assert_eq!(parameters.profile(CONN), None);
+
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&FAST);
+    controller
+        .expect(opcode::GAP_START_CONNECTION_UPDATE, &params)
+        .expect_opcode(opcode::GAP_START_CONNECTION_UPDATE);
+
+    parameters
+        .set_profile(&mut controller, CONN, Profile::Fast)
+        .await
+        .unwrap();
+    let started = status(opcode::GAP_START_CONNECTION_UPDATE, Status::Success);
+    assert!(matches!(
+        parameters.process(&mut controller, &started, secs(0)).await,
+        Ok(None)
+    ));
+    let outcome = parameters
+        .process(&mut controller, &update_complete(0x00, 0x000C), secs(0))
+        .await;
+    assert!(matches!(
+        outcome,
+        Ok(Some(Outcome::Updated {
+            conn_handle: CONN,
+            profile: Some(Profile::Fast),
+            ..
+        }))
+    ));
+    assert_eq!(parameters.profile(CONN), Some(Profile::Fast));
+
+    // Already in use: nothing is sent.
+    parameters
+        .set_profile(&mut controller, CONN, Profile::Fast)
+        .await
+        .unwrap();
+    assert_eq!(controller.commands_written(), 1);
+
+    parameters
+        .set_profile(&mut controller, CONN, Profile::Idle)
+        .await
+        .unwrap();
+    controller.verify();
+    assert_eq!(parameters.profile(CONN), Some(Profile::Fast));
+}
+
+#[tokio::test]
+async fn peripheral_retries_with_backoff() {
+    let mut controller = MockController::new();
+    let mut parameters: ConnectionParameters<2> = ConnectionParameters::new(policy());
+    let connected = connected(CONN, ConnectionRole::Peripheral);
+    parameters
+        .process(&mut controller, &connected, secs(0))
+        .await
+        .unwrap();
+
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&FAST[..8]);
+    for _ in 0..3 {
+        controller.expect(opcode::L2CAP_CONN_PARAM_UPDATE_REQ, &params);
+    }
+
+    parameters
+        .set_profile(&mut controller, CONN, Profile::Fast)
+        .await
+        .unwrap();
+    assert!(matches!(
+        parameters
+            .process(&mut controller, &peer_response(0x01), secs(10))
+            .await,
+        Ok(None)
+    ));
+    assert_eq!(parameters.next_retry(), Some(secs(11)));
+    parameters
+        .poll(&mut controller, Duration::from_millis(10_500))
+        .await;
+    assert_eq!(controller.commands_written(), 1);
+    parameters.poll(&mut controller, secs(11)).await;
+    assert_eq!(controller.commands_written(), 2);
+    assert_eq!(parameters.next_retry(), None);
+
+    // The central did not answer.
+    let timeout = mock::parse_event(&mock::vendor_event(0x0801, &[0x01, 0x08, 0x00]));
+    assert!(matches!(
+        parameters
+            .process(&mut controller, &timeout, secs(20))
+            .await,
+        Ok(None)
+    ));
+    assert_eq!(parameters.next_retry(), Some(secs(22)));
+    parameters.poll(&mut controller, secs(22)).await;
+
+    let failed = status(
+        opcode::L2CAP_CONN_PARAM_UPDATE_REQ,
+        Status::CommandDisallowed,
+    );
+    assert!(matches!(
+        parameters.process(&mut controller, &failed, secs(23)).await,
+        Ok(Some(Outcome::GaveUp {
+            conn_handle: CONN,
+            profile: Profile::Fast,
+        }))
+    ));
+    assert_eq!(parameters.next_retry(), None);
+    assert_eq!(parameters.profile(CONN), None);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn matches_statuses_of_retries_in_send_order() {
+    const OTHER: ConnectionHandle = ConnectionHandle(0x0802);
+    let mut controller = MockController::new();
+    let mut parameters: ConnectionParameters<2> = ConnectionParameters::new(policy());
+    for conn_handle in [CONN, OTHER] {
+        let connected = connected(conn_handle, ConnectionRole::Central);
+        parameters
+            .process(&mut controller, &connected, secs(0))
+            .await
+            .unwrap();
+    }
+
+    let update = |conn_handle: ConnectionHandle| {
+        let mut params = conn_handle.0.to_le_bytes().to_vec();
+        params.extend_from_slice(&FAST);
+        params
+    };
+    for conn_handle in [CONN, OTHER, CONN, OTHER, OTHER] {
+        controller.expect(opcode::GAP_START_CONNECTION_UPDATE, &update(conn_handle));
+    }
+
+    let started = status(opcode::GAP_START_CONNECTION_UPDATE, Status::Success);
+    let failed = status(
+        opcode::GAP_START_CONNECTION_UPDATE,
+        Status::CommandDisallowed,
+    );
+    for conn_handle in [CONN, OTHER] {
+        parameters
+            .set_profile(&mut controller, conn_handle, Profile::Fast)
+            .await
+            .unwrap();
+    }
+    for event in [&failed, &failed] {
+        parameters
+            .process(&mut controller, event, secs(0))
+            .await
+            .unwrap();
+    }
+    assert_eq!(parameters.next_retry(), Some(secs(1)));
+
+    // Both retries are sent at once. Only the second one fails.
+    parameters.poll(&mut controller, secs(1)).await;
+    assert_eq!(controller.commands_written(), 4);
+    for event in [&started, &failed] {
+        parameters
+            .process(&mut controller, event, secs(1))
+            .await
+            .unwrap();
+    }
+    assert_eq!(parameters.next_retry(), Some(secs(3)));
+    parameters.poll(&mut controller, secs(3)).await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn tracks_connections() {
+    let mut controller = MockController::new();
+    let mut parameters: ConnectionParameters<1> = ConnectionParameters::new(policy());
+    let connected_first = connected(CONN, ConnectionRole::Central);
+    let connected_second = connected(ConnectionHandle(0x0802), ConnectionRole::Central);
+    parameters
+        .process(&mut controller, &connected_first, secs(0))
+        .await
+        .unwrap();
+    assert!(matches!(
+        parameters
+            .process(&mut controller, &connected_second, secs(0))
+            .await,
+        Err(Error::TooManyConnections(ConnectionHandle(0x0802)))
+    ));
+
+    let disconnected = mock::parse_event(&mock::disconnection_complete(
+        CONN,
+        Status::RemoteTerminationByUser,
+    ));
+    parameters
+        .process(&mut controller, &disconnected, secs(0))
+        .await
+        .unwrap();
+    assert_eq!(
+        parameters
+            .set_profile(&mut controller, CONN, Profile::Idle)
+            .await,
+        Err(Error::UnknownConnection(CONN))
+    );
+    assert_eq!(controller.commands_written(), 0);
+
+    let backoff = policy().backoff;
+    assert_eq!(backoff.delay(1), secs(1));
+    assert_eq!(backoff.delay(2), secs(2));
+    assert_eq!(backoff.delay(3), secs(4));
+    assert_eq!(backoff.delay(10), secs(4));
+}
@@ -0,0 +1,180 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "embedded-io-async")]
+
+extern crate stm32wb_hci as hci;
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use hci::event::Event;
+use hci::host::embedded_io::{Error, IoController};
+use hci::host::uart::{Error as UartError, Packet, UartHci};
+use hci::host::HostHci;
+use hci::Controller;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct StreamError;
+
+impl embedded_io_async::Error for StreamError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+// Stream that returns at most `chunk` bytes per read, like a UART driver handing over whatever
+// its DMA buffer holds.
+struct Stream {
+    rx: Vec<u8>,
+    rx_pos: usize,
+    chunk: usize,
+    tx: Vec<u8>,
+    flushes: usize,
+}
+
+impl Stream {
+    fn new(rx: &[u8], chunk: usize) -> Stream {
+        Stream {
+            rx: rx.to_vec(),
+            rx_pos: 0,
+            chunk,
+            tx: Vec::new(),
+            flushes: 0,
+        }
+    }
+}
+
+impl ErrorType for Stream {
+    type Error = StreamError;
+}
+
+impl Read for Stream {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, StreamError> {
+        let count = buf.len().min(self.chunk).min(self.rx.len() - self.rx_pos);
+        buf[..count].copy_from_slice(&self.rx[self.rx_pos..self.rx_pos + count]);
+        self.rx_pos += count;
+        Ok(count)
+    }
+}
+
+impl Write for Stream {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, StreamError> {
+        self.tx.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), StreamError> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+const COMMAND_COMPLETE: [u8; 7] = [0x04, 0x0E, 0x04, 0x01, 0x03, 0x0C, 0x00];
+const DISCONNECTION_COMPLETE: [u8; 7] = [0x04, 0x05, 0x04, 0x00, 0x01, 0x02, 0x13];
+
+#[tokio::test]
+async fn writes_framed_commands() {
+    let mut controller: IoController<Stream> = IoController::new(Stream::new(&[], 1));
+    controller.reset().await;
+    controller
+        .controller_write(hci::Opcode(0xFC8A), &[0x01, 0x00, 0x07])
+        .await;
+
+    assert_eq!(controller.take_error(), None);
+    let stream = controller.into_inner();
+    assert_eq!(
+        stream.tx,
+        [0x01, 0x03, 0x0C, 0x00, 0x01, 0x8A, 0xFC, 0x03, 0x01, 0x00, 0x07]
+    );
+    assert_eq!(stream.flushes, 2);
+}
+
+#[tokio::test]
+async fn reassembles_split_events() {
+    let mut rx = COMMAND_COMPLETE.to_vec();
+    rx.extend_from_slice(&DISCONNECTION_COMPLETE);
+    let mut controller: IoController<Stream> = IoController::new(Stream::new(&rx, 3));
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(_)) => (),
+        other => panic!("unexpected packet {:?}", other),
+    }
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::DisconnectionComplete(event)) => {
+            assert_eq!(event.conn_handle, hci::ConnectionHandle(0x0201));
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert_eq!(controller.take_error(), None);
+}
+
+#[tokio::test]
+async fn partial_reads_by_the_caller() {
+    let mut rx = COMMAND_COMPLETE.to_vec();
+    rx.extend_from_slice(&DISCONNECTION_COMPLETE);
+    let controller: IoController<Stream> = IoController::new(Stream::new(&rx, 64));
+
+    let mut buf = [0; 3];
+    controller.controller_read_into(&mut buf).await;
+    assert_eq!(buf, COMMAND_COMPLETE[..3]);
+    assert_eq!(controller.pending(), 4);
+
+    // The rest of the packet, without any of the next one.
+    let mut buf = [0xFF; 8];
+    controller.controller_read_into(&mut buf).await;
+    assert_eq!(buf, [0x01, 0x03, 0x0C, 0x00, 0, 0, 0, 0]);
+
+    let mut buf = [0; 7];
+    controller.controller_read_into(&mut buf).await;
+    assert_eq!(buf, DISCONNECTION_COMPLETE);
+    assert_eq!(controller.pending(), 0);
+}
+
+#[tokio::test]
+async fn reassembles_acl_data() {
+    let acl = [0x02, 0x01, 0x20, 0x03, 0x00, 0xAA, 0xBB, 0xCC];
+    let controller: IoController<Stream> = IoController::new(Stream::new(&acl, 2));
+
+    let mut buf = [0; 16];
+    controller.controller_read_into(&mut buf).await;
+    assert_eq!(buf[..8], acl);
+    assert_eq!(buf[8..], [0; 8]);
+}
+
+#[tokio::test]
+async fn drops_packets_longer_than_the_buffer() {
+    let mut rx = vec![0x04, 0xFF, 0x10];
+    rx.extend_from_slice(&[0x55; 0x10]);
+    rx.extend_from_slice(&COMMAND_COMPLETE);
+    let mut controller: IoController<Stream, 16> = IoController::new(Stream::new(&rx, 5));
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(_)) => (),
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert_eq!(controller.take_error(), Some(Error::PacketTooLong(19)));
+    assert_eq!(controller.take_error(), None);
+}
+
+#[tokio::test]
+async fn unknown_packet_type() {
+    let mut controller: IoController<Stream> = IoController::new(Stream::new(&[0x07, 0x00], 1));
+
+    match controller.read().await {
+        Err(UartError::BadPacketType(0x07)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(
+        controller.take_error(),
+        Some(Error::UnknownPacketType(0x07))
+    );
+}
+
+#[tokio::test]
+async fn end_of_stream() {
+    let mut controller: IoController<Stream> =
+        IoController::new(Stream::new(&COMMAND_COMPLETE[..5], 4));
+
+    match controller.read().await {
+        Err(UartError::BadPacketType(0x00)) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(controller.take_error(), Some(Error::UnexpectedEof));
+}
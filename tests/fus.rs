@@ -0,0 +1,338 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::host::uart::{Packet, UartHci};
+use hci::mock::{self, read_vendor_return, MockController};
+use hci::vendor::stm32wb::command::shci::*;
+use hci::vendor::stm32wb::event::command::{
+    FusErrorCode, FusState, ReturnParameters as VendorReturnParameters, ShciFusUserKey,
+};
+use hci::vendor::stm32wb::event::Stm32Wb5xError;
+use hci::vendor::stm32wb::fus::{self, Action, Step, Upgrade};
+use hci::vendor::stm32wb::opcode;
+
+fn get_state(state: u8, error_code: u8) -> Vec<u8> {
+    mock::command_complete(opcode::SHCI_FUS_GET_STATE, &[state, error_code])
+}
+
+fn ready_fus() -> Vec<u8> {
+    mock::vendor_event(0x9200, &[0x01])
+}
+
+fn ready_wireless() -> Vec<u8> {
+    mock::vendor_event(0x9200, &[0x00])
+}
+
+#[tokio::test]
+async fn fus_commands() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_FW_UPGRADE, &[])
+        .expect(
+            opcode::SHCI_FUS_FW_UPGRADE,
+            &[0x00, 0x00, 0x08, 0x08, 0x00, 0x00, 0x0C, 0x08],
+        )
+        .expect(opcode::SHCI_FUS_FW_DELETE, &[])
+        .expect(opcode::SHCI_FUS_UPDATE_AUTH_KEY, &[0x03, 0xAA, 0xBB, 0xCC])
+        .expect(opcode::SHCI_FUS_LOCK_AUTH_KEY, &[])
+        .expect(opcode::SHCI_FUS_LOAD_USER_KEY, &[0x02])
+        .expect(opcode::SHCI_FUS_LOCK_USER_KEY, &[0x03])
+        .expect(opcode::SHCI_FUS_UNLOAD_USER_KEY, &[0x04])
+        .expect(opcode::SHCI_FUS_START_WIRELESS_STACK, &[]);
+
+    controller.c2_fus_fw_upgrade(None).await;
+    controller
+        .c2_fus_fw_upgrade(Some(FirmwareLocation {
+            source_address: 0x0808_0000,
+            destination_address: 0x080C_0000,
+        }))
+        .await;
+    controller.c2_fus_fw_delete().await;
+    controller
+        .c2_fus_update_auth_key(&[0xAA, 0xBB, 0xCC])
+        .await
+        .unwrap();
+    controller.c2_fus_lock_auth_key().await;
+    controller.c2_fus_load_user_key(2).await;
+    controller.c2_fus_lock_user_key(3).await;
+    controller.c2_fus_unload_user_key(4).await;
+    controller.c2_fus_start_wireless_stack().await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn fus_key_validation() {
+    let mut controller = MockController::new();
+
+    assert_eq!(
+        controller.c2_fus_update_auth_key(&[0; 65]).await,
+        Err(Error::AuthKeyTooLong(65))
+    );
+    assert_eq!(
+        controller
+            .c2_fus_store_user_key(&UserKey {
+                key_type: UserKeyType::Simple,
+                key: &[0; 24],
+            })
+            .await,
+        Err(Error::BadUserKeyLength(UserKeyType::Simple, 24))
+    );
+    assert_eq!(
+        controller
+            .c2_fus_store_user_key(&UserKey {
+                key_type: UserKeyType::Encrypted,
+                key: &[0; 8],
+            })
+            .await,
+        Err(Error::BadUserKeyLength(UserKeyType::Encrypted, 8))
+    );
+    assert_eq!(controller.commands_written(), 0);
+}
+
+#[tokio::test]
+async fn fus_store_user_key() {
+    let mut expected = vec![0x03, 28];
+    expected.extend_from_slice(&[0x5A; 28]);
+
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_STORE_USER_KEY, &expected)
+        .respond(mock::command_complete(
+            opcode::SHCI_FUS_STORE_USER_KEY,
+            &[0x00, 0x07],
+        ));
+
+    controller
+        .c2_fus_store_user_key(&UserKey {
+            key_type: UserKeyType::Encrypted,
+            key: &[0x5A; 28],
+        })
+        .await
+        .unwrap();
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciFusStoreUserKey(ShciFusUserKey { status, key_index }) => {
+            assert_eq!(status, hci::Status::Success);
+            assert_eq!(key_index, 7);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn fus_state() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(get_state(0x13, 0x00))
+        .push_event(get_state(0xFF, 0x03))
+        .push_event(get_state(0x42, 0x00))
+        .push_event(get_state(0x00, 0x42));
+
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciFusGetState(state) => {
+            assert_eq!(state.state, FusState::FirmwareUpgradeOngoing(3));
+            assert!(state.state.is_ongoing());
+            assert_eq!(state.error_code, FusErrorCode::NoError);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciFusGetState(state) => {
+            assert_eq!(state.state, FusState::Error);
+            assert!(!state.state.is_ongoing());
+            assert_eq!(state.error_code, FusErrorCode::ImageNotAuthentic);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::UnknownFusState(0x42),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::UnknownFusErrorCode(0x42),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn upgrade_from_wireless_stack() {
+    let mut controller = MockController::new();
+    controller
+        // The wireless stack answers, then CPU2 restarts into the FUS.
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0xFF, 0xFE))
+        .respond(ready_fus())
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_FW_UPGRADE, &[])
+        .respond(mock::command_complete_status(
+            opcode::SHCI_FUS_FW_UPGRADE,
+            hci::Status::Success,
+        ))
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x10, 0x00))
+        // CPU2 restarts in the middle of the upgrade, dropping the command.
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(ready_fus())
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_START_WIRELESS_STACK, &[])
+        .respond(ready_wireless());
+
+    let mut upgrade = Upgrade::new(None);
+    upgrade.run(&mut controller).await.unwrap();
+    assert_eq!(upgrade.step(), Step::Done);
+    assert_eq!(upgrade.polls(), 5);
+    assert_eq!(upgrade.restarts(), 2);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn upgrade_with_location_from_fus() {
+    let location = FirmwareLocation {
+        source_address: 0x0808_0000,
+        destination_address: 0x080C_0000,
+    };
+
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(
+            opcode::SHCI_FUS_FW_UPGRADE,
+            &[0x00, 0x00, 0x08, 0x08, 0x00, 0x00, 0x0C, 0x08],
+        )
+        // CPU2 restarts before answering.
+        .respond(ready_fus())
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_START_WIRELESS_STACK, &[])
+        .respond(ready_wireless());
+
+    let mut upgrade = Upgrade::new(Some(location));
+    upgrade.run(&mut controller).await.unwrap();
+    assert_eq!(upgrade.restarts(), 1);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn upgrade_reports_fus_errors() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_FW_UPGRADE, &[])
+        .respond(mock::command_complete_status(
+            opcode::SHCI_FUS_FW_UPGRADE,
+            hci::Status::Success,
+        ))
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0xFF, 0x01));
+
+    let mut upgrade = Upgrade::new(None);
+    assert_eq!(
+        upgrade.run(&mut controller).await,
+        Err(fus::Error::Fus(FusErrorCode::ImageNotFound))
+    );
+    assert_eq!(upgrade.step(), Step::Upgrading);
+}
+
+#[tokio::test]
+async fn upgrade_reports_fus_errors_before_upgrading() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0xFF, 0x03));
+
+    let mut upgrade = Upgrade::new(None);
+    assert_eq!(
+        upgrade.run(&mut controller).await,
+        Err(fus::Error::Fus(FusErrorCode::ImageNotAuthentic))
+    );
+    assert_eq!(upgrade.step(), Step::EnteringFus);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn upgrade_reports_rejected_commands() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_FW_UPGRADE, &[])
+        .respond(mock::command_complete_status(
+            opcode::SHCI_FUS_FW_UPGRADE,
+            hci::Status::UnspecifiedError,
+        ));
+
+    assert_eq!(
+        Upgrade::new(None).run(&mut controller).await,
+        Err(fus::Error::CommandFailed(hci::Status::UnspecifiedError))
+    );
+}
+
+#[tokio::test]
+async fn upgrade_without_wireless_stack() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x30, 0x00))
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x00, 0x00))
+        .expect(opcode::SHCI_FUS_START_WIRELESS_STACK, &[])
+        .respond(ready_fus());
+
+    assert_eq!(
+        Upgrade::new(None).run(&mut controller).await,
+        Err(fus::Error::NoWirelessStack)
+    );
+}
+
+#[tokio::test]
+async fn upgrade_gives_up_after_max_polls() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x11, 0x00))
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x12, 0x00))
+        .expect(opcode::SHCI_FUS_GET_STATE, &[])
+        .respond(get_state(0x13, 0x00));
+
+    let mut upgrade = Upgrade::new(None).with_max_polls(3);
+    assert_eq!(
+        upgrade.run(&mut controller).await,
+        Err(fus::Error::TooManyPolls)
+    );
+    assert_eq!(upgrade.polls(), 3);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn step_by_step() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(get_state(0x00, 0x00))
+        .push_event(mock::disconnection_complete(
+            hci::ConnectionHandle(0x0001),
+            hci::Status::Success,
+        ));
+
+    let mut upgrade = Upgrade::new(None);
+    assert_eq!(upgrade.step(), Step::NotStarted);
+    assert_eq!(upgrade.start(), Action::GetState);
+
+    let Packet::Event(event) = controller.read().await.unwrap();
+    assert_eq!(upgrade.handle(&event), Ok(Action::FwUpgrade(None)));
+    assert_eq!(upgrade.step(), Step::StartingUpgrade);
+
+    // Unrelated events are ignored.
+    let Packet::Event(event) = controller.read().await.unwrap();
+    assert_eq!(upgrade.handle(&event), Ok(Action::Wait));
+    assert_eq!(upgrade.step(), Step::StartingUpgrade);
+}
@@ -0,0 +1,148 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::mock::MockController;
+use hci::vendor::stm32wb::command::gatt::{
+    AddCharacteristicParameters, AddServiceParameters, CharacteristicEvent,
+    CharacteristicPermission, CharacteristicProperty, EncryptionKeySize, GattCommands,
+    IncludeServiceParameters, Range, ReadByTypeParameters, ServiceType, Uuid,
+};
+use hci::vendor::stm32wb::event::{AttError, AttributeHandle};
+use hci::vendor::stm32wb::opcode;
+
+const UUID_128: [u8; 16] = [
+    0x9E, 0xCA, 0xDC, 0x24, 0x0E, 0xE5, 0xA9, 0xE0, 0x93, 0xF3, 0xA3, 0xB5, 0x01, 0x00, 0x40, 0x6E,
+];
+
+fn characteristic(uuid: Uuid) -> AddCharacteristicParameters {
+    AddCharacteristicParameters {
+        service_handle: AttributeHandle(0x0010),
+        characteristic_uuid: uuid,
+        characteristic_value_len: 20,
+        characteristic_properties: CharacteristicProperty::READ | CharacteristicProperty::NOTIFY,
+        security_permissions: CharacteristicPermission::ENCRYPTED_READ,
+        gatt_event_mask: CharacteristicEvent::CONFIRM_READ,
+        encryption_key_size: EncryptionKeySize::with_value(16).unwrap(),
+        is_variable: true,
+    }
+}
+
+#[tokio::test]
+async fn add_service() {
+    let mut expected = vec![0x02];
+    expected.extend_from_slice(&UUID_128);
+    expected.extend_from_slice(&[0x02, 0x08]);
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GATT_ADD_SERVICE, &[0x01, 0x0D, 0x18, 0x01, 0x04])
+        .expect(opcode::GATT_ADD_SERVICE, &expected);
+
+    controller
+        .add_service(&AddServiceParameters {
+            uuid: Uuid::Uuid16(0x180D),
+            service_type: ServiceType::Primary,
+            max_attribute_records: 4,
+        })
+        .await;
+    controller
+        .add_service(&AddServiceParameters {
+            uuid: Uuid::Uuid128(UUID_128),
+            service_type: ServiceType::Secondary,
+            max_attribute_records: 8,
+        })
+        .await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn include_service() {
+    let mut expected = vec![0x10, 0x00, 0x20, 0x00, 0x25, 0x00, 0x02];
+    expected.extend_from_slice(&UUID_128);
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_INCLUDE_SERVICE,
+            &[0x10, 0x00, 0x20, 0x00, 0x25, 0x00, 0x01, 0x0F, 0x18],
+        )
+        .expect(opcode::GATT_INCLUDE_SERVICE, &expected);
+
+    for uuid in [Uuid::Uuid16(0x180F), Uuid::Uuid128(UUID_128)] {
+        controller
+            .include_service(&IncludeServiceParameters {
+                service_handle: AttributeHandle(0x0010),
+                include_handle_range: Range::new(AttributeHandle(0x0020), AttributeHandle(0x0025))
+                    .unwrap(),
+                include_uuid: uuid,
+            })
+            .await;
+    }
+    controller.verify();
+}
+
+#[tokio::test]
+async fn add_characteristic() {
+    let mut expected = vec![0x10, 0x00, 0x02];
+    expected.extend_from_slice(&UUID_128);
+    expected.extend_from_slice(&[0x14, 0x00, 0x12, 0x04, 0x04, 0x10, 0x01]);
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_ADD_CHARACTERISTIC,
+            &[
+                0x10, 0x00, 0x01, 0x37, 0x2A, 0x14, 0x00, 0x12, 0x04, 0x04, 0x10, 0x01,
+            ],
+        )
+        .expect(opcode::GATT_ADD_CHARACTERISTIC, &expected);
+
+    controller
+        .add_characteristic(&characteristic(Uuid::Uuid16(0x2A37)))
+        .await;
+    controller
+        .add_characteristic(&characteristic(Uuid::Uuid128(UUID_128)))
+        .await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn read_by_type_requests() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_READ_BY_TYPE_REQUEST,
+            &[0x01, 0x02, 0x01, 0x00, 0xFF, 0xFF, 0x01, 0x03, 0x28],
+        )
+        .expect(
+            opcode::GATT_READ_BY_GROUP_TYPE_REQUEST,
+            &[0x01, 0x02, 0x01, 0x00, 0xFF, 0xFF, 0x01, 0x00, 0x28],
+        );
+
+    let params = |uuid| ReadByTypeParameters {
+        conn_handle: hci::ConnectionHandle(0x0201),
+        attribute_handle_range: Range::new(AttributeHandle(0x0001), AttributeHandle(0xFFFF))
+            .unwrap(),
+        uuid,
+    };
+    controller
+        .read_by_type_request(&params(Uuid::Uuid16(0x2803)))
+        .await;
+    controller
+        .read_by_group_type_request(&params(Uuid::Uuid16(0x2800)))
+        .await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn deny_read() {
+    let mut controller = MockController::new();
+    controller.expect(opcode::GATT_DENY_READ, &[0x01, 0x08, 0x80]);
+
+    controller
+        .deny_read(
+            hci::ConnectionHandle(0x0801),
+            AttError::ApplicationError0x80,
+        )
+        .await;
+    controller.verify();
+}
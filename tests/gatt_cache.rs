@@ -0,0 +1,403 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::command::gatt::{CharacteristicProperty, Uuid};
+use hci::vendor::stm32wb::event::{AttError, AttRequest, AttributeHandle, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::gatt_cache::{
+    CacheStorage, Error, GattCache, GattTree, RemoteCharacteristic, RemoteService,
+};
+use hci::vendor::stm32wb::opcode;
+use hci::vendor::stm32wb::remote_attribute;
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Opcode, Status};
+
+const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+const PEER: BdAddrType = BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6]));
+
+type Tree = GattTree<4, 8>;
+
+#[derive(Default)]
+struct MemoryStorage {
+    trees: Vec<(BdAddrType, Tree)>,
+    stores: usize,
+}
+
+impl CacheStorage<4, 8> for MemoryStorage {
+    async fn load(&mut self, peer: BdAddrType) -> Option<Tree> {
+        self.trees
+            .iter()
+            .find(|(p, _)| *p == peer)
+            .map(|(_, tree)| *tree)
+    }
+
+    async fn store(&mut self, peer: BdAddrType, tree: &Tree) {
+        self.stores += 1;
+        self.trees.retain(|(p, _)| *p != peer);
+        self.trees.push((peer, *tree));
+    }
+}
+
+fn started(opcode: Opcode) -> Vec<u8> {
+    mock::command_status(opcode, Status::Success)
+}
+
+fn complete() -> Vec<u8> {
+    mock::vendor_event(0x0C10, &[0x01, 0x08, 0x00])
+}
+
+fn response(code: u16, element_len: u8, data: &[u8]) -> Vec<u8> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.push(element_len);
+    params.push(data.len() as u8);
+    params.extend_from_slice(data);
+    mock::vendor_event(code, &params)
+}
+
+fn services(data: &[u8]) -> Vec<u8> {
+    response(0x0C0A, 6, data)
+}
+
+fn characteristics(data: &[u8]) -> Vec<u8> {
+    response(0x0C06, 7, data)
+}
+
+fn descriptors(data: &[u8]) -> Vec<u8> {
+    // Format 1 holds 16-bit UUIDs.
+    response(0x0C04, 1, data)
+}
+
+fn expect_procedure(
+    controller: &mut MockController,
+    opcode: Opcode,
+    params: &[u8],
+    responses: &[Vec<u8>],
+) {
+    controller.expect(opcode, params).respond(started(opcode));
+    for response in responses {
+        controller.respond(response.clone());
+    }
+    controller.respond(complete());
+}
+
+fn expect_characteristics(
+    controller: &mut MockController,
+    range: [u8; 4],
+    data: &[u8],
+    cccd_ranges: &[[u8; 4]],
+) {
+    let mut params = vec![0x01, 0x08];
+    params.extend_from_slice(&range);
+    expect_procedure(
+        controller,
+        opcode::GATT_DISCOVER_ALL_CHARACTERISTICS_OF_SERVICE,
+        &params,
+        &[characteristics(data)],
+    );
+    for range in cccd_ranges {
+        let mut params = vec![0x01, 0x08];
+        params.extend_from_slice(range);
+        expect_procedure(
+            controller,
+            opcode::GATT_DISCOVER_ALL_CHARACTERISTIC_DESCRIPTORS,
+            &params,
+            &[descriptors(&[range[0], range[1], 0x02, 0x29])],
+        );
+    }
+}
+
+// Enables indications on the Client Characteristic Configuration descriptor of Service Changed.
+fn expect_service_changed_enabled(controller: &mut MockController) {
+    expect_procedure(
+        controller,
+        opcode::GATT_WRITE_CHARACTERISTIC_DESCRIPTOR,
+        &[0x01, 0x08, 0x04, 0x00, 0x02, 0x02, 0x00],
+        &[],
+    );
+}
+
+// Generic Attribute service at 0x0001 with Service Changed, and Heart Rate service at 0x0010 with
+// Heart Rate Measurement and Body Sensor Location.
+fn expect_discovery(controller: &mut MockController) {
+    controller
+        .expect(opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES, &[0x01, 0x08])
+        .respond(started(opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES))
+        .respond(services(&[
+            0x01, 0x00, 0x04, 0x00, 0x01, 0x18, 0x10, 0x00, 0x15, 0x00, 0x0D, 0x18,
+        ]))
+        // The server has no further service.
+        .respond(mock::vendor_event(
+            0x0C11,
+            &[0x01, 0x08, 0x10, 0x16, 0x00, 0x0A],
+        ))
+        .respond(mock::vendor_event(0x0C10, &[0x01, 0x08, 0x41]));
+    expect_characteristics(
+        controller,
+        [0x01, 0x00, 0x04, 0x00],
+        &[0x02, 0x00, 0x20, 0x03, 0x00, 0x05, 0x2A],
+        &[[0x04, 0x00, 0x04, 0x00]],
+    );
+    expect_characteristics(
+        controller,
+        [0x10, 0x00, 0x15, 0x00],
+        &[
+            0x11, 0x00, 0x10, 0x12, 0x00, 0x37, 0x2A, 0x14, 0x00, 0x02, 0x15, 0x00, 0x38, 0x2A,
+        ],
+        &[[0x13, 0x00, 0x13, 0x00]],
+    );
+    expect_service_changed_enabled(controller);
+}
+
+fn indication(handle: u16, value: &[u8]) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.push(2 + value.len() as u8);
+    params.extend_from_slice(&handle.to_le_bytes());
+    params.extend_from_slice(value);
+    mock::parse_event(&mock::vendor_event(0x0C0E, &params))
+}
+
+fn handle(handle: u16) -> AttributeHandle {
+    AttributeHandle(handle)
+}
+
+#[tokio::test]
+async fn discovers_and_saves_bonded_peer() {
+    let mut controller = MockController::new();
+    expect_discovery(&mut controller);
+    let mut storage = MemoryStorage::default();
+
+    let cache: GattCache<4, 8> = GattCache::open(&mut controller, &mut storage, CONN, Some(PEER))
+        .await
+        .unwrap();
+    controller.verify();
+
+    let tree = cache.tree();
+    assert_eq!(
+        tree.services().copied().collect::<Vec<_>>(),
+        [
+            RemoteService {
+                start_handle: handle(0x0001),
+                end_handle: handle(0x0004),
+                uuid: Uuid::Uuid16(0x1801),
+            },
+            RemoteService {
+                start_handle: handle(0x0010),
+                end_handle: handle(0x0015),
+                uuid: Uuid::Uuid16(0x180D),
+            },
+        ]
+    );
+    let heart_rate = tree.service(Uuid::Uuid16(0x180D)).unwrap();
+    assert_eq!(
+        tree.characteristics_of(heart_rate)
+            .copied()
+            .collect::<Vec<_>>(),
+        [
+            RemoteCharacteristic {
+                service_handle: handle(0x0010),
+                declaration_handle: handle(0x0011),
+                value_handle: handle(0x0012),
+                end_handle: handle(0x0013),
+                properties: CharacteristicProperty::NOTIFY,
+                uuid: Uuid::Uuid16(0x2A37),
+                cccd_handle: Some(handle(0x0013)),
+            },
+            RemoteCharacteristic {
+                service_handle: handle(0x0010),
+                declaration_handle: handle(0x0014),
+                value_handle: handle(0x0015),
+                end_handle: handle(0x0015),
+                properties: CharacteristicProperty::READ,
+                uuid: Uuid::Uuid16(0x2A38),
+                cccd_handle: None,
+            },
+        ]
+    );
+    assert_eq!(
+        tree.characteristic(Uuid::Uuid16(0x2A05))
+            .unwrap()
+            .cccd_handle,
+        Some(handle(0x0004))
+    );
+    assert_eq!(storage.stores, 1);
+
+    // Reconnecting loads the tree without discovering the server, but enables Service Changed
+    // indications again.
+    let mut controller = MockController::new();
+    expect_service_changed_enabled(&mut controller);
+    let reloaded: GattCache<4, 8> =
+        GattCache::open(&mut controller, &mut storage, CONN, Some(PEER))
+            .await
+            .unwrap();
+    assert_eq!(reloaded.tree(), cache.tree());
+    assert_eq!(storage.stores, 1);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn does_not_save_unbonded_peer() {
+    let mut controller = MockController::new();
+    expect_discovery(&mut controller);
+    expect_discovery(&mut controller);
+    let mut storage = MemoryStorage::default();
+
+    let mut cache: GattCache<4, 8> = GattCache::open(&mut controller, &mut storage, CONN, None)
+        .await
+        .unwrap();
+    assert_eq!(cache.tree().services().count(), 2);
+    cache.refresh(&mut controller, &mut storage).await.unwrap();
+    assert_eq!(cache.tree().characteristics().count(), 3);
+    assert_eq!(storage.stores, 0);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn service_changed_rediscovers_range() {
+    let mut controller = MockController::new();
+    expect_discovery(&mut controller);
+    let mut storage = MemoryStorage::default();
+    let mut cache: GattCache<4, 8> =
+        GattCache::open(&mut controller, &mut storage, CONN, Some(PEER))
+            .await
+            .unwrap();
+
+    // Notifications and indications of other characteristics are not handled.
+    assert_eq!(
+        cache
+            .process(&mut controller, &mut storage, &indication(0x0012, &[0x48]))
+            .await,
+        Ok(false)
+    );
+
+    // The Heart Rate service was replaced by a Battery service.
+    controller
+        .expect(opcode::GATT_CONFIRM_INDICATION, &[0x01, 0x08])
+        .respond(mock::command_complete(
+            opcode::GATT_CONFIRM_INDICATION,
+            &[0x00],
+        ));
+    expect_procedure(
+        &mut controller,
+        opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES,
+        &[0x01, 0x08],
+        &[services(&[
+            0x01, 0x00, 0x04, 0x00, 0x01, 0x18, 0x10, 0x00, 0x18, 0x00, 0x0F, 0x18,
+        ])],
+    );
+    expect_characteristics(
+        &mut controller,
+        [0x10, 0x00, 0x18, 0x00],
+        &[0x11, 0x00, 0x12, 0x12, 0x00, 0x19, 0x2A],
+        &[[0x13, 0x00, 0x18, 0x00]],
+    );
+
+    assert_eq!(
+        cache
+            .process(
+                &mut controller,
+                &mut storage,
+                &indication(0x0003, &[0x10, 0x00, 0xFF, 0xFF])
+            )
+            .await,
+        Ok(true)
+    );
+    controller.verify();
+
+    let tree = cache.tree();
+    assert_eq!(
+        tree.services().map(|s| s.uuid).collect::<Vec<_>>(),
+        [Uuid::Uuid16(0x1801), Uuid::Uuid16(0x180F)]
+    );
+    assert_eq!(
+        tree.characteristics().map(|c| c.uuid).collect::<Vec<_>>(),
+        [Uuid::Uuid16(0x2A05), Uuid::Uuid16(0x2A19)]
+    );
+    let battery_level = tree.characteristic(Uuid::Uuid16(0x2A19)).unwrap();
+    assert_eq!(battery_level.end_handle, handle(0x0018));
+    assert_eq!(battery_level.cccd_handle, Some(handle(0x0013)));
+    assert_eq!(storage.trees, [(PEER, *tree)]);
+}
+
+struct NoStorage;
+
+impl<const S: usize, const C: usize> CacheStorage<S, C> for NoStorage {
+    async fn load(&mut self, _peer: BdAddrType) -> Option<GattTree<S, C>> {
+        None
+    }
+
+    async fn store(&mut self, _peer: BdAddrType, _tree: &GattTree<S, C>) {}
+}
+
+#[tokio::test]
+async fn reports_errors() {
+    let mut controller = MockController::new();
+    expect_procedure(
+        &mut controller,
+        opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES,
+        &[0x01, 0x08],
+        &[services(&[
+            0x01, 0x00, 0x04, 0x00, 0x01, 0x18, 0x10, 0x00, 0x15, 0x00, 0x0D, 0x18,
+        ])],
+    );
+    controller
+        .expect_opcode(opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES)
+        .respond(mock::command_status(
+            opcode::GATT_DISCOVER_ALL_PRIMARY_SERVICES,
+            Status::CommandDisallowed,
+        ));
+
+    assert_eq!(
+        GattCache::<1, 8>::open(&mut controller, &mut NoStorage, CONN, None)
+            .await
+            .err(),
+        Some(Error::TooManyServices(handle(0x0010)))
+    );
+
+    let mut storage = MemoryStorage::default();
+    assert_eq!(
+        GattCache::<4, 8>::open(&mut controller, &mut storage, CONN, Some(PEER))
+            .await
+            .err(),
+        Some(Error::Procedure(remote_attribute::Error::CommandFailed(
+            Status::CommandDisallowed
+        )))
+    );
+    assert_eq!(storage.stores, 0);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reports_rejected_service_changed_configuration() {
+    let mut controller = MockController::new();
+    expect_discovery(&mut controller);
+    let mut storage = MemoryStorage::default();
+    GattCache::<4, 8>::open(&mut controller, &mut storage, CONN, Some(PEER))
+        .await
+        .unwrap();
+    controller.verify();
+
+    // The server requires encryption to configure indications.
+    let mut controller = MockController::new();
+    expect_procedure(
+        &mut controller,
+        opcode::GATT_WRITE_CHARACTERISTIC_DESCRIPTOR,
+        &[0x01, 0x08, 0x04, 0x00, 0x02, 0x02, 0x00],
+        &[mock::vendor_event(
+            0x0C11,
+            &[0x01, 0x08, 0x12, 0x04, 0x00, 0x05],
+        )],
+    );
+    assert_eq!(
+        GattCache::<4, 8>::open(&mut controller, &mut storage, CONN, Some(PEER))
+            .await
+            .err(),
+        Some(Error::Procedure(remote_attribute::Error::Att(
+            AttRequest::WriteRequest,
+            handle(0x0004),
+            AttError::InsufficientAuthentication
+        )))
+    );
+    controller.verify();
+}
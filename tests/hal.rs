@@ -0,0 +1,402 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{Channels, EncryptionKey};
+use hci::mock::{self, read_vendor_return, MockController};
+use hci::vendor::stm32wb::command::hal::{
+    ConfigData, ConfigParameter, ConfigValue, HalCommands, HalEventMask, RadioActivityMask, Role,
+    SmpMode,
+};
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::event::{
+    FwErrorType, HalEndOfRadioActivity, RadioState, Stm32Wb5xError, Stm32Wb5xEvent,
+};
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, Opcode};
+
+#[test]
+fn opcodes() {
+    assert_eq!(opcode::HAL_DEVICE_STANDBY, Opcode(0xFC13));
+    assert_eq!(opcode::HAL_SET_RADIO_ACTIVITY_MASK, Opcode(0xFC18));
+    assert_eq!(opcode::HAL_SET_EVENT_MASK, Opcode(0xFC1A));
+    assert_eq!(opcode::HAL_GET_PM_DEBUG_INFO, Opcode(0xFC1C));
+    assert_eq!(opcode::HAL_READ_RSSI, Opcode(0xFC22));
+}
+
+#[tokio::test]
+async fn commands() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::HAL_DEVICE_STANDBY, &[])
+        .expect(opcode::HAL_SET_RADIO_ACTIVITY_MASK, &[0x26, 0x00])
+        .expect(opcode::HAL_SET_RADIO_ACTIVITY_MASK, &[0x19, 0x00])
+        .expect(opcode::HAL_SET_EVENT_MASK, &[0x05, 0x00, 0x00, 0x00])
+        .expect(opcode::HAL_GET_PM_DEBUG_INFO, &[])
+        .expect(opcode::HAL_READ_RSSI, &[])
+        .expect(opcode::HAL_TX_TEST_PACKET_COUNT, &[]);
+
+    controller.device_standby().await;
+    controller
+        .set_radio_activity_mask(
+            RadioActivityMask::ADVERTISING
+                | RadioActivityMask::PERIPHERAL_CONNECTION
+                | RadioActivityMask::CENTRAL_CONNECTION,
+        )
+        .await;
+    controller
+        .set_radio_activity_mask(
+            RadioActivityMask::IDLE
+                | RadioActivityMask::SCANNING
+                | RadioActivityMask::CONNECTION_REQUEST,
+        )
+        .await;
+    controller
+        .set_event_mask(HalEventMask::END_OF_RADIO_ACTIVITY | HalEventMask::FW_ERROR)
+        .await;
+    controller.get_pm_debug_info().await;
+    controller.read_rssi().await;
+    controller.get_tx_test_packet_count().await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn return_parameters() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::command_complete_status(
+            opcode::HAL_DEVICE_STANDBY,
+            hci::Status::Success,
+        ))
+        .push_event(mock::command_complete_status(
+            opcode::HAL_SET_RADIO_ACTIVITY_MASK,
+            hci::Status::Success,
+        ))
+        .push_event(mock::command_complete_status(
+            opcode::HAL_SET_EVENT_MASK,
+            hci::Status::InvalidParameters,
+        ))
+        .push_event(mock::command_complete(
+            opcode::HAL_GET_PM_DEBUG_INFO,
+            &[0x00, 0x03, 0x05, 0x10],
+        ))
+        .push_event(mock::command_complete(opcode::HAL_READ_RSSI, &[0x00, 0xB5]))
+        .push_event(mock::command_complete(opcode::HAL_READ_RSSI, &[0x00, 0x7F]))
+        .push_event(mock::command_complete(
+            opcode::HAL_TX_TEST_PACKET_COUNT,
+            &[0x00, 0x10, 0x27, 0x00, 0x00],
+        ));
+
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalDeviceStandby(status) => {
+            assert_eq!(status, hci::Status::Success)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalSetRadioActivityMask(status) => {
+            assert_eq!(status, hci::Status::Success)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalSetEventMask(status) => {
+            assert_eq!(status, hci::Status::InvalidParameters)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalGetPmDebugInfo(info) => {
+            assert_eq!(info.status, hci::Status::Success);
+            assert_eq!(info.allocated_for_tx, 3);
+            assert_eq!(info.allocated_for_rx, 5);
+            assert_eq!(info.allocated_mblocks, 16);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalReadRssi(rssi) => {
+            assert_eq!(rssi.status, hci::Status::Success);
+            assert_eq!(rssi.rssi, Some(-75));
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalReadRssi(rssi) => assert_eq!(rssi.rssi, None),
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalGetTxTestPacketCount(count) => {
+            assert_eq!(count.packet_count, 10000)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+}
+
+async fn read_vendor_event(controller: &mut MockController) -> Stm32Wb5xEvent {
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::Vendor(event)) => event,
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn end_of_radio_activity() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::vendor_event(
+            0x0004,
+            &[0x01, 0x05, 0x78, 0x56, 0x34, 0x12],
+        ))
+        .push_event(mock::vendor_event(
+            0x0004,
+            &[0x04, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ))
+        .push_event(mock::vendor_event(
+            0x0004,
+            &[0x08, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ));
+
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalEndOfRadioActivity(event) => assert_eq!(
+            event,
+            HalEndOfRadioActivity {
+                last_state: RadioState::Advertising,
+                next_state: RadioState::CentralConnection,
+                next_state_sys_time: 0x1234_5678,
+            }
+        ),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalEndOfRadioActivity(event) => assert_eq!(
+            event,
+            HalEndOfRadioActivity {
+                last_state: RadioState::ConnectionRequest,
+                next_state: RadioState::Idle,
+                next_state_sys_time: 0,
+            }
+        ),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::BadRadioState(0x08),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn scan_req_report() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::vendor_event(
+            0x0005,
+            &[0xC4, 0x01, 0x01, 0x02, 0x03, 0x04, 0x05, 0xC6],
+        ))
+        .push_event(mock::vendor_event(
+            0x0005,
+            &[0x7F, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        ));
+
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalScanReqReport(event) => {
+            assert_eq!(event.rssi, Some(-60));
+            assert_eq!(
+                event.peer_address,
+                BdAddrType::Random(BdAddr([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]))
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalScanReqReport(event) => {
+            assert_eq!(event.rssi, None);
+            assert_eq!(
+                event.peer_address,
+                BdAddrType::Public(BdAddr([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]))
+            );
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn fw_error() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::vendor_event(0x0006, &[0x02, 0x02, 0x01, 0x08]))
+        .push_event(mock::vendor_event(0x0006, &[0x03, 0x00]))
+        .push_event(mock::vendor_event(0x0006, &[0x02, 0x03, 0x01, 0x08]))
+        .push_event(mock::vendor_event(0x0006, &[0x09, 0x00]));
+
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalFwError(event) => {
+            assert_eq!(event.error_type, FwErrorType::GattUnexpectedPeerMessage);
+            assert_eq!(event.data(), [0x01, 0x08]);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::HalFwError(event) => {
+            assert_eq!(event.error_type, FwErrorType::NvmLevelWarning);
+            assert!(event.data().is_empty());
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::BadLength(6, 7))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::BadFwErrorType(0x09),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn config_data_commands() {
+    let addr = BdAddr([0x01, 0x02, 0x03, 0x04, 0x05, 0xC6]);
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::HAL_WRITE_CONFIG_DATA,
+            &[0x2E, 6, 0x01, 0x02, 0x03, 0x04, 0x05, 0xC6],
+        )
+        .expect(opcode::HAL_WRITE_CONFIG_DATA, &[0xB0, 1, 2])
+        .expect(opcode::HAL_WRITE_CONFIG_DATA, &[0xC0, 1, 0b101])
+        .expect(
+            opcode::HAL_WRITE_CONFIG_DATA,
+            &[0x2C, 8, 1, 3, 0x01, 0x02, 0x03, 0x04, 0x05, 0xC6],
+        )
+        .expect(opcode::HAL_READ_CONFIG_DATA, &[0x2E])
+        .expect(opcode::HAL_READ_CONFIG_DATA, &[0x2D]);
+
+    controller
+        .write_config_data(&ConfigData::new(ConfigValue::RandomAddress(addr)))
+        .await;
+    controller
+        .write_config_data(&ConfigData::new(ConfigValue::SmpMode(SmpMode::NoBlacklist)))
+        .await;
+    controller
+        .write_config_data(&ConfigData::new(ConfigValue::ScanChannelMap(
+            Channels::CH_37 | Channels::CH_39,
+        )))
+        .await;
+    controller
+        .write_config_data(
+            &ConfigData::link_layer_only(true)
+                .role(Role::Primary12Kb)
+                .random_address(addr)
+                .build(),
+        )
+        .await;
+    controller
+        .read_config_data(ConfigParameter::RandomAddress)
+        .await;
+    controller.read_config_data(ConfigParameter::Role).await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn read_config_data_is_decoded_as_requested_parameter() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::command_complete(
+            opcode::HAL_READ_CONFIG_DATA,
+            &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0xC6],
+        ))
+        .push_event(mock::command_complete(
+            opcode::HAL_READ_CONFIG_DATA,
+            &[0x00, 0x03],
+        ))
+        .push_event(mock::command_complete(
+            opcode::HAL_READ_CONFIG_DATA,
+            &[0x00; 18],
+        ));
+
+    let data = match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalReadConfigData(data) => data,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    assert_eq!(data.status, hci::Status::Success);
+    assert_eq!(
+        data.value(ConfigParameter::RandomAddress),
+        Ok(ConfigValue::RandomAddress(BdAddr([
+            0x01, 0x02, 0x03, 0x04, 0x05, 0xC6
+        ])))
+    );
+    assert_eq!(
+        data.value(ConfigParameter::PublicAddress),
+        Ok(ConfigValue::PublicAddress(BdAddr([
+            0x01, 0x02, 0x03, 0x04, 0x05, 0xC6
+        ])))
+    );
+    assert_eq!(
+        data.value(ConfigParameter::Diversifier),
+        Err(Stm32Wb5xError::BadConfigParameterLength(6))
+    );
+
+    let data = match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::HalReadConfigData(data) => data,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    assert_eq!(data.value_bytes(), [0x03]);
+    assert_eq!(
+        data.value(ConfigParameter::Role),
+        Ok(ConfigValue::Role(Role::Primary12Kb))
+    );
+    assert_eq!(
+        data.value(ConfigParameter::GapAdditionalRecords),
+        Ok(ConfigValue::GapAdditionalRecords(3))
+    );
+    assert_eq!(
+        data.value(ConfigParameter::LinkLayerOnly),
+        Err(Stm32Wb5xError::BadConfigParameterValue(
+            ConfigParameter::LinkLayerOnly,
+            3
+        ))
+    );
+
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::BadConfigParameterLength(17),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn config_values_round_trip() {
+    let values = [
+        ConfigValue::PublicAddress(BdAddr([1, 2, 3, 4, 5, 6])),
+        ConfigValue::Diversifier(0x1234),
+        ConfigValue::EncryptionRoot(EncryptionKey([1; 16])),
+        ConfigValue::IdentityRoot(EncryptionKey([2; 16])),
+        ConfigValue::LinkLayerOnly(true),
+        ConfigValue::Role(Role::SimultaneousAdvertisingScanning),
+        ConfigValue::RandomAddress(BdAddr([1, 2, 3, 4, 5, 0xC6])),
+        ConfigValue::GapAdditionalRecords(2),
+        ConfigValue::ScKeyType(hci::vendor::stm32wb::command::hal::ScKeyType::Debug),
+        ConfigValue::SmpMode(SmpMode::Bypass),
+        ConfigValue::ScanChannelMap(Channels::all()),
+        ConfigValue::BackgroundScan(false),
+        ConfigValue::LinkLayerPrivacy(true),
+    ];
+
+    for value in values {
+        let param = value.parameter();
+        let mut bytes = [0; ConfigData::MAX_LENGTH];
+        let len = ConfigData::new(value.clone()).copy_into_slice(&mut bytes);
+        assert_eq!(bytes[0], param as u8);
+        assert_eq!(bytes[1] as usize, param.length());
+        assert_eq!(len, 2 + param.length());
+        assert_eq!(ConfigValue::from_bytes(param, &bytes[2..len]), Ok(value));
+    }
+}
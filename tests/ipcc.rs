@@ -0,0 +1,386 @@
+#![feature(async_fn_in_trait)]
+
+extern crate stm32wb_hci as hci;
+
+use core::cell::{Cell, RefCell};
+use hci::event::Event;
+use hci::host::ipcc::*;
+use hci::host::uart::{Packet, UartHci};
+use hci::host::HostHci;
+use hci::Opcode;
+use std::rc::Rc;
+
+const BASE: u32 = 0x2003_0000;
+const BUFFER_SIZE: u32 = 0x110;
+
+const LAYOUT: Layout = Layout {
+    command_buffer: BASE,
+    acl_data_buffer: BASE + BUFFER_SIZE,
+    event_queue: Queue {
+        head: BASE + 2 * BUFFER_SIZE,
+    },
+    free_buffer_queue: Queue {
+        head: BASE + 2 * BUFFER_SIZE + 8,
+    },
+};
+
+fn event_buffer(i: u32) -> u32 {
+    BASE + 3 * BUFFER_SIZE + i * BUFFER_SIZE
+}
+
+// Mailbox where CPU2 consumes every transmitted buffer immediately.
+#[derive(Default)]
+struct FakeMailbox {
+    tx: [Cell<usize>; 7],
+    rx: [Cell<bool>; 7],
+}
+
+impl Mailbox for FakeMailbox {
+    async fn wait_tx_free(&self, _channel: Channel) {}
+
+    fn set_tx(&self, channel: Channel) {
+        let count = &self.tx[channel.0 as usize];
+        count.set(count.get() + 1);
+    }
+
+    async fn wait_rx(&self, channel: Channel) {
+        assert!(
+            self.rx[channel.0 as usize].get(),
+            "waiting for channel {} would block",
+            channel.0
+        );
+    }
+
+    fn clear_rx(&self, channel: Channel) {
+        self.rx[channel.0 as usize].set(false);
+    }
+}
+
+// Plays CPU2: writes the event into an event buffer and queues it.
+fn queue_event<S: SharedMemory>(memory: &mut S, buffer: u32, event: TlPacket) {
+    let mut serial = [0; MAX_SERIAL_PACKET_LENGTH];
+    let len = event.copy_into_slice(&mut serial);
+    memory.write(buffer + PACKET_HEADER_LENGTH as u32, &serial[..len]);
+    LAYOUT.event_queue.push_back(memory, buffer);
+}
+
+#[test]
+fn packet_round_trip() {
+    let packets = [
+        TlPacket::Command {
+            packet_type: PacketType::BleCommand,
+            opcode: Opcode(0x0C03),
+            params: &[],
+        },
+        TlPacket::Command {
+            packet_type: PacketType::SystemCommand,
+            opcode: Opcode(0xFC66),
+            params: &[1, 2, 3],
+        },
+        TlPacket::Event {
+            packet_type: PacketType::BleEvent,
+            event_code: 0x0E,
+            params: &[0x01, 0x03, 0x0C, 0x00],
+        },
+        TlPacket::Event {
+            packet_type: PacketType::SystemResponse,
+            event_code: 0x0E,
+            params: &[0x01, 0x66, 0xFC, 0x00],
+        },
+        TlPacket::AclData {
+            handle: 0x2001,
+            data: &[0xAA, 0xBB],
+        },
+    ];
+
+    for packet in packets.iter() {
+        let mut bytes = [0; 32];
+        let len = packet.copy_into_slice(&mut bytes);
+        assert_eq!(len, packet.serialized_length());
+        assert_eq!(TlPacket::from_bytes(&bytes[..len]), Ok(*packet));
+    }
+}
+
+#[test]
+fn packet_layout() {
+    let mut bytes = [0; 16];
+    let len = TlPacket::AclData {
+        handle: 0x2001,
+        data: &[0xAA, 0xBB],
+    }
+    .copy_into_slice(&mut bytes);
+    assert_eq!(bytes[..len], [0x02, 0x01, 0x20, 0x02, 0x00, 0xAA, 0xBB]);
+
+    let len = TlPacket::Command {
+        packet_type: PacketType::SystemCommand,
+        opcode: Opcode(0xFC66),
+        params: &[0x05],
+    }
+    .copy_into_slice(&mut bytes);
+    assert_eq!(bytes[..len], [0x10, 0x66, 0xFC, 0x01, 0x05]);
+}
+
+#[test]
+fn packet_errors() {
+    assert_eq!(TlPacket::from_bytes(&[]), Err(Error::BadLength(0)));
+    assert_eq!(
+        TlPacket::from_bytes(&[0x03, 0x00]),
+        Err(Error::BadPacketType(0x03))
+    );
+    assert_eq!(
+        TlPacket::from_bytes(&[0x04, 0x0E]),
+        Err(Error::BadLength(2))
+    );
+    assert_eq!(
+        TlPacket::from_bytes(&[0x04, 0x0E, 0x02, 0x00]),
+        Err(Error::BadLength(4))
+    );
+}
+
+#[test]
+fn queue_operations() {
+    let mut ram = [0; 2048];
+    let mut memory = RamMemory::new(BASE, &mut ram);
+    let queue = LAYOUT.event_queue;
+
+    queue.init(&mut memory);
+    assert!(queue.is_empty(&memory));
+    assert_eq!(memory.read_u32(queue.head), queue.head);
+    assert_eq!(memory.read_u32(queue.head + 4), queue.head);
+
+    queue.push_back(&mut memory, event_buffer(0));
+    queue.push_back(&mut memory, event_buffer(1));
+    queue.push_back(&mut memory, event_buffer(2));
+    assert!(!queue.is_empty(&memory));
+    assert_eq!(memory.read_u32(event_buffer(1)), event_buffer(2));
+    assert_eq!(memory.read_u32(event_buffer(1) + 4), event_buffer(0));
+    assert_eq!(memory.read_u32(event_buffer(2)), queue.head);
+
+    assert_eq!(queue.pop_front(&mut memory), Some(event_buffer(0)));
+    assert_eq!(queue.pop_front(&mut memory), Some(event_buffer(1)));
+    queue.push_back(&mut memory, event_buffer(0));
+    assert_eq!(queue.pop_front(&mut memory), Some(event_buffer(2)));
+    assert_eq!(queue.pop_front(&mut memory), Some(event_buffer(0)));
+    assert_eq!(queue.pop_front(&mut memory), None);
+    assert!(queue.is_empty(&memory));
+}
+
+#[tokio::test]
+async fn writes_commands_to_the_command_buffer() {
+    let mut ram = [0; 2048];
+    let mut controller = IpccController::new(
+        FakeMailbox::default(),
+        RamMemory::new(BASE, &mut ram),
+        LAYOUT,
+    );
+
+    controller.reset().await;
+    assert_eq!(
+        controller.mailbox().tx[Channel::BLE_COMMAND.0 as usize].get(),
+        1
+    );
+
+    let mut serial = [0; 4];
+    controller.memory_mut().read(
+        LAYOUT.command_buffer + PACKET_HEADER_LENGTH as u32,
+        &mut serial,
+    );
+    assert_eq!(serial, [0x01, 0x03, 0x0C, 0x00]);
+}
+
+#[tokio::test]
+async fn writes_acl_data_to_the_acl_buffer() {
+    let mut ram = [0; 2048];
+    let mut controller = IpccController::new(
+        FakeMailbox::default(),
+        RamMemory::new(BASE, &mut ram),
+        LAYOUT,
+    );
+
+    controller.write_acl_data(0x0001, &[1, 2, 3]).await;
+    assert_eq!(
+        controller.mailbox().tx[Channel::ACL_DATA.0 as usize].get(),
+        1
+    );
+
+    let mut serial = [0; 8];
+    controller.memory_mut().read(
+        LAYOUT.acl_data_buffer + PACKET_HEADER_LENGTH as u32,
+        &mut serial,
+    );
+    assert_eq!(serial, [0x02, 0x01, 0x00, 0x03, 0x00, 1, 2, 3]);
+}
+
+#[tokio::test]
+async fn reads_events_and_releases_buffers() {
+    let mut ram = [0; 2048];
+    let mut controller = IpccController::new(
+        FakeMailbox::default(),
+        RamMemory::new(BASE, &mut ram),
+        LAYOUT,
+    );
+
+    queue_event(
+        controller.memory_mut(),
+        event_buffer(0),
+        TlPacket::Event {
+            packet_type: PacketType::BleEvent,
+            event_code: 0x0E,
+            params: &[0x01, 0x03, 0x0C, 0x00],
+        },
+    );
+    queue_event(
+        controller.memory_mut(),
+        event_buffer(1),
+        TlPacket::Event {
+            packet_type: PacketType::BleEvent,
+            event_code: 0x05,
+            params: &[0x00, 0x01, 0x02, 0x13],
+        },
+    );
+    controller.mailbox().rx[Channel::BLE_EVENT.0 as usize].set(true);
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(_)) => (),
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert!(!controller.mailbox().rx[Channel::BLE_EVENT.0 as usize].get());
+
+    // The second event is already queued, so no need to wait on the mailbox.
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::DisconnectionComplete(event)) => {
+            assert_eq!(event.conn_handle, hci::ConnectionHandle(0x0201));
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+
+    assert_eq!(controller.buffers_released(), 2);
+    assert_eq!(
+        controller.mailbox().tx[Channel::RELEASE_BUFFER.0 as usize].get(),
+        2
+    );
+    let free = LAYOUT.free_buffer_queue;
+    let memory = controller.memory_mut();
+    assert!(LAYOUT.event_queue.is_empty(memory));
+    assert_eq!(free.pop_front(memory), Some(event_buffer(0)));
+    assert_eq!(free.pop_front(memory), Some(event_buffer(1)));
+    assert_eq!(free.pop_front(memory), None);
+}
+
+#[tokio::test]
+#[should_panic(expected = "waiting for channel 1 would block")]
+async fn waits_for_the_mailbox_when_the_queue_is_empty() {
+    let mut ram = [0; 2048];
+    let mut controller = IpccController::new(
+        FakeMailbox::default(),
+        RamMemory::new(BASE, &mut ram),
+        LAYOUT,
+    );
+    let _ = controller.read().await;
+}
+
+#[tokio::test]
+async fn drops_system_events() {
+    let mut ram = [0; 2048];
+    let mut controller = IpccController::new(
+        FakeMailbox::default(),
+        RamMemory::new(BASE, &mut ram),
+        LAYOUT,
+    );
+
+    queue_event(
+        controller.memory_mut(),
+        event_buffer(0),
+        TlPacket::Event {
+            packet_type: PacketType::SystemEvent,
+            event_code: 0xFF,
+            params: &[0x01, 0x92, 0x00],
+        },
+    );
+    queue_event(
+        controller.memory_mut(),
+        event_buffer(1),
+        TlPacket::Event {
+            packet_type: PacketType::BleEvent,
+            event_code: 0x05,
+            params: &[0x00, 0x01, 0x02, 0x13],
+        },
+    );
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::DisconnectionComplete(_)) => (),
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert_eq!(controller.buffers_released(), 2);
+}
+
+// Shared memory that the mailbox can look at as well, standing in for CPU2.
+#[derive(Clone)]
+struct SharedRam(Rc<RefCell<Vec<u8>>>);
+
+impl SharedMemory for SharedRam {
+    fn read(&self, address: u32, buf: &mut [u8]) {
+        RamMemory::new(BASE, &mut self.0.borrow_mut()).read(address, buf);
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) {
+        RamMemory::new(BASE, &mut self.0.borrow_mut()).write(address, bytes);
+    }
+}
+
+// Mailbox that records how many buffers the free buffer queue holds each time CPU1 waits for the
+// release channel. Only the buffers of earlier releases may be there, since CPU2 owns the queue
+// until it clears the channel.
+struct ReleaseMailbox {
+    memory: SharedRam,
+    queued_while_busy: RefCell<Vec<usize>>,
+}
+
+impl Mailbox for ReleaseMailbox {
+    async fn wait_tx_free(&self, channel: Channel) {
+        if channel == Channel::RELEASE_BUFFER {
+            // CPU2 takes the buffers released before, and clears the channel.
+            let free = LAYOUT.free_buffer_queue;
+            let mut memory = self.memory.clone();
+            let mut queued = 0;
+            while free.pop_front(&mut memory).is_some() {
+                queued += 1;
+            }
+            self.queued_while_busy.borrow_mut().push(queued);
+        }
+    }
+
+    fn set_tx(&self, _channel: Channel) {}
+
+    async fn wait_rx(&self, _channel: Channel) {}
+
+    fn clear_rx(&self, _channel: Channel) {}
+}
+
+#[tokio::test]
+async fn releases_buffers_once_the_channel_is_free() {
+    let memory = SharedRam(Rc::new(RefCell::new(vec![0; 2048])));
+    let mailbox = ReleaseMailbox {
+        memory: memory.clone(),
+        queued_while_busy: RefCell::new(Vec::new()),
+    };
+    let mut controller = IpccController::new(mailbox, memory, LAYOUT);
+
+    for i in 0..2 {
+        queue_event(
+            controller.memory_mut(),
+            event_buffer(i),
+            TlPacket::Event {
+                packet_type: PacketType::BleEvent,
+                event_code: 0x05,
+                params: &[0x00, 0x01, 0x02, 0x13],
+            },
+        );
+    }
+    for _ in 0..2 {
+        controller.read().await.unwrap();
+    }
+
+    assert_eq!(*controller.mailbox().queued_while_busy.borrow(), [0, 1]);
+    assert_eq!(controller.buffers_released(), 2);
+}
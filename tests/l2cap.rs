@@ -0,0 +1,423 @@
+#![feature(async_fn_in_trait)]
+
+extern crate stm32wb_hci as hci;
+
+use hci::host::l2cap::*;
+use hci::types::ConnectionIntervalBuilder;
+use std::time::Duration;
+
+struct AcceptSpsm(u16);
+
+impl SignalingPolicy for AcceptSpsm {
+    fn accept_le_credit_based_connection(&mut self, spsm: u16) -> LeCreditBasedConnectionResult {
+        if spsm == self.0 {
+            LeCreditBasedConnectionResult::Success
+        } else {
+            LeCreditBasedConnectionResult::SpsmNotSupported
+        }
+    }
+}
+
+struct DefaultPolicy;
+
+impl SignalingPolicy for DefaultPolicy {}
+
+fn local_parameters() -> ChannelParameters {
+    ChannelParameters {
+        mtu: 100,
+        mps: 30,
+        initial_credits: 4,
+    }
+}
+
+fn serialize(command: &SignalingCommand) -> Vec<u8> {
+    let mut bytes = [0; MAX_COMMAND_LENGTH];
+    let len = command.copy_into_slice(&mut bytes);
+    bytes[..len].to_vec()
+}
+
+fn open_channel(signaling: &mut LeSignalingChannel<2>, remote_mps: u16, credits: u16) -> u16 {
+    let request = [
+        0x14,
+        0x07,
+        10,
+        0,
+        0x80,
+        0x00,
+        0x45,
+        0x00,
+        100,
+        0,
+        remote_mps as u8,
+        (remote_mps >> 8) as u8,
+        credits as u8,
+        (credits >> 8) as u8,
+    ];
+    let response = signaling.process(&request, &mut AcceptSpsm(0x80)).unwrap();
+    match response.event {
+        Some(SignalingEvent::ChannelConnected { local_cid }) => local_cid,
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[test]
+fn parse_basic_frame_splits_header() {
+    let frame = [3, 0, 0x05, 0x00, 1, 2, 3];
+    assert_eq!(
+        parse_basic_frame(&frame),
+        Ok((LE_SIGNALING_CID, &frame[4..]))
+    );
+    assert_eq!(parse_basic_frame(&frame[..6]), Err(Error::BadLength(6)));
+}
+
+#[test]
+fn connection_parameter_update_request_round_trip() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let conn_interval = ConnectionIntervalBuilder::new()
+        .with_range(Duration::from_millis(50), Duration::from_millis(500))
+        .with_latency(10)
+        .with_supervision_timeout(Duration::from_secs(15))
+        .build()
+        .unwrap();
+    let request = signaling.connection_parameter_update_request(conn_interval);
+
+    let mut frame = [0; BASIC_HEADER_LENGTH + MAX_COMMAND_LENGTH];
+    let len = request.copy_into_frame(&mut frame);
+    assert_eq!(
+        frame[..len],
+        [12, 0, 0x05, 0x00, 0x12, 0x01, 8, 0, 0x28, 0x00, 0x90, 0x01, 0x0A, 0x00, 0xDC, 0x05]
+    );
+
+    let response = signaling
+        .process(&[0x13, 0x01, 2, 0, 0x01, 0x00], &mut DefaultPolicy)
+        .unwrap();
+    assert!(response.reply.is_none());
+    match response.event {
+        Some(SignalingEvent::ConnectionParameterUpdateResponse { accepted }) => {
+            assert!(!accepted)
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    // A second response with the same identifier is no longer pending.
+    let response = signaling
+        .process(&[0x13, 0x01, 2, 0, 0x00, 0x00], &mut DefaultPolicy)
+        .unwrap();
+    assert!(response.event.is_none());
+}
+
+#[test]
+fn connection_parameter_update_request_from_peer() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let request = [
+        0x12, 0x09, 8, 0, 0x28, 0x00, 0x90, 0x01, 0x0A, 0x00, 0xDC, 0x05,
+    ];
+    let response = signaling.process(&request, &mut DefaultPolicy).unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x13, 0x09, 2, 0, 0x00, 0x00]
+    );
+    match response.event {
+        Some(SignalingEvent::ConnectionParameterUpdateRequest {
+            conn_interval,
+            accepted,
+        }) => {
+            assert!(accepted);
+            assert_eq!(conn_interval.conn_latency(), 10);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[test]
+fn invalid_connection_parameters_are_rejected() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+
+    // Interval min > max
+    let request = [
+        0x12, 0x02, 8, 0, 0x90, 0x01, 0x28, 0x00, 0x0A, 0x00, 0xDC, 0x05,
+    ];
+    let response = signaling.process(&request, &mut DefaultPolicy).unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x13, 0x02, 2, 0, 0x01, 0x00]
+    );
+    assert!(response.event.is_none());
+}
+
+#[test]
+fn unknown_command_is_rejected() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let response = signaling
+        .process(&[0x0A, 0x03, 2, 0, 0x01, 0x00], &mut DefaultPolicy)
+        .unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x01, 0x03, 2, 0, 0x00, 0x00]
+    );
+    assert_eq!(
+        signaling.process(&[0x0A, 0x03], &mut DefaultPolicy).err(),
+        Some(Error::BadLength(2))
+    );
+}
+
+#[test]
+fn command_reject_round_trip() {
+    let command = SignalingCommand::CommandReject {
+        identifier: 4,
+        reason: RejectReason::InvalidCid {
+            local: 0x0040,
+            remote: 0x0041,
+        },
+    };
+    let bytes = serialize(&command);
+    assert_eq!(
+        bytes,
+        [0x01, 0x04, 6, 0, 0x02, 0x00, 0x40, 0x00, 0x41, 0x00]
+    );
+    match SignalingCommand::from_bytes(&bytes).unwrap() {
+        SignalingCommand::CommandReject { identifier, reason } => {
+            assert_eq!(identifier, 4);
+            assert_eq!(
+                reason,
+                RejectReason::InvalidCid {
+                    local: 0x0040,
+                    remote: 0x0041
+                }
+            );
+        }
+        other => panic!("unexpected command {:?}", other),
+    }
+}
+
+#[test]
+fn bad_local_parameters() {
+    assert_eq!(
+        LeSignalingChannel::<1>::new(ChannelParameters {
+            mtu: 22,
+            mps: 23,
+            initial_credits: 1
+        })
+        .err(),
+        Some(Error::BadMtu(22))
+    );
+    assert_eq!(
+        LeSignalingChannel::<1>::new(ChannelParameters {
+            mtu: 23,
+            mps: 65534,
+            initial_credits: 1
+        })
+        .err(),
+        Some(Error::BadMps(65534))
+    );
+}
+
+#[test]
+fn peer_connection_refused_for_unknown_spsm() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let request = [
+        0x14, 0x07, 10, 0, 0x81, 0x00, 0x45, 0x00, 100, 0, 23, 0, 1, 0,
+    ];
+    let response = signaling.process(&request, &mut AcceptSpsm(0x80)).unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x15, 0x07, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x00]
+    );
+    assert!(response.event.is_none());
+}
+
+#[test]
+fn peer_connection_accepted() {
+    let mut signaling: LeSignalingChannel<2> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let request = [
+        0x14, 0x07, 10, 0, 0x80, 0x00, 0x45, 0x00, 100, 0, 23, 0, 1, 0,
+    ];
+    let response = signaling.process(&request, &mut AcceptSpsm(0x80)).unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x15, 0x07, 10, 0, 0x40, 0x00, 100, 0, 30, 0, 4, 0, 0x00, 0x00]
+    );
+
+    let channel = signaling.channel(0x0040).unwrap();
+    assert!(channel.is_open());
+    assert_eq!(channel.remote_cid(), 0x0045);
+    assert_eq!(channel.remote_mps(), 23);
+    assert_eq!(channel.tx_credits(), 1);
+    assert_eq!(channel.rx_credits(), 4);
+
+    // The same source CID cannot be connected twice.
+    let response = signaling.process(&request, &mut AcceptSpsm(0x80)).unwrap();
+    assert_eq!(serialize(&response.reply.unwrap())[12..], [0x0A, 0x00]);
+}
+
+#[test]
+fn local_connection_and_disconnection() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let request = signaling.connect(0x0025).unwrap();
+    assert_eq!(
+        serialize(&request),
+        [0x14, 0x01, 10, 0, 0x25, 0x00, 0x40, 0x00, 100, 0, 30, 0, 4, 0]
+    );
+    assert_eq!(signaling.connect(0x0025).err(), Some(Error::NoFreeChannel));
+
+    let response = signaling
+        .process(
+            &[
+                0x15, 0x01, 10, 0, 0x50, 0x00, 64, 0, 23, 0, 2, 0, 0x00, 0x00,
+            ],
+            &mut DefaultPolicy,
+        )
+        .unwrap();
+    match response.event {
+        Some(SignalingEvent::ChannelConnected { local_cid }) => assert_eq!(local_cid, 0x0040),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert_eq!(signaling.channel(0x0040).unwrap().remote_mtu(), 64);
+
+    let request = signaling.disconnect(0x0040).unwrap();
+    assert_eq!(
+        serialize(&request),
+        [0x06, 0x03, 4, 0, 0x50, 0x00, 0x40, 0x00]
+    );
+    let response = signaling
+        .process(
+            &[0x07, 0x03, 4, 0, 0x50, 0x00, 0x40, 0x00],
+            &mut DefaultPolicy,
+        )
+        .unwrap();
+    match response.event {
+        Some(SignalingEvent::ChannelDisconnected { local_cid }) => assert_eq!(local_cid, 0x0040),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(signaling.channel(0x0040).is_none());
+}
+
+#[test]
+fn local_connection_refused() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    signaling.connect(0x0025).unwrap();
+    let response = signaling
+        .process(
+            &[0x15, 0x01, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x00],
+            &mut DefaultPolicy,
+        )
+        .unwrap();
+    match response.event {
+        Some(SignalingEvent::ChannelRefused { local_cid, result }) => {
+            assert_eq!(local_cid, 0x0040);
+            assert_eq!(result, LeCreditBasedConnectionResult::NoResources);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(signaling.channel(0x0040).is_none());
+}
+
+#[test]
+fn peer_disconnection_of_unknown_channel_is_rejected() {
+    let mut signaling: LeSignalingChannel<1> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let response = signaling
+        .process(
+            &[0x06, 0x05, 4, 0, 0x40, 0x00, 0x45, 0x00],
+            &mut DefaultPolicy,
+        )
+        .unwrap();
+    assert_eq!(
+        serialize(&response.reply.unwrap()),
+        [0x01, 0x05, 6, 0, 0x02, 0x00, 0x40, 0x00, 0x45, 0x00]
+    );
+}
+
+#[test]
+fn segmentation_respects_mps_and_credits() {
+    let mut signaling: LeSignalingChannel<2> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let local_cid = open_channel(&mut signaling, 23, 2);
+
+    let sdu: Vec<u8> = (0..50).collect();
+    let channel = signaling.channel_mut(local_cid).unwrap();
+    let mut segmenter = channel.segment(&sdu).unwrap();
+    let mut frame = [0; 64];
+
+    let len = segmenter.next_frame(channel, &mut frame).unwrap().unwrap();
+    assert_eq!(len, BASIC_HEADER_LENGTH + 23);
+    assert_eq!(frame[..6], [23, 0, 0x45, 0x00, 50, 0]);
+    assert_eq!(frame[6..len], sdu[..21]);
+
+    let len = segmenter.next_frame(channel, &mut frame).unwrap().unwrap();
+    assert_eq!(len, BASIC_HEADER_LENGTH + 23);
+    assert_eq!(frame[4..len], sdu[21..44]);
+
+    assert_eq!(
+        segmenter.next_frame(channel, &mut frame),
+        Err(Error::NoCredits)
+    );
+
+    let response = signaling
+        .process(&[0x16, 0x08, 4, 0, 0x45, 0x00, 1, 0], &mut DefaultPolicy)
+        .unwrap();
+    match response.event {
+        Some(SignalingEvent::CreditsReceived {
+            local_cid: cid,
+            credits,
+        }) => {
+            assert_eq!(cid, local_cid);
+            assert_eq!(credits, 1);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    let channel = signaling.channel_mut(local_cid).unwrap();
+    let len = segmenter.next_frame(channel, &mut frame).unwrap().unwrap();
+    assert_eq!(frame[..4], [6, 0, 0x45, 0x00]);
+    assert_eq!(frame[4..len], sdu[44..]);
+    assert!(segmenter.is_done());
+    assert_eq!(segmenter.next_frame(channel, &mut frame), Ok(None));
+}
+
+#[test]
+fn segmentation_rejects_sdu_over_mtu() {
+    let mut signaling: LeSignalingChannel<2> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let local_cid = open_channel(&mut signaling, 23, 2);
+    let sdu = [0; 101];
+    assert_eq!(
+        signaling.channel(local_cid).unwrap().segment(&sdu).err(),
+        Some(Error::MtuExceeded(101, 100))
+    );
+}
+
+#[test]
+fn reassembly() {
+    let mut signaling: LeSignalingChannel<2> = LeSignalingChannel::new(local_parameters()).unwrap();
+    let local_cid = open_channel(&mut signaling, 23, 2);
+    let channel = signaling.channel_mut(local_cid).unwrap();
+
+    let mut sdu = [0; 100];
+    let mut first = vec![40, 0];
+    first.extend(0..28);
+    assert_eq!(channel.receive(&first, &mut sdu), Ok(None));
+    let second: Vec<u8> = (28..40).collect();
+    assert_eq!(channel.receive(&second, &mut sdu), Ok(Some(40)));
+    assert_eq!(sdu[..40], (0..40).collect::<Vec<u8>>()[..]);
+    assert_eq!(channel.rx_credits(), 2);
+
+    assert_eq!(
+        channel.receive(&[0; 31], &mut sdu),
+        Err(Error::MpsExceeded(31, 30))
+    );
+    assert_eq!(
+        channel.receive(&[101, 0], &mut sdu),
+        Err(Error::MtuExceeded(101, 100))
+    );
+    assert_eq!(
+        channel.receive(&[1, 0], &mut sdu),
+        Err(Error::CreditUnderflow(local_cid))
+    );
+
+    let credits = signaling.grant_credits(local_cid, 3).unwrap();
+    assert_eq!(serialize(&credits), [0x16, 0x01, 4, 0, 0x40, 0x00, 3, 0]);
+
+    let channel = signaling.channel_mut(local_cid).unwrap();
+    assert_eq!(
+        channel.receive(&[2, 0, 1, 2, 3], &mut sdu),
+        Err(Error::BadSduLength(local_cid))
+    );
+}
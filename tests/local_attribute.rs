@@ -0,0 +1,261 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::command::ReturnParameters;
+use hci::event::Event;
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{AdvertisingFilterPolicy, AdvertisingType, OwnAddressType};
+use hci::mock::simulator::Simulator;
+use hci::mock::{self, read_vendor_return, MockController};
+use hci::vendor::stm32wb::command::gap::{DiscoverableParameters, GapCommands};
+use hci::vendor::stm32wb::command::gatt::*;
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::event::{AttributeHandle, Status as VendorStatus, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::local_attribute::{self, Error};
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Status};
+
+const HANDLE: AttributeHandle = AttributeHandle(0x0010);
+
+fn read_complete(attribute_len: u16, value: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x00];
+    bytes.extend_from_slice(&attribute_len.to_le_bytes());
+    bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(value);
+    mock::command_complete(opcode::GATT_READ_HANDLE_VALUE, &bytes)
+}
+
+fn attribute_modified(offset: u16, data: &[u8]) -> Vec<u8> {
+    let mut params = vec![0x01, 0x08, 0x10, 0x00];
+    params.extend_from_slice(&offset.to_le_bytes());
+    params.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    params.extend_from_slice(data);
+    mock::vendor_event(0x0C01, &params)
+}
+
+async fn read_modified(controller: &mut MockController) -> Stm32Wb5xEvent {
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::Vendor(event)) => event,
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn read_handle_value_command() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0x20, 0x00, 0xF5, 0x00],
+        )
+        .respond(read_complete(300, &[1, 2, 3]));
+
+    controller.read_handle_value(HANDLE, 0x20, 245).await;
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(cc)) => match cc.return_params {
+            ReturnParameters::Vendor(VendorReturnParameters::GattReadHandleValue(value)) => {
+                assert_eq!(value.status, Status::Success);
+                assert_eq!(value.attribute_len(), 300);
+                assert_eq!(value.value(), [1, 2, 3]);
+            }
+            other => panic!("unexpected return parameters {:?}", other),
+        },
+        other => panic!("unexpected packet {:?}", other),
+    }
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reads_long_value_in_chunks() {
+    let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0x00, 0x00, 0xF5, 0x00],
+        )
+        .respond(read_complete(300, &value[..245]))
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0xF5, 0x00, 0xF5, 0x00],
+        )
+        .respond(read_complete(300, &value[245..]));
+
+    let mut buf = [0; local_attribute::MAX_ATTRIBUTE_LEN];
+    let len = local_attribute::read(&mut controller, HANDLE, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(&buf[..len], value.as_slice());
+    controller.verify();
+}
+
+#[tokio::test]
+async fn buffer_too_small() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0x00, 0x00, 0x10, 0x00],
+        )
+        .respond(read_complete(20, &[0; 16]));
+
+    let mut buf = [0; 16];
+    assert_eq!(
+        local_attribute::read(&mut controller, HANDLE, &mut buf).await,
+        Err(Error::BufferTooSmall(20))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn unknown_handle() {
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_READ_HANDLE_VALUE)
+        .respond(mock::command_complete(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x60, 0x00, 0x00, 0x00, 0x00],
+        ));
+
+    let mut buf = [0; 16];
+    assert_eq!(
+        local_attribute::read(&mut controller, HANDLE, &mut buf).await,
+        Err(Error::CommandFailed(Status::Vendor(
+            VendorStatus::InvalidHandle
+        )))
+    );
+}
+
+#[tokio::test]
+async fn verify_modified_reads_written_range() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(attribute_modified(4, &[5, 6, 7]))
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0x04, 0x00, 0x03, 0x00],
+        )
+        .respond(read_complete(7, &[5, 6, 7]));
+
+    let Stm32Wb5xEvent::GattAttributeModified(modified) = read_modified(&mut controller).await
+    else {
+        panic!("unexpected event");
+    };
+    assert_eq!(modified.offset(), 4);
+    assert_eq!(
+        local_attribute::verify_modified(&mut controller, &modified).await,
+        Ok(true)
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn verify_modified_detects_overwritten_data() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(attribute_modified(0, &[9, 9]))
+        .expect(
+            opcode::GATT_READ_HANDLE_VALUE,
+            &[0x10, 0x00, 0x00, 0x00, 0x02, 0x00],
+        )
+        .respond(read_complete(7, &[1, 2]));
+
+    let Stm32Wb5xEvent::GattAttributeModified(modified) = read_modified(&mut controller).await
+    else {
+        panic!("unexpected event");
+    };
+    assert_eq!(
+        local_attribute::verify_modified(&mut controller, &modified).await,
+        Ok(false)
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn peer_write_lands_in_simulator_database() {
+    let mut sim = Simulator::new();
+    sim.init_gatt().await;
+    sim.read().await.unwrap();
+
+    sim.add_service(&AddServiceParameters {
+        uuid: Uuid::Uuid16(0x180D),
+        service_type: ServiceType::Primary,
+        max_attribute_records: 4,
+    })
+    .await;
+    let service_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddService(params) => params.service_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+
+    sim.add_characteristic(&AddCharacteristicParameters {
+        service_handle,
+        characteristic_uuid: Uuid::Uuid16(0x2A39),
+        characteristic_value_len: 200,
+        characteristic_properties: CharacteristicProperty::WRITE,
+        security_permissions: CharacteristicPermission::empty(),
+        gatt_event_mask: CharacteristicEvent::ATTRIBUTE_WRITE,
+        encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+        is_variable: true,
+    })
+    .await;
+    let value_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddCharacteristic(params) => {
+            AttributeHandle(params.characteristic_handle.0 + 1)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+
+    sim.set_discoverable(&DiscoverableParameters {
+        advertising_type: AdvertisingType::ConnectableUndirected,
+        advertising_interval: None,
+        address_type: OwnAddressType::Public,
+        filter_policy: AdvertisingFilterPolicy::AllowConnectionAndScan,
+        local_name: None,
+        advertising_data: &[],
+        conn_interval: (None, None),
+    })
+    .await
+    .unwrap();
+    sim.read().await.unwrap();
+
+    let conn_handle: ConnectionHandle = sim
+        .connect(BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])))
+        .unwrap();
+    sim.read().await.unwrap();
+
+    let written: Vec<u8> = (0..200).map(|i| (i * 3) as u8).collect();
+    sim.peer_write(conn_handle, value_handle, &written).unwrap();
+    let Packet::Event(Event::Vendor(Stm32Wb5xEvent::GattAttributeModified(modified))) =
+        sim.read().await.unwrap()
+    else {
+        panic!("unexpected packet");
+    };
+
+    assert_eq!(
+        local_attribute::verify_modified(&mut sim, &modified).await,
+        Ok(true)
+    );
+    let mut buf = [0; local_attribute::MAX_ATTRIBUTE_LEN];
+    let len = local_attribute::read(&mut sim, value_handle, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(&buf[..len], written.as_slice());
+}
+
+#[tokio::test]
+async fn simulator_rejects_unknown_handle() {
+    let mut sim = Simulator::new();
+    sim.init_gatt().await;
+    sim.read().await.unwrap();
+
+    let mut buf = [0; 16];
+    assert_eq!(
+        local_attribute::read(&mut sim, AttributeHandle(0x0100), &mut buf).await,
+        Err(Error::CommandFailed(Status::Vendor(
+            VendorStatus::InvalidHandle
+        )))
+    );
+}
@@ -0,0 +1,214 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::host::uart::UartHci;
+use hci::host::{AdvertisingFilterPolicy, AdvertisingType, OwnAddressType};
+use hci::mock::simulator::Simulator;
+use hci::mock::{self, read_vendor_return, MockController};
+use hci::vendor::stm32wb::command::gap::{DiscoverableParameters, GapCommands};
+use hci::vendor::stm32wb::command::gatt::*;
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::event::{AttributeHandle, Status as VendorStatus};
+use hci::vendor::stm32wb::long_update::{self, Error, LongUpdate};
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, Status};
+
+const SERVICE: AttributeHandle = AttributeHandle(0x000C);
+const CHARACTERISTIC: AttributeHandle = AttributeHandle(0x000D);
+
+fn long_update(value: &[u8]) -> LongUpdate<'_> {
+    LongUpdate {
+        conn_handle_to_notify: ConnectionHandleToNotify::NotifyAll,
+        service_handle: SERVICE,
+        characteristic_handle: CHARACTERISTIC,
+        update_type: UpdateType::NOTIFICATION,
+        value,
+    }
+}
+
+fn part_command(update_type: u8, total_len: u16, offset: u16, value: &[u8]) -> Vec<u8> {
+    let mut params = vec![0x00, 0x00, 0x0C, 0x00, 0x0D, 0x00, update_type];
+    params.extend_from_slice(&total_len.to_le_bytes());
+    params.extend_from_slice(&offset.to_le_bytes());
+    params.push(value.len() as u8);
+    params.extend_from_slice(value);
+    params
+}
+
+fn update_complete(status: u8) -> Vec<u8> {
+    mock::command_complete(opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE, &[status])
+}
+
+#[test]
+fn splits_value_into_parts() {
+    let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let parts: Vec<_> = long_update(&value)
+        .parts()
+        .unwrap()
+        .map(|p| (p.update_type, p.total_len, p.offset, p.value.len()))
+        .collect();
+    assert_eq!(
+        parts,
+        [
+            (UpdateType::empty(), 300, 0, 243),
+            (UpdateType::NOTIFICATION, 300, 243, 57)
+        ]
+    );
+
+    let value = [0; 486];
+    let parts: Vec<_> = long_update(&value)
+        .parts()
+        .unwrap()
+        .map(|p| (p.update_type, p.offset, p.value.len()))
+        .collect();
+    assert_eq!(
+        parts,
+        [
+            (UpdateType::empty(), 0, 243),
+            (UpdateType::NOTIFICATION, 243, 243)
+        ]
+    );
+
+    let parts: Vec<_> = long_update(&[])
+        .parts()
+        .unwrap()
+        .map(|p| (p.update_type, p.total_len, p.offset, p.value.len()))
+        .collect();
+    assert_eq!(parts, [(UpdateType::NOTIFICATION, 0, 0, 0)]);
+}
+
+#[test]
+fn value_too_long() {
+    assert!(matches!(
+        long_update(&[0; 513]).parts(),
+        Err(Error::ValueTooLong(513))
+    ));
+}
+
+#[tokio::test]
+async fn sends_parts_in_order() {
+    let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &part_command(0x00, 300, 0, &value[..243]),
+        )
+        .respond(update_complete(0x00))
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &part_command(0x01, 300, 243, &value[243..]),
+        )
+        .respond(update_complete(0x00));
+
+    assert_eq!(
+        long_update::update(&mut controller, &long_update(&value)).await,
+        Ok(())
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reports_rejected_part() {
+    let value = [0x55; 300];
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE)
+        .respond(update_complete(0x00))
+        .expect_opcode(opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE)
+        .respond(update_complete(0x64));
+
+    assert_eq!(
+        long_update::update(&mut controller, &long_update(&value)).await,
+        Err(Error::CommandFailed(
+            243,
+            Status::Vendor(VendorStatus::InsufficientResources)
+        ))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn simulated_peer_receives_whole_value_once() {
+    let mut sim = Simulator::new();
+    sim.init_gatt().await;
+    sim.read().await.unwrap();
+
+    sim.add_service(&AddServiceParameters {
+        uuid: Uuid::Uuid16(0x180D),
+        service_type: ServiceType::Primary,
+        max_attribute_records: 4,
+    })
+    .await;
+    let service_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddService(params) => params.service_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    sim.add_characteristic(&AddCharacteristicParameters {
+        service_handle,
+        characteristic_uuid: Uuid::Uuid16(0x2A37),
+        characteristic_value_len: 400,
+        characteristic_properties: CharacteristicProperty::NOTIFY,
+        security_permissions: CharacteristicPermission::empty(),
+        gatt_event_mask: CharacteristicEvent::empty(),
+        encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+        is_variable: true,
+    })
+    .await;
+    let characteristic_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddCharacteristic(params) => params.characteristic_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+
+    sim.set_discoverable(&DiscoverableParameters {
+        advertising_type: AdvertisingType::ConnectableUndirected,
+        advertising_interval: None,
+        address_type: OwnAddressType::Public,
+        filter_policy: AdvertisingFilterPolicy::AllowConnectionAndScan,
+        local_name: None,
+        advertising_data: &[],
+        conn_interval: (None, None),
+    })
+    .await
+    .unwrap();
+    sim.read().await.unwrap();
+    let conn_handle = sim
+        .connect(BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])))
+        .unwrap();
+    sim.read().await.unwrap();
+    let value_handle = AttributeHandle(characteristic_handle.0 + 1);
+    sim.peer_subscribe(conn_handle, value_handle, true, false)
+        .unwrap();
+    sim.read().await.unwrap();
+
+    let value: Vec<u8> = (0..320).map(|i| (i * 7) as u8).collect();
+    let update = LongUpdate {
+        service_handle,
+        characteristic_handle,
+        ..long_update(&value)
+    };
+    assert_eq!(long_update::update(&mut sim, &update).await, Ok(()));
+
+    let notifications = sim.take_peer_notifications(conn_handle).unwrap();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].handle, value_handle);
+    assert_eq!(notifications[0].value, value);
+    assert_eq!(sim.attribute(value_handle).unwrap().value, value);
+
+    let too_long = [0; 450];
+    let update = LongUpdate {
+        service_handle,
+        characteristic_handle,
+        ..long_update(&too_long)
+    };
+    assert_eq!(
+        long_update::update(&mut sim, &update).await,
+        Err(Error::CommandFailed(
+            243,
+            Status::Vendor(VendorStatus::InvalidParameter)
+        ))
+    );
+    assert!(sim.take_peer_notifications(conn_handle).unwrap().is_empty());
+}
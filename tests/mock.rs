@@ -0,0 +1,100 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::command::ReturnParameters;
+use hci::event::{ConnectionRole, Event};
+use hci::host::uart::{Packet, UartHci};
+use hci::host::HostHci;
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::command::gap::{GapCommands, Role};
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, ConnectionHandle};
+use std::time::Duration;
+
+#[tokio::test]
+async fn gap_init_conversation() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GAP_INIT, &[0x01, 0x00, 0x07])
+        .respond(mock::gap_init_complete(0x0001, 0x0002, 0x0003));
+
+    controller.init(Role::PERIPHERAL, false, 7).await;
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::CommandComplete(cc)) => match cc.return_params {
+            ReturnParameters::Vendor(VendorReturnParameters::GapInit(params)) => {
+                assert_eq!(params.status, hci::Status::Success);
+                assert_eq!(params.service_handle.0, 0x0001);
+                assert_eq!(params.dev_name_handle.0, 0x0002);
+                assert_eq!(params.appearance_handle.0, 0x0003);
+            }
+            other => panic!("unexpected return parameters {:?}", other),
+        },
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert!(controller.is_done());
+    assert_eq!(controller.pending_events(), 0);
+}
+
+#[tokio::test]
+async fn le_connection_complete_event() {
+    let mut controller = MockController::new();
+    controller.push_event(mock::le_connection_complete(
+        ConnectionHandle(0x0201),
+        ConnectionRole::Peripheral,
+        BdAddrType::Random(BdAddr([1, 2, 3, 4, 5, 6])),
+        Duration::from_millis(50),
+        4,
+        Duration::from_secs(1),
+    ));
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::LeConnectionComplete(event)) => {
+            assert_eq!(event.conn_handle, ConnectionHandle(0x0201));
+            assert_eq!(event.role, ConnectionRole::Peripheral);
+            assert_eq!(
+                event.peer_bd_addr,
+                BdAddrType::Random(BdAddr([1, 2, 3, 4, 5, 6]))
+            );
+            assert_eq!(event.conn_interval.interval(), Duration::from_millis(50));
+            assert_eq!(event.conn_interval.conn_latency(), 4);
+            assert_eq!(
+                event.conn_interval.supervision_timeout(),
+                Duration::from_secs(1)
+            );
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn any_params() {
+    let mut controller = MockController::new();
+    controller.expect_opcode(opcode::GAP_INIT);
+    controller.init(Role::CENTRAL, true, 20).await;
+    assert_eq!(controller.commands_written(), 1);
+}
+
+#[tokio::test]
+#[should_panic(expected = "first difference at byte 2: expected 0x07, got 0x08")]
+async fn mismatched_params() {
+    let mut controller = MockController::new();
+    controller.expect(opcode::GAP_INIT, &[0x01, 0x00, 0x07]);
+    controller.init(Role::PERIPHERAL, false, 8).await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "the script has no more expected commands")]
+async fn unexpected_command() {
+    let mut controller = MockController::new();
+    controller.reset().await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "1 expected command(s) not written after 0 command(s)")]
+async fn unmet_expectation() {
+    let mut controller = MockController::new();
+    controller.expect_opcode(opcode::GAP_INIT);
+}
@@ -0,0 +1,183 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use core::time::Duration;
+use hci::event::{ConnectionRole, Event};
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{OwnAddressType, PeerAddrType};
+use hci::mock::{self, MockController};
+use hci::types::{ConnectionIntervalBuilder, ExpectedConnectionLength, ScanWindow};
+use hci::vendor::stm32wb::command::gap::{GapCommands, NameDiscoveryProcedureParameters};
+use hci::vendor::stm32wb::event::{GapProcedure, GapProcedureStatus, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::name_discovery::{self, Action, Error, NameDiscovery};
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Status};
+
+const PEER: BdAddr = BdAddr([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+const HANDLE: ConnectionHandle = ConnectionHandle(0x0801);
+
+fn params() -> NameDiscoveryProcedureParameters {
+    NameDiscoveryProcedureParameters {
+        scan_window: ScanWindow::start_every(Duration::from_millis(100))
+            .unwrap()
+            .open_for(Duration::from_millis(50))
+            .unwrap(),
+        peer_address: PeerAddrType::PublicDeviceAddress(PEER),
+        own_address_type: OwnAddressType::Public,
+        conn_interval: ConnectionIntervalBuilder::new()
+            .with_range(Duration::from_millis(50), Duration::from_millis(100))
+            .with_latency(0)
+            .with_supervision_timeout(Duration::from_secs(2))
+            .build()
+            .unwrap(),
+        expected_connection_length: ExpectedConnectionLength::new(
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        )
+        .unwrap(),
+    }
+}
+
+fn params_bytes() -> [u8; 24] {
+    [
+        0xA0, 0x00, 0x50, 0x00, // scan interval and window
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, // peer address
+        0x00, // own address type
+        0x28, 0x00, 0x50, 0x00, 0x00, 0x00, 0xC8, 0x00, // connection interval
+        0x10, 0x00, 0x20, 0x00, // expected connection length
+    ]
+}
+
+fn connection_complete() -> Vec<u8> {
+    mock::le_connection_complete(
+        HANDLE,
+        ConnectionRole::Central,
+        BdAddrType::Public(PEER),
+        Duration::from_millis(50),
+        0,
+        Duration::from_secs(2),
+    )
+}
+
+fn name_discovery_complete(status: u8, name: &[u8]) -> Vec<u8> {
+    let mut params = vec![0x04, status, name.len() as u8];
+    params.extend_from_slice(name);
+    mock::vendor_event(0x0407, &params)
+}
+
+#[tokio::test]
+async fn start_name_discovery_procedure() {
+    let mut controller = MockController::new();
+    controller.expect(opcode::GAP_START_NAME_DISCOVERY_PROCEDURE, &params_bytes());
+
+    controller.start_name_discovery_procedure(&params()).await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn procedure_complete_contains_name() {
+    let mut controller = MockController::new();
+    controller.push_event(name_discovery_complete(0x00, b"Sensor"));
+
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::Vendor(Stm32Wb5xEvent::GapProcedureComplete(complete))) => {
+            assert_eq!(complete.status, GapProcedureStatus::Success);
+            match complete.procedure {
+                GapProcedure::NameDiscovery(len, name) => assert_eq!(&name.0[..len], b"Sensor"),
+                other => panic!("unexpected procedure {:?}", other),
+            }
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn reads_name_and_disconnects() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GAP_START_NAME_DISCOVERY_PROCEDURE, &params_bytes())
+        .respond(mock::command_status(
+            opcode::GAP_START_NAME_DISCOVERY_PROCEDURE,
+            Status::Success,
+        ))
+        .respond(connection_complete())
+        .respond(name_discovery_complete(0x00, b"Thermometer"))
+        .expect(hci::Opcode(0x0406), &[0x01, 0x08, 0x13])
+        .respond(mock::disconnection_complete(
+            HANDLE,
+            Status::ConnectionTerminatedByHost,
+        ));
+
+    let name = name_discovery::read_device_name(&mut controller, &params())
+        .await
+        .unwrap();
+    assert_eq!(name.as_bytes(), b"Thermometer");
+    assert_eq!(name.as_str(), Ok("Thermometer"));
+    controller.verify();
+}
+
+#[tokio::test]
+async fn failed_read_still_disconnects() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GAP_START_NAME_DISCOVERY_PROCEDURE, &params_bytes())
+        .respond(mock::command_status(
+            opcode::GAP_START_NAME_DISCOVERY_PROCEDURE,
+            Status::Success,
+        ))
+        .respond(connection_complete())
+        .respond(name_discovery_complete(0x41, &[]))
+        .expect(hci::Opcode(0x0406), &[0x01, 0x08, 0x13])
+        .respond(mock::disconnection_complete(
+            HANDLE,
+            Status::ConnectionTerminatedByHost,
+        ));
+
+    assert_eq!(
+        name_discovery::read_device_name(&mut controller, &params()).await,
+        Err(Error::ProcedureFailed(GapProcedureStatus::Failed))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn rejected_command() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GAP_START_NAME_DISCOVERY_PROCEDURE, &params_bytes())
+        .respond(mock::command_status(
+            opcode::GAP_START_NAME_DISCOVERY_PROCEDURE,
+            Status::CommandDisallowed,
+        ));
+
+    assert_eq!(
+        name_discovery::read_device_name(&mut controller, &params()).await,
+        Err(Error::CommandFailed(Status::CommandDisallowed))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn peer_disconnects_before_name() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(connection_complete())
+        .push_event(mock::disconnection_complete(
+            HANDLE,
+            Status::ConnectionTimeout,
+        ));
+
+    let mut discovery = NameDiscovery::new();
+    assert_eq!(discovery.start(), Action::StartProcedure);
+
+    let Packet::Event(event) = controller.read().await.unwrap();
+    assert_eq!(discovery.handle(&event), Ok(Action::Wait));
+    let Packet::Event(event) = controller.read().await.unwrap();
+    assert_eq!(
+        discovery.handle(&event),
+        Err(Error::Disconnected(Status::ConnectionTimeout))
+    );
+    assert!(discovery.is_done());
+}
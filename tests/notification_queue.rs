@@ -0,0 +1,375 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use core::time::Duration;
+use hci::event::ConnectionRole;
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{AdvertisingFilterPolicy, AdvertisingType, OwnAddressType};
+use hci::mock::simulator::{PeerNotification, Simulator};
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::command::gap::{DiscoverableParameters, GapCommands};
+use hci::vendor::stm32wb::command::gatt::*;
+use hci::vendor::stm32wb::event::{AttributeHandle, Status as VendorStatus};
+use hci::vendor::stm32wb::notification_queue::{Error, Kind, NotificationQueue};
+use hci::vendor::stm32wb::opcode;
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Status};
+
+const CONN_A: ConnectionHandle = ConnectionHandle(0x0801);
+const CONN_B: ConnectionHandle = ConnectionHandle(0x0802);
+const SERVICE: AttributeHandle = AttributeHandle(0x000C);
+const CHARACTERISTIC: AttributeHandle = AttributeHandle(0x000D);
+
+fn update_bytes(conn_handle: ConnectionHandle, update_type: u8, value: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&conn_handle.0.to_le_bytes());
+    bytes.extend_from_slice(&[0x0C, 0x00, 0x0D, 0x00, update_type]);
+    bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&[0x00, 0x00, value.len() as u8]);
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+fn update_complete(status: Status<VendorStatus>) -> Vec<u8> {
+    mock::command_complete_status(opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE, status)
+}
+
+fn tx_pool_available(conn_handle: ConnectionHandle) -> Vec<u8> {
+    let mut params = conn_handle.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&[0x02, 0x00]);
+    mock::vendor_event(0x0C16, &params)
+}
+
+fn server_confirmation(conn_handle: ConnectionHandle) -> Vec<u8> {
+    mock::vendor_event(0x0C17, &conn_handle.0.to_le_bytes())
+}
+
+fn connected<const N: usize, const D: usize>(
+    queue: &mut NotificationQueue<N, D>,
+    conn_handle: ConnectionHandle,
+) {
+    let event = mock::parse_event(&mock::le_connection_complete(
+        conn_handle,
+        ConnectionRole::Peripheral,
+        BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])),
+        Duration::from_millis(50),
+        0,
+        Duration::from_secs(5),
+    ));
+    queue.handle(&event).unwrap();
+}
+
+#[tokio::test]
+async fn update_targets_one_connection() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &update_bytes(CONN_A, 0x01, &[1, 2, 3]),
+        )
+        .respond(update_complete(Status::Success));
+
+    let mut queue: NotificationQueue<2, 4> = NotificationQueue::new();
+    connected(&mut queue, CONN_A);
+    queue
+        .enqueue(
+            CONN_A,
+            SERVICE,
+            CHARACTERISTIC,
+            Kind::Notification,
+            &[1, 2, 3],
+        )
+        .unwrap();
+    assert_eq!(queue.queued(CONN_A), 1);
+
+    queue.flush(&mut controller).await.unwrap();
+    assert_eq!(queue.queued(CONN_A), 0);
+    assert!(queue.is_idle());
+    controller.verify();
+}
+
+#[tokio::test]
+async fn retries_when_pool_is_available() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &update_bytes(CONN_A, 0x01, &[1]),
+        )
+        .respond(update_complete(Status::Vendor(
+            VendorStatus::InsufficientResources,
+        )))
+        .respond(tx_pool_available(CONN_A))
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &update_bytes(CONN_A, 0x01, &[1]),
+        )
+        .respond(update_complete(Status::Success))
+        .expect(
+            opcode::GATT_UPDATE_LONG_CHARACTERISTIC_VALUE,
+            &update_bytes(CONN_A, 0x01, &[2]),
+        )
+        .respond(update_complete(Status::Success));
+
+    let mut queue: NotificationQueue<1, 4> = NotificationQueue::new();
+    queue.add_connection(CONN_A).unwrap();
+    for value in [1, 2] {
+        queue
+            .enqueue(
+                CONN_A,
+                SERVICE,
+                CHARACTERISTIC,
+                Kind::Notification,
+                &[value],
+            )
+            .unwrap();
+    }
+
+    queue.flush(&mut controller).await.unwrap();
+    assert!(!queue.is_pool_full());
+    controller.verify();
+}
+
+#[test]
+fn nothing_is_sent_while_pool_is_full() {
+    let mut queue: NotificationQueue<2, 4> = NotificationQueue::new();
+    connected(&mut queue, CONN_A);
+    connected(&mut queue, CONN_B);
+    queue
+        .enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[1])
+        .unwrap();
+    queue
+        .enqueue(CONN_B, SERVICE, CHARACTERISTIC, Kind::Notification, &[2])
+        .unwrap();
+
+    assert!(queue.next_update().is_some());
+    assert!(queue.next_update().is_none());
+    queue
+        .handle(&mock::parse_event(&update_complete(Status::Vendor(
+            VendorStatus::InsufficientResources,
+        ))))
+        .unwrap();
+    assert!(queue.is_pool_full());
+    assert!(queue.next_update().is_none());
+
+    queue
+        .handle(&mock::parse_event(&tx_pool_available(CONN_A)))
+        .unwrap();
+    let update = queue.next_update().unwrap();
+    assert_eq!(update.value, [2]);
+}
+
+#[test]
+fn one_indication_in_flight_per_connection() {
+    let mut queue: NotificationQueue<2, 4> = NotificationQueue::new();
+    connected(&mut queue, CONN_A);
+    connected(&mut queue, CONN_B);
+    for value in [1, 2] {
+        queue
+            .enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Indication, &[value])
+            .unwrap();
+    }
+    queue
+        .enqueue(CONN_B, SERVICE, CHARACTERISTIC, Kind::Notification, &[3])
+        .unwrap();
+
+    let success = mock::parse_event(&update_complete(Status::Success));
+    assert_eq!(queue.next_update().unwrap().value, [1]);
+    queue.handle(&success).unwrap();
+
+    // The second indication waits for the confirmation, but the other connection proceeds.
+    assert_eq!(queue.next_update().unwrap().value, [3]);
+    queue.handle(&success).unwrap();
+    assert!(queue.next_update().is_none());
+    assert!(!queue.is_idle());
+
+    queue
+        .handle(&mock::parse_event(&server_confirmation(CONN_A)))
+        .unwrap();
+    assert_eq!(queue.next_update().unwrap().value, [2]);
+    queue.handle(&success).unwrap();
+    queue
+        .handle(&mock::parse_event(&server_confirmation(CONN_A)))
+        .unwrap();
+    assert!(queue.is_idle());
+}
+
+#[test]
+fn disconnect_drops_queue() {
+    let mut queue: NotificationQueue<1, 4> = NotificationQueue::new();
+    connected(&mut queue, CONN_A);
+    for value in [1, 2, 3] {
+        queue
+            .enqueue(
+                CONN_A,
+                SERVICE,
+                CHARACTERISTIC,
+                Kind::Notification,
+                &[value],
+            )
+            .unwrap();
+    }
+    assert!(queue.next_update().is_some());
+
+    queue
+        .handle(&mock::parse_event(&mock::disconnection_complete(
+            CONN_A,
+            Status::RemoteTerminationByUser,
+        )))
+        .unwrap();
+    assert_eq!(queue.queued(CONN_A), 0);
+    assert_eq!(
+        queue.enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[4]),
+        Err(Error::UnknownConnection(CONN_A))
+    );
+
+    // The completion of the update sent before the disconnection is ignored.
+    queue
+        .handle(&mock::parse_event(&update_complete(Status::Success)))
+        .unwrap();
+    assert!(queue.is_idle());
+
+    // The queue is free for the next connection.
+    connected(&mut queue, CONN_B);
+    assert_eq!(
+        queue.handle(&mock::parse_event(&mock::le_connection_complete(
+            ConnectionHandle(0x0803),
+            ConnectionRole::Peripheral,
+            BdAddrType::Public(BdAddr([0; 6])),
+            Duration::from_millis(50),
+            0,
+            Duration::from_secs(5),
+        ))),
+        Err(Error::TooManyConnections(ConnectionHandle(0x0803)))
+    );
+}
+
+#[test]
+fn rejected_update_is_dropped() {
+    let mut queue: NotificationQueue<1, 4> = NotificationQueue::new();
+    queue.add_connection(CONN_A).unwrap();
+    queue
+        .enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[1])
+        .unwrap();
+
+    assert!(queue.next_update().is_some());
+    assert_eq!(
+        queue.handle(&mock::parse_event(&update_complete(Status::Vendor(
+            VendorStatus::InvalidHandle
+        )))),
+        Err(Error::CommandFailed(
+            CONN_A,
+            Status::Vendor(VendorStatus::InvalidHandle)
+        ))
+    );
+    assert!(queue.is_idle());
+}
+
+#[test]
+fn bounded_queue() {
+    let mut queue: NotificationQueue<1, 2, 4> = NotificationQueue::new();
+    queue.add_connection(CONN_A).unwrap();
+    for _ in 0..2 {
+        queue
+            .enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[0; 4])
+            .unwrap();
+    }
+
+    assert_eq!(
+        queue.enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[0]),
+        Err(Error::QueueFull(CONN_A))
+    );
+    assert_eq!(
+        queue.enqueue(CONN_B, SERVICE, CHARACTERISTIC, Kind::Notification, &[0]),
+        Err(Error::UnknownConnection(CONN_B))
+    );
+    assert_eq!(
+        queue.enqueue(CONN_A, SERVICE, CHARACTERISTIC, Kind::Notification, &[0; 5]),
+        Err(Error::ValueTooLong(5))
+    );
+}
+
+#[tokio::test]
+async fn streams_through_small_tx_pool() {
+    let mut sim = Simulator::new();
+    sim.init_gatt().await;
+    sim.read().await.unwrap();
+    sim.add_service(&AddServiceParameters {
+        uuid: Uuid::Uuid16(0x181A),
+        service_type: ServiceType::Primary,
+        max_attribute_records: 4,
+    })
+    .await;
+    sim.read().await.unwrap();
+    sim.add_characteristic(&AddCharacteristicParameters {
+        service_handle: AttributeHandle(0x0005),
+        characteristic_uuid: Uuid::Uuid16(0x2A6E),
+        characteristic_value_len: 2,
+        characteristic_properties: CharacteristicProperty::NOTIFY,
+        security_permissions: CharacteristicPermission::empty(),
+        gatt_event_mask: CharacteristicEvent::empty(),
+        encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+        is_variable: false,
+    })
+    .await;
+    sim.read().await.unwrap();
+    sim.set_discoverable(&DiscoverableParameters {
+        advertising_type: AdvertisingType::ConnectableUndirected,
+        advertising_interval: None,
+        address_type: OwnAddressType::Public,
+        filter_policy: AdvertisingFilterPolicy::AllowConnectionAndScan,
+        local_name: None,
+        advertising_data: &[],
+        conn_interval: (None, None),
+    })
+    .await
+    .unwrap();
+    sim.read().await.unwrap();
+
+    let mut queue: NotificationQueue<1, 8> = NotificationQueue::new();
+    let conn_handle = sim
+        .connect(BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])))
+        .unwrap();
+    let Packet::Event(event) = sim.read().await.unwrap();
+    queue.handle(&event).unwrap();
+    let value_handle = AttributeHandle(0x0007);
+    sim.peer_subscribe(conn_handle, value_handle, true, false)
+        .unwrap();
+    sim.read().await.unwrap();
+
+    sim.set_tx_pool_size(2);
+    for i in 0..5u16 {
+        queue
+            .enqueue(
+                conn_handle,
+                AttributeHandle(0x0005),
+                AttributeHandle(0x0006),
+                Kind::Notification,
+                &i.to_le_bytes(),
+            )
+            .unwrap();
+    }
+
+    let mut transmissions = 0;
+    while !queue.is_idle() {
+        if queue.is_pool_full() {
+            sim.transmit();
+            transmissions += 1;
+        } else {
+            assert!(queue.send_next(&mut sim).await);
+        }
+        let Packet::Event(event) = sim.read().await.unwrap();
+        queue.handle(&event).unwrap();
+    }
+
+    assert_eq!(transmissions, 2);
+    let expected: Vec<PeerNotification> = (0..5u16)
+        .map(|i| PeerNotification {
+            handle: value_handle,
+            value: i.to_le_bytes().to_vec(),
+            indication: false,
+        })
+        .collect();
+    assert_eq!(sim.take_peer_notifications(conn_handle).unwrap(), expected);
+}
@@ -0,0 +1,221 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::event::{AttributeHandle, Status as VendorStatus, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::opcode;
+use hci::vendor::stm32wb::read_permit::{Error, ReadPermits, ValueProvider};
+use hci::{ConnectionHandle, Status};
+
+const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+const SERVICE: AttributeHandle = AttributeHandle(0x000C);
+const TEMPERATURE: AttributeHandle = AttributeHandle(0x000D);
+const HUMIDITY: AttributeHandle = AttributeHandle(0x0010);
+const NAME: AttributeHandle = AttributeHandle(0x0013);
+
+#[derive(Default)]
+struct Sensor {
+    reads: Vec<AttributeHandle>,
+}
+
+impl ValueProvider for Sensor {
+    async fn value(
+        &mut self,
+        conn_handle: ConnectionHandle,
+        attribute_handle: AttributeHandle,
+        buf: &mut [u8],
+    ) -> Option<usize> {
+        assert_eq!(conn_handle, CONN);
+        self.reads.push(attribute_handle);
+        let value: &[u8] = match attribute_handle {
+            AttributeHandle(0x000E) => &[0x15, 0x01],
+            AttributeHandle(0x0011) => &[0x2A],
+            _ => return None,
+        };
+        buf[..value.len()].copy_from_slice(value);
+        Some(value.len())
+    }
+}
+
+fn read_request(handle: u16, offset: u16) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&handle.to_le_bytes());
+    params.extend_from_slice(&offset.to_le_bytes());
+    mock::parse_event(&mock::vendor_event(0x0C14, &params))
+}
+
+fn read_multiple_request(handles: &[u16]) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.push(2 * handles.len() as u8);
+    for handle in handles {
+        params.extend_from_slice(&handle.to_le_bytes());
+    }
+    mock::parse_event(&mock::vendor_event(0x0C15, &params))
+}
+
+fn update_complete(status: Status<VendorStatus>) -> Vec<u8> {
+    mock::command_complete_status(opcode::GATT_UPDATE_CHARACTERISTIC_VALUE, status)
+}
+
+fn permits() -> ReadPermits<4> {
+    let mut permits = ReadPermits::new();
+    permits.register(SERVICE, TEMPERATURE).unwrap();
+    permits.register(SERVICE, HUMIDITY).unwrap();
+    permits.register(SERVICE, NAME).unwrap();
+    permits
+}
+
+#[tokio::test]
+async fn updates_value_before_allowing_read() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_CHARACTERISTIC_VALUE,
+            &[0x0C, 0x00, 0x0D, 0x00, 0x00, 0x02, 0x15, 0x01],
+        )
+        .respond(update_complete(Status::Success))
+        .expect(opcode::GATT_ALLOW_READ, &[0x01, 0x08]);
+
+    let mut permits = permits();
+    let mut sensor = Sensor::default();
+    assert!(permits
+        .process(&mut controller, &mut sensor, &read_request(0x000E, 0))
+        .await
+        .unwrap());
+    controller.verify();
+    assert_eq!(controller.pending_events(), 0);
+    assert_eq!(sensor.reads, [AttributeHandle(0x000E)]);
+}
+
+#[tokio::test]
+async fn denies_read_when_update_fails() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_CHARACTERISTIC_VALUE,
+            &[0x0C, 0x00, 0x10, 0x00, 0x00, 0x01, 0x2A],
+        )
+        .respond(update_complete(Status::Vendor(
+            VendorStatus::InsufficientResources,
+        )))
+        .expect(opcode::GATT_DENY_READ, &[0x01, 0x08, 0x0E]);
+
+    let mut permits = permits();
+    let mut sensor = Sensor::default();
+    assert_eq!(
+        permits
+            .process(
+                &mut controller,
+                &mut sensor,
+                &read_multiple_request(&[0x0011, 0x000E])
+            )
+            .await,
+        Err(Error::CommandFailed(Status::Vendor(
+            VendorStatus::InsufficientResources
+        )))
+    );
+    controller.verify();
+    // The read is denied without updating the remaining values.
+    assert_eq!(sensor.reads, [AttributeHandle(0x0011)]);
+}
+
+#[tokio::test]
+async fn read_blob_keeps_value() {
+    let mut controller = MockController::new();
+    controller.expect(opcode::GATT_ALLOW_READ, &[0x01, 0x08]);
+
+    let mut permits = permits();
+    let mut sensor = Sensor::default();
+    assert!(permits
+        .process(&mut controller, &mut sensor, &read_request(0x000E, 22))
+        .await
+        .unwrap());
+    controller.verify();
+    assert!(sensor.reads.is_empty());
+}
+
+#[tokio::test]
+async fn allows_reads_of_other_attributes() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GATT_ALLOW_READ, &[0x01, 0x08])
+        .expect(opcode::GATT_ALLOW_READ, &[0x01, 0x08]);
+
+    let mut permits = permits();
+    let mut sensor = Sensor::default();
+    // Not registered.
+    assert!(permits
+        .process(&mut controller, &mut sensor, &read_request(0x0020, 0))
+        .await
+        .unwrap());
+    // Registered, but the provider keeps the stored value.
+    assert!(permits
+        .process(&mut controller, &mut sensor, &read_request(0x0014, 0))
+        .await
+        .unwrap());
+    assert!(!permits
+        .process(
+            &mut controller,
+            &mut sensor,
+            &mock::parse_event(&mock::vendor_event(0x0C17, &[0x01, 0x08]))
+        )
+        .await
+        .unwrap());
+    controller.verify();
+    assert_eq!(sensor.reads, [AttributeHandle(0x0014)]);
+}
+
+#[tokio::test]
+async fn read_multiple_updates_each_value() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_UPDATE_CHARACTERISTIC_VALUE,
+            &[0x0C, 0x00, 0x10, 0x00, 0x00, 0x01, 0x2A],
+        )
+        .respond(update_complete(Status::Success))
+        .expect(
+            opcode::GATT_UPDATE_CHARACTERISTIC_VALUE,
+            &[0x0C, 0x00, 0x0D, 0x00, 0x00, 0x02, 0x15, 0x01],
+        )
+        .respond(update_complete(Status::Success))
+        .expect(opcode::GATT_ALLOW_READ, &[0x01, 0x08]);
+
+    let mut permits = permits();
+    let mut sensor = Sensor::default();
+    assert!(permits
+        .process(
+            &mut controller,
+            &mut sensor,
+            &read_multiple_request(&[0x0011, 0x0020, 0x000E])
+        )
+        .await
+        .unwrap());
+    controller.verify();
+    assert_eq!(
+        sensor.reads,
+        [AttributeHandle(0x0011), AttributeHandle(0x000E)]
+    );
+}
+
+#[test]
+fn registration() {
+    let mut permits: ReadPermits<2> = ReadPermits::new();
+    permits.register(SERVICE, TEMPERATURE).unwrap();
+    permits.register(SERVICE, TEMPERATURE).unwrap();
+    permits.register(SERVICE, HUMIDITY).unwrap();
+    assert_eq!(
+        permits.register(SERVICE, NAME),
+        Err(Error::TooManyCharacteristics(NAME))
+    );
+    assert!(permits.is_registered(AttributeHandle(0x000E)));
+    assert!(!permits.is_registered(TEMPERATURE));
+
+    permits.unregister(TEMPERATURE);
+    assert!(!permits.is_registered(AttributeHandle(0x000E)));
+    permits.register(SERVICE, NAME).unwrap();
+    assert!(permits.is_registered(AttributeHandle(0x0014)));
+}
@@ -0,0 +1,257 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::event::{AttError, AttRequest, AttributeHandle};
+use hci::vendor::stm32wb::opcode;
+use hci::vendor::stm32wb::remote_attribute::{self, Error, WriteMethod};
+use hci::{ConnectionHandle, Opcode, Status};
+
+const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+const HANDLE: AttributeHandle = AttributeHandle(0x0012);
+
+fn started(opcode: Opcode) -> Vec<u8> {
+    mock::command_status(opcode, Status::Success)
+}
+
+fn blob(value: &[u8]) -> Vec<u8> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.push(value.len() as u8);
+    params.extend_from_slice(value);
+    mock::vendor_event(0x0C08, &params)
+}
+
+fn prepare_write_response(offset: u16, value: &[u8]) -> Vec<u8> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.push(4 + value.len() as u8);
+    params.extend_from_slice(&HANDLE.0.to_le_bytes());
+    params.extend_from_slice(&offset.to_le_bytes());
+    params.extend_from_slice(value);
+    mock::vendor_event(0x0C0C, &params)
+}
+
+fn error_response(request: u8, error: u8) -> Vec<u8> {
+    mock::vendor_event(0x0C11, &[0x01, 0x08, request, 0x12, 0x00, error])
+}
+
+fn complete(status: u8) -> Vec<u8> {
+    mock::vendor_event(0x0C10, &[0x01, 0x08, status])
+}
+
+#[tokio::test]
+async fn read_collects_blob_responses() {
+    let value: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE,
+            &[0x01, 0x08, 0x12, 0x00, 0x00, 0x00],
+        )
+        .respond(started(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE))
+        .respond(blob(&value[..120]))
+        // A notification from the server is not part of the value.
+        .respond(mock::vendor_event(
+            0x0C0F,
+            &[0x01, 0x08, 0x03, 0x20, 0x00, 0xFF],
+        ))
+        .respond(blob(&value[120..240]))
+        .respond(blob(&value[240..]))
+        .respond(complete(0x00));
+
+    let mut buf = [0; 512];
+    let len = remote_attribute::read(&mut controller, CONN, HANDLE, &mut buf)
+        .await
+        .unwrap();
+    assert_eq!(&buf[..len], value.as_slice());
+    controller.verify();
+}
+
+#[tokio::test]
+async fn read_reports_value_length() {
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_READ_LONG_CHARACTERISTIC_DESCRIPTOR)
+        .respond(started(opcode::GATT_READ_LONG_CHARACTERISTIC_DESCRIPTOR))
+        .respond(blob(&[1, 2, 3, 4]))
+        .respond(blob(&[5, 6]))
+        .respond(complete(0x00));
+
+    let mut buf = [0; 5];
+    assert_eq!(
+        remote_attribute::read_descriptor(&mut controller, CONN, HANDLE, &mut buf).await,
+        Err(Error::BufferTooSmall(6))
+    );
+    assert_eq!(buf, [1, 2, 3, 4, 5]);
+    controller.verify();
+}
+
+#[tokio::test]
+async fn read_reports_procedure_errors() {
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE)
+        .respond(started(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE))
+        .respond(error_response(0x0C, 0x02))
+        .respond(complete(0x41))
+        .expect_opcode(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE)
+        .respond(started(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE))
+        .respond(blob(&[1, 2, 3]))
+        .respond(mock::vendor_event(0x0C02, &[0x01, 0x08]))
+        .expect_opcode(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE)
+        .respond(mock::command_status(
+            opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE,
+            Status::CommandDisallowed,
+        ))
+        .expect_opcode(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE)
+        .respond(started(opcode::GATT_READ_LONG_CHARACTERISTIC_VALUE))
+        .respond(mock::disconnection_complete(
+            CONN,
+            Status::ConnectionTimeout,
+        ));
+
+    let mut buf = [0; 16];
+    assert_eq!(
+        remote_attribute::read(&mut controller, CONN, HANDLE, &mut buf).await,
+        Err(Error::Att(
+            AttRequest::ReadBlobRequest,
+            HANDLE,
+            AttError::ReadNotPermitted
+        ))
+    );
+    assert_eq!(
+        remote_attribute::read(&mut controller, CONN, HANDLE, &mut buf).await,
+        Err(Error::Timeout)
+    );
+    assert_eq!(
+        remote_attribute::read(&mut controller, CONN, HANDLE, &mut buf).await,
+        Err(Error::CommandFailed(Status::CommandDisallowed))
+    );
+    assert_eq!(
+        remote_attribute::read(&mut controller, CONN, HANDLE, &mut buf).await,
+        Err(Error::Disconnected(Status::ConnectionTimeout))
+    );
+    controller.verify();
+}
+
+#[test]
+fn chooses_write_method() {
+    assert_eq!(WriteMethod::choose(23, 20), WriteMethod::Write);
+    assert_eq!(WriteMethod::choose(23, 21), WriteMethod::LongWrite);
+    assert_eq!(WriteMethod::choose(0, 20), WriteMethod::Write);
+    assert_eq!(WriteMethod::choose(247, 244), WriteMethod::Write);
+    assert_eq!(WriteMethod::choose(247, 248), WriteMethod::LongWrite);
+    assert_eq!(WriteMethod::choose(517, 250), WriteMethod::Write);
+    assert_eq!(WriteMethod::choose(517, 251), WriteMethod::ReliableWrite);
+}
+
+#[tokio::test]
+async fn writes_short_and_long_values() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_WRITE_CHARACTERISTIC_VALUE,
+            &[0x01, 0x08, 0x12, 0x00, 0x02, 0xAA, 0xBB],
+        )
+        .respond(started(opcode::GATT_WRITE_CHARACTERISTIC_VALUE))
+        .respond(complete(0x00))
+        .expect_opcode(opcode::GATT_WRITE_LONG_CHARACTERISTIC_VALUE)
+        .respond(started(opcode::GATT_WRITE_LONG_CHARACTERISTIC_VALUE))
+        .respond(prepare_write_response(0, &[0; 18]))
+        .respond(prepare_write_response(18, &[0; 12]))
+        .respond(mock::vendor_event(0x0C0D, &[0x01, 0x08]))
+        .respond(complete(0x00));
+
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &[0xAA, 0xBB], 23).await,
+        Ok(WriteMethod::Write)
+    );
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &[0; 30], 23).await,
+        Ok(WriteMethod::LongWrite)
+    );
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &[0; 513], 23).await,
+        Err(Error::ValueTooLong(513))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reliable_write_checks_echoed_parts() {
+    let value: Vec<u8> = (0..300).map(|i| (i * 5) as u8).collect();
+    let mut controller = MockController::new();
+    for (offset, part) in [(0u16, &value[..242]), (242, &value[242..])] {
+        let mut params = vec![0x01, 0x08, 0x12, 0x00];
+        params.extend_from_slice(&offset.to_le_bytes());
+        params.push(part.len() as u8);
+        params.extend_from_slice(part);
+        controller
+            .expect(opcode::GATT_PREPARE_WRITE_REQUEST, &params)
+            .respond(started(opcode::GATT_PREPARE_WRITE_REQUEST))
+            .respond(prepare_write_response(offset, part))
+            .respond(complete(0x00));
+    }
+    controller
+        .expect(opcode::GATT_EXECUTE_WRITE_REQUEST, &[0x01, 0x08, 0x01])
+        .respond(started(opcode::GATT_EXECUTE_WRITE_REQUEST))
+        .respond(mock::vendor_event(0x0C0D, &[0x01, 0x08]))
+        .respond(complete(0x00));
+
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &value, 247).await,
+        Ok(WriteMethod::ReliableWrite)
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reliable_write_cancels_on_mismatch() {
+    let value = [0x11; 260];
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_PREPARE_WRITE_REQUEST)
+        .respond(started(opcode::GATT_PREPARE_WRITE_REQUEST))
+        .respond(prepare_write_response(0, &[0x11; 18]))
+        .respond(complete(0x00))
+        .expect_opcode(opcode::GATT_PREPARE_WRITE_REQUEST)
+        .respond(started(opcode::GATT_PREPARE_WRITE_REQUEST))
+        .respond(prepare_write_response(18, &[0x22; 18]))
+        .respond(complete(0x00))
+        .expect(opcode::GATT_EXECUTE_WRITE_REQUEST, &[0x01, 0x08, 0x00])
+        .respond(started(opcode::GATT_EXECUTE_WRITE_REQUEST))
+        .respond(mock::vendor_event(0x0C0D, &[0x01, 0x08]))
+        .respond(complete(0x00));
+
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &value, 23).await,
+        Err(Error::ReliableWriteMismatch(18))
+    );
+    controller.verify();
+}
+
+#[tokio::test]
+async fn reliable_write_cancels_on_error_response() {
+    let value = [0x11; 260];
+    let mut controller = MockController::new();
+    controller
+        .expect_opcode(opcode::GATT_PREPARE_WRITE_REQUEST)
+        .respond(started(opcode::GATT_PREPARE_WRITE_REQUEST))
+        .respond(error_response(0x16, 0x09))
+        .respond(complete(0x41))
+        .expect(opcode::GATT_EXECUTE_WRITE_REQUEST, &[0x01, 0x08, 0x00])
+        .respond(started(opcode::GATT_EXECUTE_WRITE_REQUEST))
+        .respond(mock::vendor_event(0x0C0D, &[0x01, 0x08]))
+        .respond(complete(0x00));
+
+    assert_eq!(
+        remote_attribute::write(&mut controller, CONN, HANDLE, &value, 23).await,
+        Err(Error::Att(
+            AttRequest::PrepareWriteRequest,
+            HANDLE,
+            AttError::PrepareQueueFull
+        ))
+    );
+    controller.verify();
+}
@@ -0,0 +1,184 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::{AdvertisementEvent, Event};
+use hci::mock;
+use hci::vendor::stm32wb::command::gatt::Uuid;
+use hci::vendor::stm32wb::event::Stm32Wb5xEvent;
+use hci::vendor::stm32wb::scanner::{Filter, ScanConfig, Scanner};
+use hci::{BdAddr, BdAddrType};
+use std::time::Duration;
+
+const CONFIG: ScanConfig = ScanConfig {
+    dedup_window: Duration::from_secs(1),
+    rssi_smoothing: 4,
+};
+
+// Complete local name "Sensor", then the 16-bit service UUID 0x180F.
+const ADV_DATA: [u8; 12] = [
+    0x07, 0x09, b'S', b'e', b'n', b's', b'o', b'r', 0x03, 0x03, 0x0F, 0x18,
+];
+
+// Manufacturer specific data of company 0x0059.
+const SCAN_RESPONSE_DATA: [u8; 5] = [0x04, 0xFF, 0x59, 0x00, 0x2A];
+
+fn address(last: u8) -> BdAddrType {
+    BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, last]))
+}
+
+fn advertising_report(event_type: u8, last: u8, data: &[u8], rssi: i8) -> Event<Stm32Wb5xEvent> {
+    let mut params = vec![
+        0x02,
+        1,
+        event_type,
+        0x00,
+        1,
+        2,
+        3,
+        4,
+        5,
+        last,
+        data.len() as u8,
+    ];
+    params.extend_from_slice(data);
+    params.push(rssi as u8);
+    mock::parse_event(&mock::event(0x3E, &params))
+}
+
+fn device_found(event_type: u8, last: u8, data: &[u8], rssi: i8) -> Event<Stm32Wb5xEvent> {
+    let mut params = vec![event_type, 0x00, 1, 2, 3, 4, 5, last, data.len() as u8];
+    params.extend_from_slice(data);
+    params.push(rssi as u8);
+    mock::parse_event(&mock::vendor_event(0x0406, &params))
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn merges_scan_responses_and_deduplicates() {
+    let mut scanner: Scanner<4> = Scanner::new(CONFIG, &[]);
+    let mut reported = Vec::new();
+
+    let advertisement = advertising_report(0x00, 6, &ADV_DATA, -40);
+    scanner.handle(&advertisement, ms(0), |device| {
+        reported.push(device.address)
+    });
+    assert_eq!(reported, [address(6)]);
+
+    // The scan response comes from the vendor event, within the deduplication window.
+    let scan_response = device_found(0x04, 6, &SCAN_RESPONSE_DATA, -60);
+    scanner.handle(&scan_response, ms(100), |device| {
+        reported.push(device.address)
+    });
+    assert_eq!(reported, [address(6)]);
+
+    let device = scanner.device(address(6)).unwrap();
+    assert_eq!(device.event_type, AdvertisementEvent::Advertisement);
+    assert_eq!(device.advertising_data(), ADV_DATA);
+    assert_eq!(device.scan_response_data(), SCAN_RESPONSE_DATA);
+    assert_eq!(device.local_name(), Some(&b"Sensor"[..]));
+    assert_eq!(device.manufacturer_data(), Some((0x0059, &[0x2A][..])));
+    assert!(device.has_service(Uuid::Uuid16(0x180F)));
+    assert!(!device.has_service(Uuid::Uuid16(0x180D)));
+    assert_eq!(device.rssi(), Some(-45));
+    assert_eq!(device.last_seen, ms(100));
+
+    scanner.handle(&advertisement, ms(999), |device| {
+        reported.push(device.address)
+    });
+    assert_eq!(reported, [address(6)]);
+    scanner.handle(&advertisement, ms(1_000), |device| {
+        reported.push(device.address)
+    });
+    assert_eq!(reported, [address(6), address(6)]);
+}
+
+#[test]
+fn reports_matching_devices() {
+    let addresses = [address(9)];
+    let filters = [
+        Filter::ServiceUuid(Uuid::Uuid16(0x180F)),
+        Filter::Addresses(&addresses),
+    ];
+    let mut scanner: Scanner<4> = Scanner::new(CONFIG, &filters);
+    let mut reported = Vec::new();
+
+    scanner.handle(
+        &advertising_report(0x03, 7, &[0x02, 0x01, 0x06], -50),
+        ms(0),
+        |device| reported.push(device.address),
+    );
+    scanner.handle(
+        &advertising_report(0x00, 8, &ADV_DATA, -50),
+        ms(0),
+        |device| reported.push(device.address),
+    );
+    scanner.handle(&device_found(0x03, 9, &[], 127), ms(0), |device| {
+        reported.push(device.address)
+    });
+    assert_eq!(reported, [address(8), address(9)]);
+    assert_eq!(scanner.devices().count(), 2);
+    assert_eq!(scanner.device(address(9)).unwrap().rssi(), None);
+
+    // The scan response of the first device does not list the service either.
+    scanner.handle(
+        &advertising_report(0x04, 7, &SCAN_RESPONSE_DATA, -50),
+        ms(10),
+        |device| reported.push(device.address),
+    );
+    assert_eq!(reported, [address(8), address(9)]);
+    let name_filters = [Filter::NamePrefix(b"Sen"), Filter::ManufacturerId(0x0059)];
+    scanner.set_filters(&name_filters);
+    let matching: Vec<_> = scanner.devices().map(|device| device.address).collect();
+    assert_eq!(matching.len(), 2);
+    assert!(matching.contains(&address(7)) && matching.contains(&address(8)));
+}
+
+#[test]
+fn replaces_devices_seen_least_recently() {
+    let mut scanner: Scanner<2> = Scanner::new(CONFIG, &[]);
+    for (last, now) in [(1, 0), (2, 10), (1, 20), (3, 30)] {
+        let report = advertising_report(0x00, last, &ADV_DATA, -50);
+        scanner.handle(&report, ms(now), |_| ());
+    }
+    assert!(scanner.device(address(1)).is_some());
+    assert!(scanner.device(address(2)).is_none());
+    assert!(scanner.device(address(3)).is_some());
+
+    scanner.expire(ms(1_025), ms(1_000));
+    assert!(scanner.device(address(1)).is_none());
+    assert!(scanner.device(address(3)).is_some());
+
+    scanner.clear();
+    assert_eq!(scanner.devices().count(), 0);
+}
+
+#[test]
+fn smooths_rssi() {
+    let rssi = |rssi_smoothing, samples: &[i8]| {
+        let mut scanner: Scanner<1> = Scanner::new(
+            ScanConfig {
+                rssi_smoothing,
+                ..CONFIG
+            },
+            &[],
+        );
+        samples
+            .iter()
+            .map(|&sample| {
+                scanner.handle(&advertising_report(0x00, 6, &[], sample), ms(0), |_| ());
+                scanner.device(address(6)).unwrap().rssi().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(rssi(0, &[-40, -80, -60]), [-40, -80, -60]);
+    assert_eq!(rssi(1, &[-40, -80, -60]), [-40, -80, -60]);
+    // Each report moves the RSSI by a quarter of the difference: -40, -50, -57.5 and -63.125,
+    // rounded to the nearest dBm with halves up.
+    assert_eq!(rssi(4, &[-40, -80, -80, -80]), [-40, -50, -57, -63]);
+}
@@ -0,0 +1,307 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::host::uart::{Packet, UartHci};
+use hci::mock::{self, read_vendor_return, MockController};
+use hci::vendor::stm32wb::command::shci::*;
+use hci::vendor::stm32wb::event::command::{
+    FusErrorCode, FusState, ReturnParameters as VendorReturnParameters,
+};
+use hci::vendor::stm32wb::event::{
+    FirmwareKind, ShciErrorCode, ShciNvmRamUpdate, Stm32Wb5xError, Stm32Wb5xEvent,
+};
+use hci::vendor::stm32wb::opcode;
+use hci::Opcode;
+
+async fn read_vendor_event(controller: &mut MockController) -> Stm32Wb5xEvent {
+    match controller.read().await.unwrap() {
+        Packet::Event(Event::Vendor(event)) => event,
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+#[test]
+fn opcodes() {
+    assert_eq!(opcode::SHCI_FUS_GET_STATE, Opcode(0xFC52));
+    assert_eq!(opcode::SHCI_BLE_INIT, Opcode(0xFC66));
+    assert_eq!(opcode::SHCI_CONFIG, Opcode(0xFC75));
+
+    assert!(is_system_command(opcode::SHCI_BLE_INIT));
+    assert!(is_system_command(opcode::SHCI_FUS_GET_STATE));
+    assert!(!is_system_command(opcode::HAL_GET_FIRMWARE_REVISION));
+    assert!(!is_system_command(opcode::GAP_INIT));
+    assert!(!is_system_command(Opcode(0x0C03)));
+}
+
+#[tokio::test]
+async fn ble_init() {
+    #[rustfmt::skip]
+    let expected = [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0x44, 0x00, 0x08, 0x00, 0x40, 0x05, 0x08, 0x01, 0x3A, 0x79, 0x9C, 0x00,
+        0xF4, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x48, 0x01, 0x01, 0x18,
+        0x00, 0x20, 0xD8, 0x06, 0x00, 0x02, 0xCF, 0x00, 0xEC, 0xFF, 0x05, 0x00,
+        0x0C, 0x00, 0x00, 0x00,
+    ];
+
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_BLE_INIT, &expected)
+        .respond(mock::command_complete_status(
+            opcode::SHCI_BLE_INIT,
+            hci::Status::Success,
+        ));
+
+    controller
+        .c2_ble_init(&BleInitParameters {
+            options: BleInitOptions::EXT_ADV | BleInitOptions::CS_ALG2,
+            tx_path_compens: -20,
+            rx_path_compens: 5,
+            ..Default::default()
+        })
+        .await;
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciBleInit(status) => assert_eq!(status, hci::Status::Success),
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    assert!(controller.is_done());
+}
+
+#[tokio::test]
+async fn single_byte_commands() {
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::SHCI_FLASH_ERASE_ACTIVITY, &[0x01])
+        .expect(opcode::SHCI_CONCURRENT_SET_MODE, &[0x02])
+        .expect(opcode::SHCI_RADIO_ALLOW_LOW_POWER, &[0x01, 0x00])
+        .expect(opcode::SHCI_FUS_GET_STATE, &[]);
+
+    controller
+        .c2_flash_erase_activity(FlashEraseActivity::On)
+        .await;
+    controller
+        .c2_concurrent_set_mode(ConcurrentMode::Zigbee)
+        .await;
+    controller
+        .c2_radio_allow_low_power(RadioStack::Ieee802154, false)
+        .await;
+    controller.c2_fus_get_state().await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn debug_init() {
+    let mut controller = MockController::new();
+    let mut expected = vec![0; 12];
+    expected.extend_from_slice(&[
+        0x00, 0x10, 0x00, 0x20, 0x00, 0x20, 0x00, 0x20, 0x00, 0x30, 0x00, 0x20, 0x04, 0x08, 0x01,
+    ]);
+    controller.expect(opcode::SHCI_DEBUG_INIT, &expected);
+
+    controller
+        .c2_debug_init(&DebugInitParameters {
+            gpio_config: 0x2000_1000,
+            traces_config: 0x2000_2000,
+            general_config: 0x2000_3000,
+            gpio_config_size: 4,
+            traces_config_size: 8,
+            general_config_size: 1,
+        })
+        .await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn extpa_and_config() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::SHCI_EXTPA_CONFIG,
+            &[0x00, 0x04, 0x00, 0x48, 0x08, 0x00, 0x01, 0x01],
+        )
+        .expect(
+            opcode::SHCI_CONFIG,
+            &[
+                0x10, 0x01, 0x43, 0x00, 0x00, 0x00, 0x03, 0x20, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20,
+                0x95, 0x04,
+            ],
+        );
+
+    controller
+        .c2_extpa_config(&ExtpaConfig {
+            gpio_port_base: 0x4800_0400,
+            gpio_pin: 0x0008,
+            active_low: true,
+            enable: true,
+        })
+        .await;
+    controller
+        .c2_config(&ConfigParameters {
+            config: ConfigFlags::BLE_NVM_SRAM,
+            event_mask: SystemEventMask::ERROR_NOTIFICATION
+                | SystemEventMask::BLE_NVM_RAM_UPDATE
+                | SystemEventMask::NVM_END_ERASE,
+            ble_nvm_ram_address: 0x2003_0000,
+            thread_nvm_ram_address: 0,
+            revision_id: 0x2001,
+            device_id: 0x0495,
+        })
+        .await;
+    controller.verify();
+}
+
+#[tokio::test]
+async fn return_parameters() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::command_complete(
+            opcode::SHCI_FUS_GET_STATE,
+            &[0x10, 0x00],
+        ))
+        .push_event(mock::command_complete(opcode::SHCI_FUS_GET_STATE, &[0xFF]))
+        .push_event(mock::command_complete_status(
+            opcode::SHCI_CONFIG,
+            hci::Status::Vendor(hci::vendor::stm32wb::event::Status::Timeout),
+        ));
+
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciFusGetState(state) => {
+            assert_eq!(state.state, FusState::FirmwareUpgradeOngoing(0));
+            assert_eq!(state.error_code, FusErrorCode::NoError);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciFusGetState(state) => {
+            assert_eq!(state.state, FusState::Error);
+            assert_eq!(state.error_code, FusErrorCode::NoError);
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    match read_vendor_return(&mut controller).await {
+        VendorReturnParameters::ShciConfig(status) => assert_eq!(
+            status,
+            hci::Status::Vendor(hci::vendor::stm32wb::event::Status::Timeout)
+        ),
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn coprocessor_ready() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::vendor_event(0x9200, &[0x00]))
+        .push_event(mock::vendor_event(0x9200, &[0x01]))
+        .push_event(mock::vendor_event(0x9200, &[0x02]));
+
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::CoprocessorReady(kind) => assert_eq!(kind, FirmwareKind::Wireless),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::CoprocessorReady(kind) => assert_eq!(kind, FirmwareKind::Rcc),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::UnknownFirmwareKind(0x02),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn system_events() {
+    let mut controller = MockController::new();
+    controller
+        .push_event(mock::vendor_event(0x9201, &[125]))
+        .push_event(mock::vendor_event(
+            0x9202,
+            &[0x00, 0x00, 0x03, 0x20, 0x40, 0x00, 0x00, 0x00],
+        ))
+        .push_event(mock::vendor_event(
+            0x9203,
+            &[0x00, 0x10, 0x03, 0x20, 0x00, 0x01, 0x00, 0x00],
+        ))
+        .push_event(mock::vendor_event(0x9204, &[0x20, 0x00, 0x00, 0x00]))
+        .push_event(mock::vendor_event(0x9205, &[]))
+        .push_event(mock::vendor_event(0x9206, &[0x02, 0x00, 0x00, 0x00]))
+        .push_event(mock::vendor_event(0x9207, &[]))
+        .push_event(mock::vendor_event(0x9201, &[0x01]));
+
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciErrorNotification(code) => {
+            assert_eq!(code, ShciErrorCode::ThreadLldFatalError)
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciBleNvmRamUpdate(update) => assert_eq!(
+            update,
+            ShciNvmRamUpdate {
+                start_address: 0x2003_0000,
+                size: 0x40,
+            }
+        ),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciThreadNvmRamUpdate(update) => assert_eq!(
+            update,
+            ShciNvmRamUpdate {
+                start_address: 0x2003_1000,
+                size: 0x100,
+            }
+        ),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciNvmStartWrite(words) => assert_eq!(words, 0x20),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciNvmEndWrite => (),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciNvmStartErase(sectors) => assert_eq!(sectors, 2),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match read_vendor_event(&mut controller).await {
+        Stm32Wb5xEvent::ShciNvmEndErase => (),
+        other => panic!("unexpected event {:?}", other),
+    }
+    match controller.read().await {
+        Err(hci::host::uart::Error::BLE(hci::event::Error::Vendor(
+            Stm32Wb5xError::UnknownShciErrorCode(0x01),
+        ))) => (),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn wireless_fw_info() {
+    let info = WirelessFwInfo::from_bytes(&[
+        0x20, 0x02, 0x0F, 0x01, 0x0E, 0x1C, 0x08, 0x1F, 0x01, 0x00, 0x00, 0x00,
+    ]);
+    assert_eq!(
+        info,
+        WirelessFwInfo {
+            version_major: 1,
+            version_minor: 15,
+            version_sub: 2,
+            version_branch: 2,
+            version_release_type: 0,
+            memory_size_sram2b: 0x1F,
+            memory_size_sram2a: 0x08,
+            memory_size_sram1: 0x1C,
+            memory_size_flash: 0x0E,
+            stack_type: 0x01,
+        }
+    );
+}
@@ -0,0 +1,232 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{AdvertisingFilterPolicy, AdvertisingType, OwnAddressType};
+use hci::mock::read_vendor_return;
+use hci::mock::simulator::{PeerNotification, Simulator, SimulatorError, State};
+use hci::vendor::stm32wb::command::gap::{DiscoverableParameters, GapCommands, Role};
+use hci::vendor::stm32wb::command::gatt::*;
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::event::{AttributeHandle, Stm32Wb5xEvent};
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Controller};
+
+async fn read_vendor_event(sim: &mut Simulator) -> Stm32Wb5xEvent {
+    match sim.read().await.unwrap() {
+        Packet::Event(Event::Vendor(event)) => event,
+        other => panic!("unexpected packet {:?}", other),
+    }
+}
+
+async fn setup_server(sim: &mut Simulator) -> AttributeHandle {
+    sim.init_gatt().await;
+    match read_vendor_return(sim).await {
+        VendorReturnParameters::GattInit(status) => assert_eq!(status, hci::Status::Success),
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+
+    sim.init_gap(Role::PERIPHERAL, false, 8).await;
+    match read_vendor_return(sim).await {
+        VendorReturnParameters::GapInit(params) => {
+            assert_eq!(params.status, hci::Status::Success);
+            assert_eq!(params.service_handle, AttributeHandle(0x0005));
+            assert_eq!(params.dev_name_handle, AttributeHandle(0x0006));
+            assert_eq!(params.appearance_handle, AttributeHandle(0x0008));
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+
+    sim.add_service(&AddServiceParameters {
+        uuid: Uuid::Uuid128([0x11; 16]),
+        service_type: ServiceType::Primary,
+        max_attribute_records: 6,
+    })
+    .await;
+    let service_handle = match read_vendor_return(sim).await {
+        VendorReturnParameters::GattAddService(params) => params.service_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    assert_eq!(service_handle, AttributeHandle(0x000A));
+
+    sim.add_characteristic(&AddCharacteristicParameters {
+        service_handle,
+        characteristic_uuid: Uuid::Uuid16(0x2A37),
+        characteristic_value_len: 4,
+        characteristic_properties: CharacteristicProperty::NOTIFY | CharacteristicProperty::WRITE,
+        security_permissions: CharacteristicPermission::empty(),
+        gatt_event_mask: CharacteristicEvent::ATTRIBUTE_WRITE,
+        encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+        is_variable: true,
+    })
+    .await;
+    let characteristic_handle = match read_vendor_return(sim).await {
+        VendorReturnParameters::GattAddCharacteristic(params) => params.characteristic_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    assert_eq!(characteristic_handle, AttributeHandle(0x000B));
+
+    sim.set_discoverable(&DiscoverableParameters {
+        advertising_type: AdvertisingType::ConnectableUndirected,
+        advertising_interval: None,
+        address_type: OwnAddressType::Public,
+        filter_policy: AdvertisingFilterPolicy::AllowConnectionAndScan,
+        local_name: None,
+        advertising_data: &[],
+        conn_interval: (None, None),
+    })
+    .await
+    .unwrap();
+    match read_vendor_return(sim).await {
+        VendorReturnParameters::GapSetDiscoverable(status) => {
+            assert_eq!(status, hci::Status::Success)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    assert_eq!(sim.state(), State::Advertising);
+
+    characteristic_handle
+}
+
+#[tokio::test]
+async fn gatt_database_handles() {
+    let mut sim = Simulator::new();
+    setup_server(&mut sim).await;
+
+    let handles: Vec<u16> = sim.attributes().iter().map(|a| a.handle.0).collect();
+    assert_eq!(handles, [1, 2, 3, 4, 5, 6, 7, 8, 9, 0x0A, 0x0B, 0x0C, 0x0D]);
+    assert_eq!(sim.pending_events(), 0);
+}
+
+#[tokio::test]
+async fn rejects_truncated_characteristic() {
+    let mut sim = Simulator::new();
+    setup_server(&mut sim).await;
+    let attributes = sim.attributes().len();
+
+    // Service handle and 16-bit UUID, then everything up to the key size, without the variable
+    // length flag.
+    sim.controller_write(
+        hci::vendor::stm32wb::opcode::GATT_ADD_CHARACTERISTIC,
+        &[
+            0x0A, 0x00, 0x01, 0x38, 0x2A, 0x04, 0x00, 0x02, 0x00, 0x00, 0x07,
+        ],
+    )
+    .await;
+    match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddCharacteristic(params) => assert_eq!(
+            params.status,
+            hci::Status::Vendor(hci::vendor::stm32wb::event::Status::InsufficientResources)
+        ),
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    assert_eq!(sim.attributes().len(), attributes);
+}
+
+#[tokio::test]
+async fn peer_subscribes_and_receives_notifications() {
+    let mut sim = Simulator::new();
+    let characteristic_handle = setup_server(&mut sim).await;
+    let value_handle = AttributeHandle(characteristic_handle.0 + 1);
+
+    let conn_handle = sim
+        .connect(BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6])))
+        .unwrap();
+    assert_eq!(sim.state(), State::Connected);
+    match sim.read().await.unwrap() {
+        Packet::Event(Event::LeConnectionComplete(event)) => {
+            assert_eq!(event.conn_handle, conn_handle)
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+
+    sim.peer_subscribe(conn_handle, value_handle, true, false)
+        .unwrap();
+    match read_vendor_event(&mut sim).await {
+        Stm32Wb5xEvent::GattAttributeModified(event) => {
+            assert_eq!(event.attr_handle, AttributeHandle(0x000D));
+            assert_eq!(event.data(), [0x01, 0x00]);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    sim.update_characteristic_value(&UpdateCharacteristicValueParameters {
+        service_handle: AttributeHandle(0x000A),
+        characteristic_handle,
+        offset: 0,
+        value: &[1, 2, 3],
+    })
+    .await
+    .unwrap();
+    match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattUpdateCharacteristicValue(status) => {
+            assert_eq!(status, hci::Status::Success)
+        }
+        other => panic!("unexpected return parameters {:?}", other),
+    }
+    assert_eq!(
+        sim.take_peer_notifications(conn_handle).unwrap(),
+        [PeerNotification {
+            handle: value_handle,
+            value: vec![1, 2, 3],
+            indication: false,
+        }]
+    );
+
+    sim.peer_write(conn_handle, value_handle, &[9, 8]).unwrap();
+    match read_vendor_event(&mut sim).await {
+        Stm32Wb5xEvent::GattAttributeModified(event) => {
+            assert_eq!(event.attr_handle, value_handle);
+            assert_eq!(event.data(), [9, 8]);
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert_eq!(sim.attribute(value_handle).unwrap().value, [9, 8]);
+
+    sim.terminate(conn_handle, hci::Status::RemoteTerminationByUser)
+        .await
+        .unwrap();
+    match sim.read().await.unwrap() {
+        Packet::Event(Event::CommandStatus(event)) => {
+            assert_eq!(event.status, hci::Status::Success)
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+    match sim.read().await.unwrap() {
+        Packet::Event(Event::DisconnectionComplete(event)) => {
+            assert_eq!(event.conn_handle, conn_handle)
+        }
+        other => panic!("unexpected packet {:?}", other),
+    }
+    assert_eq!(sim.state(), State::Idle);
+}
+
+#[tokio::test]
+async fn peer_errors() {
+    let mut sim = Simulator::new();
+    assert_eq!(
+        sim.connect(BdAddrType::Public(BdAddr([0; 6]))),
+        Err(SimulatorError::NotAdvertising)
+    );
+
+    let characteristic_handle = setup_server(&mut sim).await;
+    let conn_handle = sim.connect(BdAddrType::Public(BdAddr([0; 6]))).unwrap();
+    assert_eq!(
+        sim.peer_write(conn_handle, AttributeHandle(0x0007), &[0]),
+        Err(SimulatorError::NotWritable(AttributeHandle(0x0007)))
+    );
+    assert_eq!(
+        sim.peer_write(conn_handle, AttributeHandle(0x0C), &[0; 5]),
+        Err(SimulatorError::ValueTooLong(AttributeHandle(0x0C)))
+    );
+    assert_eq!(
+        sim.peer_subscribe(conn_handle, AttributeHandle(0x0007), true, false),
+        Err(SimulatorError::NotSubscribable(AttributeHandle(0x0007)))
+    );
+    assert_eq!(
+        sim.peer_write(ConnectionHandle(0x0100), characteristic_handle, &[0]),
+        Err(SimulatorError::UnknownConnection(ConnectionHandle(0x0100)))
+    );
+}
@@ -0,0 +1,249 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::{ConnectionRole, Event};
+use hci::host::uart::{Packet, UartHci};
+use hci::host::{AdvertisingFilterPolicy, AdvertisingType, OwnAddressType};
+use hci::mock::simulator::Simulator;
+use hci::mock::{self, read_vendor_return};
+use hci::vendor::stm32wb::command::gap::{DiscoverableParameters, GapCommands};
+use hci::vendor::stm32wb::command::gatt::*;
+use hci::vendor::stm32wb::event::command::ReturnParameters as VendorReturnParameters;
+use hci::vendor::stm32wb::event::{AttributeHandle, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::subscription::{Error, Subscription, SubscriptionChange, Subscriptions};
+use hci::{BdAddr, BdAddrType, ConnectionHandle, Status};
+use std::time::Duration;
+
+const CHARACTERISTIC: AttributeHandle = AttributeHandle(0x0010);
+const OTHER: AttributeHandle = AttributeHandle(0x0020);
+const PEER: BdAddrType = BdAddrType::Public(BdAddr([1, 2, 3, 4, 5, 6]));
+
+fn connected(conn_handle: ConnectionHandle, peer: BdAddrType) -> Event<Stm32Wb5xEvent> {
+    mock::parse_event(&mock::le_connection_complete(
+        conn_handle,
+        ConnectionRole::Peripheral,
+        peer,
+        Duration::from_millis(50),
+        0,
+        Duration::from_secs(5),
+    ))
+}
+
+fn disconnected(conn_handle: ConnectionHandle) -> Event<Stm32Wb5xEvent> {
+    mock::parse_event(&mock::disconnection_complete(
+        conn_handle,
+        Status::RemoteTerminationByUser,
+    ))
+}
+
+fn cccd_written(
+    conn_handle: ConnectionHandle,
+    handle: AttributeHandle,
+    value: &[u8],
+) -> Event<Stm32Wb5xEvent> {
+    let mut params = conn_handle.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&(handle.0 + 2).to_le_bytes());
+    params.extend_from_slice(&[0x00, 0x00]);
+    params.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    params.extend_from_slice(value);
+    mock::parse_event(&mock::vendor_event(0x0C01, &params))
+}
+
+fn tracker() -> Subscriptions<2, 2, 1> {
+    let mut subscriptions = Subscriptions::new();
+    subscriptions.register(CHARACTERISTIC).unwrap();
+    subscriptions.register(OTHER).unwrap();
+    subscriptions
+}
+
+#[test]
+fn decodes_descriptor_writes() {
+    let conn = ConnectionHandle(0x0801);
+    let mut subscriptions = tracker();
+    assert_eq!(subscriptions.handle(&connected(conn, PEER)), Ok(None));
+
+    assert_eq!(
+        subscriptions.handle(&cccd_written(conn, CHARACTERISTIC, &[0x01, 0x00])),
+        Ok(Some(SubscriptionChange {
+            conn_handle: conn,
+            characteristic_handle: CHARACTERISTIC,
+            previous: Subscription::empty(),
+            current: Subscription::NOTIFY,
+        }))
+    );
+    assert_eq!(
+        subscriptions.handle(&cccd_written(conn, CHARACTERISTIC, &[0x03, 0x00])),
+        Ok(Some(SubscriptionChange {
+            conn_handle: conn,
+            characteristic_handle: CHARACTERISTIC,
+            previous: Subscription::NOTIFY,
+            current: Subscription::NOTIFY | Subscription::INDICATE,
+        }))
+    );
+    // Unchanged.
+    assert_eq!(
+        subscriptions.handle(&cccd_written(conn, CHARACTERISTIC, &[0x03, 0x00])),
+        Ok(None)
+    );
+    // Not a registered descriptor.
+    assert_eq!(
+        subscriptions.handle(&cccd_written(conn, AttributeHandle(0x0030), &[0x01, 0x00])),
+        Ok(None)
+    );
+
+    assert_eq!(
+        subscriptions.subscription(conn, CHARACTERISTIC),
+        Subscription::NOTIFY | Subscription::INDICATE
+    );
+    assert_eq!(
+        subscriptions.subscription(conn, OTHER),
+        Subscription::empty()
+    );
+    assert_eq!(
+        subscriptions
+            .subscribers(CHARACTERISTIC)
+            .collect::<Vec<_>>(),
+        [(conn, Subscription::NOTIFY | Subscription::INDICATE)]
+    );
+    assert_eq!(subscriptions.subscribers(OTHER).count(), 0);
+
+    assert_eq!(subscriptions.handle(&disconnected(conn)), Ok(None));
+    assert_eq!(subscriptions.subscribers(CHARACTERISTIC).count(), 0);
+}
+
+#[test]
+fn restores_bonded_peer() {
+    let first = ConnectionHandle(0x0801);
+    let second = ConnectionHandle(0x0802);
+    let other_peer = BdAddrType::Random(BdAddr([9, 9, 9, 9, 9, 0xC9]));
+    let mut subscriptions = tracker();
+
+    subscriptions.handle(&connected(first, PEER)).unwrap();
+    subscriptions.bond(first).unwrap();
+    subscriptions
+        .handle(&cccd_written(first, OTHER, &[0x02, 0x00]))
+        .unwrap();
+    subscriptions.handle(&disconnected(first)).unwrap();
+
+    // Another peer does not get the subscriptions, and cannot bond.
+    subscriptions.handle(&connected(first, other_peer)).unwrap();
+    assert_eq!(
+        subscriptions.subscription(first, OTHER),
+        Subscription::empty()
+    );
+    assert_eq!(subscriptions.bond(first), Err(Error::TooManyBonds(first)));
+
+    subscriptions.handle(&connected(second, PEER)).unwrap();
+    assert_eq!(
+        subscriptions.subscribers(OTHER).collect::<Vec<_>>(),
+        [(second, Subscription::INDICATE)]
+    );
+
+    subscriptions.forget(PEER);
+    subscriptions.handle(&disconnected(second)).unwrap();
+    subscriptions.handle(&connected(second, PEER)).unwrap();
+    assert_eq!(
+        subscriptions.subscription(second, OTHER),
+        Subscription::empty()
+    );
+}
+
+#[test]
+fn limits() {
+    let mut subscriptions: Subscriptions<1, 1> = Subscriptions::new();
+    subscriptions.register(CHARACTERISTIC).unwrap();
+    subscriptions.register(CHARACTERISTIC).unwrap();
+    assert_eq!(
+        subscriptions.register(OTHER),
+        Err(Error::TooManyCharacteristics(OTHER))
+    );
+
+    let conn = ConnectionHandle(0x0801);
+    subscriptions.handle(&connected(conn, PEER)).unwrap();
+    assert_eq!(
+        subscriptions.handle(&connected(ConnectionHandle(0x0802), PEER)),
+        Err(Error::TooManyConnections(ConnectionHandle(0x0802)))
+    );
+    assert_eq!(
+        subscriptions.bond(ConnectionHandle(0x0802)),
+        Err(Error::UnknownConnection(ConnectionHandle(0x0802)))
+    );
+    assert_eq!(subscriptions.bond(conn), Err(Error::TooManyBonds(conn)));
+}
+
+#[tokio::test]
+async fn tracks_simulated_peer() {
+    let mut sim = Simulator::new();
+    sim.init_gatt().await;
+    sim.read().await.unwrap();
+
+    sim.add_service(&AddServiceParameters {
+        uuid: Uuid::Uuid16(0x180D),
+        service_type: ServiceType::Primary,
+        max_attribute_records: 4,
+    })
+    .await;
+    let service_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddService(params) => params.service_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+    sim.add_characteristic(&AddCharacteristicParameters {
+        service_handle,
+        characteristic_uuid: Uuid::Uuid16(0x2A37),
+        characteristic_value_len: 2,
+        characteristic_properties: CharacteristicProperty::NOTIFY,
+        security_permissions: CharacteristicPermission::empty(),
+        gatt_event_mask: CharacteristicEvent::empty(),
+        encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+        is_variable: false,
+    })
+    .await;
+    let characteristic_handle = match read_vendor_return(&mut sim).await {
+        VendorReturnParameters::GattAddCharacteristic(params) => params.characteristic_handle,
+        other => panic!("unexpected return parameters {:?}", other),
+    };
+
+    let mut subscriptions: Subscriptions<1, 1> = Subscriptions::new();
+    subscriptions.register(characteristic_handle).unwrap();
+
+    sim.set_discoverable(&DiscoverableParameters {
+        advertising_type: AdvertisingType::ConnectableUndirected,
+        advertising_interval: None,
+        address_type: OwnAddressType::Public,
+        filter_policy: AdvertisingFilterPolicy::AllowConnectionAndScan,
+        local_name: None,
+        advertising_data: &[],
+        conn_interval: (None, None),
+    })
+    .await
+    .unwrap();
+    sim.read().await.unwrap();
+
+    let conn_handle = sim.connect(PEER).unwrap();
+    let Packet::Event(event) = sim.read().await.unwrap();
+    subscriptions.handle(&event).unwrap();
+
+    let value_handle = AttributeHandle(characteristic_handle.0 + 1);
+    sim.peer_subscribe(conn_handle, value_handle, true, false)
+        .unwrap();
+    let Packet::Event(event) = sim.read().await.unwrap();
+    assert_eq!(
+        subscriptions.handle(&event),
+        Ok(Some(SubscriptionChange {
+            conn_handle,
+            characteristic_handle,
+            previous: Subscription::empty(),
+            current: Subscription::NOTIFY,
+        }))
+    );
+
+    sim.peer_disconnect(conn_handle).unwrap();
+    let Packet::Event(event) = sim.read().await.unwrap();
+    subscriptions.handle(&event).unwrap();
+    assert_eq!(
+        subscriptions.subscription(conn_handle, characteristic_handle),
+        Subscription::empty()
+    );
+}
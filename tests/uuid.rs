@@ -0,0 +1,153 @@
+#![feature(async_fn_in_trait)]
+
+extern crate stm32wb_hci as hci;
+
+use hci::types::{Advertisement, Uuid, UuidError};
+
+const HEART_RATE: Uuid = Uuid::new("0000180D-0000-1000-8000-00805F9B34FB");
+const NORDIC_UART: Uuid = Uuid::new("6e400001-b5a3-f393-e0a9-e50e24dcca9e");
+
+// Nordic UART service, in the order sent over the air.
+const NORDIC_UART_BYTES: [u8; 16] = [
+    0x9e, 0xca, 0xdc, 0x24, 0x0e, 0xe5, 0xa9, 0xe0, 0x93, 0xf3, 0xa3, 0xb5, 0x01, 0x00, 0x40, 0x6e,
+];
+
+#[test]
+fn parse() {
+    assert!(matches!(HEART_RATE, Uuid::Uuid16(0x180D)));
+    assert!(matches!(
+        Uuid::new("12345678-0000-1000-8000-00805f9b34fb"),
+        Uuid::Uuid32(0x1234_5678)
+    ));
+    assert!(matches!(NORDIC_UART, Uuid::Uuid128(bytes) if bytes == NORDIC_UART_BYTES));
+    assert_eq!(
+        "6E400001-B5A3-F393-E0A9-E50E24DCCA9E".parse::<Uuid>(),
+        Ok(NORDIC_UART)
+    );
+
+    assert_eq!(Uuid::parse("180d"), Err(UuidError::BadStringLength(4)));
+    assert_eq!(
+        Uuid::parse("6e400001-b5a3-f393-e0a9_e50e24dcca9e"),
+        Err(UuidError::BadCharacter(23))
+    );
+    assert_eq!(
+        Uuid::parse("6e400001-b5a3-f393-e0a9-e50e24dcca9g"),
+        Err(UuidError::BadCharacter(35))
+    );
+}
+
+#[test]
+fn display() {
+    assert_eq!(
+        HEART_RATE.to_string(),
+        "0000180d-0000-1000-8000-00805f9b34fb"
+    );
+    assert_eq!(
+        NORDIC_UART.to_string(),
+        "6e400001-b5a3-f393-e0a9-e50e24dcca9e"
+    );
+}
+
+#[test]
+fn sizes() {
+    let long = HEART_RATE.to_uuid128();
+    assert!(matches!(long, Uuid::Uuid128(_)));
+    assert_eq!(long, HEART_RATE);
+    assert_eq!(long, Uuid::Uuid32(0x180D));
+    assert!(matches!(long.shortened(), Uuid::Uuid16(0x180D)));
+    assert_eq!(long.as_u16(), Some(0x180D));
+    assert_eq!(Uuid::Uuid32(0x1_0000).as_u16(), None);
+    assert_eq!(Uuid::Uuid32(0x1_0000).as_u32(), Some(0x1_0000));
+    assert_eq!(NORDIC_UART.as_u32(), None);
+    assert_ne!(HEART_RATE, NORDIC_UART);
+
+    assert_eq!(
+        NORDIC_UART.to_u128(),
+        0x6e400001_b5a3_f393_e0a9_e50e24dcca9e
+    );
+    assert_eq!(
+        Uuid::from(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e_u128),
+        NORDIC_UART
+    );
+    assert_eq!(Uuid::from(0x180D_u16), HEART_RATE);
+}
+
+#[test]
+fn wire_form() {
+    let mut bytes = [0; 16];
+    assert_eq!(HEART_RATE.copy_into_slice(&mut bytes), 2);
+    assert_eq!(bytes[..2], [0x0D, 0x18]);
+    assert_eq!(Uuid::Uuid32(0x1234_5678).copy_into_slice(&mut bytes), 4);
+    assert_eq!(bytes[..4], [0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(NORDIC_UART.copy_into_slice(&mut bytes), 16);
+    assert_eq!(bytes, NORDIC_UART_BYTES);
+
+    assert!(matches!(
+        Uuid::from_bytes(&[0x0D, 0x18]),
+        Ok(Uuid::Uuid16(0x180D))
+    ));
+    assert_eq!(Uuid::from_bytes(&NORDIC_UART_BYTES), Ok(NORDIC_UART));
+    assert_eq!(Uuid::from_bytes(&[0; 3]), Err(UuidError::BadLength(3)));
+}
+
+#[test]
+fn service_data() {
+    let mut bytes = [0; 31];
+    let len =
+        Advertisement::service_data(HEART_RATE.to_uuid128(), &[0x42]).copy_into_slice(&mut bytes);
+    assert_eq!(bytes[..len], [0x04, 0x16, 0x0D, 0x18, 0x42]);
+
+    let len = Advertisement::service_data(NORDIC_UART, &[0x42]).copy_into_slice(&mut bytes);
+    assert_eq!(bytes[..2], [0x12, 0x21]);
+    assert_eq!(bytes[2..18], NORDIC_UART_BYTES);
+    assert_eq!(bytes[18..len], [0x42]);
+}
+
+#[cfg(feature = "mock")]
+#[tokio::test]
+async fn gatt_commands_send_32_bit_uuids_in_128_bit_form() {
+    use hci::mock::MockController;
+    use hci::vendor::stm32wb::command::gatt::{
+        AddCharacteristicParameters, AddServiceParameters, CharacteristicEvent,
+        CharacteristicPermission, CharacteristicProperty, EncryptionKeySize, GattCommands,
+        ServiceType,
+    };
+    use hci::vendor::stm32wb::event::AttributeHandle;
+    use hci::vendor::stm32wb::opcode;
+
+    const UUID_128: [u8; 16] = [
+        0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x78, 0x56, 0x34,
+        0x12,
+    ];
+
+    let mut service = vec![0x02];
+    service.extend_from_slice(&UUID_128);
+    service.extend_from_slice(&[0x01, 0x04]);
+    let mut characteristic = vec![0x0C, 0x00, 0x02];
+    characteristic.extend_from_slice(&UUID_128);
+    characteristic.extend_from_slice(&[0x14, 0x00, 0x02, 0x00, 0x00, 0x07, 0x00]);
+    let mut controller = MockController::new();
+    controller
+        .expect(opcode::GATT_ADD_SERVICE, &service)
+        .expect(opcode::GATT_ADD_CHARACTERISTIC, &characteristic);
+    controller
+        .add_service(&AddServiceParameters {
+            uuid: Uuid::Uuid32(0x1234_5678),
+            service_type: ServiceType::Primary,
+            max_attribute_records: 4,
+        })
+        .await;
+    controller
+        .add_characteristic(&AddCharacteristicParameters {
+            service_handle: AttributeHandle(0x000C),
+            characteristic_uuid: Uuid::Uuid32(0x1234_5678),
+            characteristic_value_len: 20,
+            characteristic_properties: CharacteristicProperty::READ,
+            security_permissions: CharacteristicPermission::empty(),
+            gatt_event_mask: CharacteristicEvent::empty(),
+            encryption_key_size: EncryptionKeySize::with_value(7).unwrap(),
+            is_variable: false,
+        })
+        .await;
+    controller.verify();
+}
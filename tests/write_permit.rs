@@ -0,0 +1,336 @@
+#![feature(async_fn_in_trait)]
+#![cfg(feature = "mock")]
+
+extern crate stm32wb_hci as hci;
+
+use hci::event::Event;
+use hci::mock::{self, MockController};
+use hci::vendor::stm32wb::event::{AttError, AttributeHandle, Stm32Wb5xEvent};
+use hci::vendor::stm32wb::opcode;
+use hci::vendor::stm32wb::write_permit::{Write, WritePermits, WriteValidator};
+use hci::{ConnectionHandle, Status};
+
+const CONN: ConnectionHandle = ConnectionHandle(0x0801);
+const LOG: AttributeHandle = AttributeHandle(0x0010);
+const MODE: AttributeHandle = AttributeHandle(0x0020);
+
+#[derive(Default)]
+struct Device {
+    validated: Vec<(Vec<u8>, bool)>,
+    executed: Vec<(AttributeHandle, Vec<u8>)>,
+}
+
+impl WriteValidator for Device {
+    fn max_len(&self, attribute_handle: AttributeHandle) -> Option<usize> {
+        match attribute_handle {
+            LOG => Some(20),
+            MODE => Some(4),
+            _ => None,
+        }
+    }
+
+    fn validate(&mut self, write: &Write) -> Result<(), AttError> {
+        self.validated.push((write.value.to_vec(), write.prepared));
+        if write.value.contains(&0xFF) {
+            return Err(AttError::ApplicationError0x80);
+        }
+        Ok(())
+    }
+
+    fn executed(
+        &mut self,
+        _conn_handle: ConnectionHandle,
+        attribute_handle: AttributeHandle,
+        value: &[u8],
+    ) {
+        self.executed.push((attribute_handle, value.to_vec()));
+    }
+}
+
+fn write_request(handle: AttributeHandle, value: &[u8]) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&handle.0.to_le_bytes());
+    params.push(value.len() as u8);
+    params.extend_from_slice(value);
+    mock::parse_event(&mock::vendor_event(0x0C13, &params))
+}
+
+fn prepare_write_request(
+    handle: AttributeHandle,
+    offset: u16,
+    value: &[u8],
+) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&handle.0.to_le_bytes());
+    params.extend_from_slice(&offset.to_le_bytes());
+    params.push(value.len() as u8);
+    params.extend_from_slice(value);
+    mock::parse_event(&mock::vendor_event(0x0C18, &params))
+}
+
+fn attribute_modified(handle: AttributeHandle, value: &[u8]) -> Event<Stm32Wb5xEvent> {
+    let mut params = CONN.0.to_le_bytes().to_vec();
+    params.extend_from_slice(&handle.0.to_le_bytes());
+    params.extend_from_slice(&[0x00, 0x00]);
+    params.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    params.extend_from_slice(value);
+    mock::parse_event(&mock::vendor_event(0x0C01, &params))
+}
+
+fn status<const N: usize, const L: usize>(
+    permits: &mut WritePermits<N, L>,
+    device: &mut Device,
+    event: &Event<Stm32Wb5xEvent>,
+) -> Result<(), AttError> {
+    permits.handle(device, event).unwrap().status
+}
+
+#[tokio::test]
+async fn write_response_command() {
+    let mut controller = MockController::new();
+    controller
+        .expect(
+            opcode::GATT_WRITE_RESPONSE,
+            &[0x01, 0x08, 0x20, 0x00, 0x00, 0x00, 0x02, 0x01, 0x02],
+        )
+        .expect(
+            opcode::GATT_WRITE_RESPONSE,
+            &[0x01, 0x08, 0x20, 0x00, 0x01, 0x80, 0x01, 0xFF],
+        );
+
+    let mut permits: WritePermits<1, 32> = WritePermits::new();
+    let mut device = Device::default();
+    assert!(
+        permits
+            .process(&mut controller, &mut device, &write_request(MODE, &[1, 2]))
+            .await
+    );
+    assert!(
+        permits
+            .process(&mut controller, &mut device, &write_request(MODE, &[0xFF]))
+            .await
+    );
+    assert!(
+        !permits
+            .process(
+                &mut controller,
+                &mut device,
+                &mock::parse_event(&mock::disconnection_complete(
+                    CONN,
+                    Status::RemoteTerminationByUser
+                ))
+            )
+            .await
+    );
+    controller.verify();
+    assert_eq!(device.validated, [(vec![1, 2], false), (vec![0xFF], false)]);
+}
+
+#[test]
+fn rejects_writes_that_do_not_fit() {
+    let mut permits: WritePermits<1, 32> = WritePermits::new();
+    let mut device = Device::default();
+
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &write_request(AttributeHandle(0x0030), &[1])
+        ),
+        Err(AttError::WriteNotPermitted)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &write_request(MODE, &[1, 2, 3, 4, 5])
+        ),
+        Err(AttError::InvalidAttributeValueLength)
+    );
+    assert!(device.validated.is_empty());
+}
+
+#[test]
+fn reassembles_long_write() {
+    let mut permits: WritePermits<2, 32> = WritePermits::new();
+    let mut device = Device::default();
+    let value: Vec<u8> = (1..=20).collect();
+
+    for offset in [0, 8, 16] {
+        let end = (offset + 8).min(20);
+        let event = prepare_write_request(LOG, offset as u16, &value[offset..end]);
+        let response = permits.handle(&mut device, &event).unwrap();
+        assert_eq!(response.conn_handle, CONN);
+        assert_eq!(response.attribute_handle, LOG);
+        assert_eq!(response.status, Ok(()));
+        assert_eq!(response.value, &value[offset..end]);
+    }
+    assert_eq!(permits.prepared(CONN), Some((LOG, value.as_slice())));
+    assert_eq!(
+        device.validated,
+        [
+            (value[..8].to_vec(), true),
+            (value[..16].to_vec(), true),
+            (value.clone(), true)
+        ]
+    );
+
+    assert!(permits
+        .handle(&mut device, &attribute_modified(LOG, &value))
+        .is_none());
+    assert_eq!(device.executed, [(LOG, value)]);
+    assert_eq!(permits.prepared(CONN), None);
+}
+
+#[test]
+fn rejects_bad_long_write_parts() {
+    let mut permits: WritePermits<1, 16> = WritePermits::new();
+    let mut device = Device::default();
+
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 4, &[1])
+        ),
+        Err(AttError::InvalidOffset)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 0, &[1, 2, 3, 4])
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 6, &[7])
+        ),
+        Err(AttError::InvalidOffset)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 21, &[])
+        ),
+        Err(AttError::InvalidOffset)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 4, &[0; 17])
+        ),
+        Err(AttError::InvalidAttributeValueLength)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 4, &[0; 13])
+        ),
+        Err(AttError::PrepareQueueFull)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(MODE, 2, &[1])
+        ),
+        Err(AttError::PrepareQueueFull)
+    );
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 4, &[0xFF])
+        ),
+        Err(AttError::ApplicationError0x80)
+    );
+    assert_eq!(permits.prepared(CONN), Some((LOG, &[1, 2, 3, 4][..])));
+
+    permits.handle(
+        &mut device,
+        &mock::parse_event(&mock::disconnection_complete(
+            CONN,
+            Status::RemoteTerminationByUser,
+        )),
+    );
+    assert_eq!(permits.prepared(CONN), None);
+    assert!(device.executed.is_empty());
+}
+
+#[test]
+fn rejected_first_part_keeps_pending_write() {
+    let mut permits: WritePermits<1, 16> = WritePermits::new();
+    let mut device = Device::default();
+
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 0, &[0xFF])
+        ),
+        Err(AttError::ApplicationError0x80)
+    );
+    assert_eq!(permits.prepared(CONN), None);
+
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 0, &[1, 2, 3, 4])
+        ),
+        Ok(())
+    );
+    for event in [
+        prepare_write_request(LOG, 0, &[0xFF]),
+        prepare_write_request(MODE, 0, &[0; 5]),
+        prepare_write_request(AttributeHandle(0x0030), 0, &[1]),
+    ] {
+        assert!(status(&mut permits, &mut device, &event).is_err());
+        assert_eq!(permits.prepared(CONN), Some((LOG, &[1, 2, 3, 4][..])));
+    }
+}
+
+#[test]
+fn cancelled_long_write_is_not_executed() {
+    let mut permits: WritePermits<1, 16> = WritePermits::new();
+    let mut device = Device::default();
+
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &prepare_write_request(LOG, 0, &[1, 2, 3, 4])
+        ),
+        Ok(())
+    );
+    // Only the reassembled value is executed.
+    permits.handle(&mut device, &attribute_modified(LOG, &[5, 6, 7, 8]));
+    assert!(device.executed.is_empty());
+
+    // The client cancels the long write, and writes the attribute directly. Writes of other
+    // attributes keep the long write.
+    assert_eq!(
+        status(&mut permits, &mut device, &write_request(MODE, &[1])),
+        Ok(())
+    );
+    assert_eq!(permits.prepared(CONN), Some((LOG, &[1, 2, 3, 4][..])));
+    assert_eq!(
+        status(
+            &mut permits,
+            &mut device,
+            &write_request(LOG, &[1, 2, 3, 4])
+        ),
+        Ok(())
+    );
+    assert_eq!(permits.prepared(CONN), None);
+
+    permits.handle(&mut device, &attribute_modified(LOG, &[1, 2, 3, 4]));
+    assert!(device.executed.is_empty());
+}